@@ -219,6 +219,53 @@ impl AppCategory {
             AppCategory::Weather => "public.app-category.weather",
         }
     }
+
+    /// Map an AppCategory to the closest Debian archive section (as used in
+    /// the `Section` field of a deb control file).
+    pub fn debian_section(&self) -> &'static str {
+        match &self {
+            AppCategory::Business => "misc",
+            AppCategory::DeveloperTool => "devel",
+            AppCategory::Education => "education",
+            AppCategory::Entertainment => "misc",
+            AppCategory::Finance => "misc",
+            AppCategory::Game => "games",
+            AppCategory::ActionGame => "games",
+            AppCategory::AdventureGame => "games",
+            AppCategory::ArcadeGame => "games",
+            AppCategory::BoardGame => "games",
+            AppCategory::CardGame => "games",
+            AppCategory::CasinoGame => "games",
+            AppCategory::DiceGame => "games",
+            AppCategory::EducationalGame => "games",
+            AppCategory::FamilyGame => "games",
+            AppCategory::KidsGame => "games",
+            AppCategory::MusicGame => "games",
+            AppCategory::PuzzleGame => "games",
+            AppCategory::RacingGame => "games",
+            AppCategory::RolePlayingGame => "games",
+            AppCategory::SimulationGame => "games",
+            AppCategory::SportsGame => "games",
+            AppCategory::StrategyGame => "games",
+            AppCategory::TriviaGame => "games",
+            AppCategory::WordGame => "games",
+            AppCategory::GraphicsAndDesign => "graphics",
+            AppCategory::HealthcareAndFitness => "misc",
+            AppCategory::Lifestyle => "misc",
+            AppCategory::Medical => "science",
+            AppCategory::Music => "sound",
+            AppCategory::News => "news",
+            AppCategory::Photography => "graphics",
+            AppCategory::Productivity => "misc",
+            AppCategory::Reference => "misc",
+            AppCategory::SocialNetworking => "net",
+            AppCategory::Sports => "misc",
+            AppCategory::Travel => "misc",
+            AppCategory::Utility => "utils",
+            AppCategory::Video => "video",
+            AppCategory::Weather => "science",
+        }
+    }
 }
 
 impl<'d> serde::Deserialize<'d> for AppCategory {
@@ -1,4 +1,8 @@
+use super::settings::{FileAssociation, Settings};
 use anyhow::Context;
+use image::ExtendedColorType;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use std::collections::{BTreeMap, btree_map};
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
@@ -16,6 +20,271 @@ pub fn is_retina<P: AsRef<Path>>(path: P) -> bool {
         .unwrap_or(false)
 }
 
+/// The sizes of the images in generated Windows icons.
+const ICO_SIZES: [u32; 6] = [16, 24, 32, 48, 64, 256];
+
+/// The smallest size of a raster `icon_source` from which every icon size can
+/// be generated by downscaling.
+const MIN_ICON_SOURCE_SIZE: u32 = 1024;
+
+/// An image from which icons of any size can be generated: an SVG image, which
+/// is rendered at each size, or a high-resolution image, which is downscaled.
+pub enum IconSource {
+    Svg(Box<resvg::usvg::Tree>),
+    Image(image::DynamicImage),
+}
+
+impl IconSource {
+    /// Opens the SVG or raster image at `path`.
+    pub fn open(path: &Path) -> crate::Result<IconSource> {
+        if path.extension() == Some(OsStr::new("svg")) {
+            let data = fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+            let options = resvg::usvg::Options {
+                resources_dir: path.parent().map(Path::to_path_buf),
+                ..resvg::usvg::Options::default()
+            };
+            let tree = resvg::usvg::Tree::from_data(&data, &options)
+                .with_context(|| format!("Failed to parse SVG image {path:?}"))?;
+            Ok(IconSource::Svg(Box::new(tree)))
+        } else {
+            let image =
+                image::open(path).with_context(|| format!("Failed to read image {path:?}"))?;
+            Ok(IconSource::Image(image))
+        }
+    }
+
+    /// Returns the width and height of the image.
+    pub fn dimensions(&self) -> (f32, f32) {
+        match self {
+            IconSource::Svg(tree) => (tree.size().width(), tree.size().height()),
+            IconSource::Image(image) => (image.width() as f32, image.height() as f32),
+        }
+    }
+
+    /// Generates a `size`x`size` icon from the image.  Non-square images are
+    /// scaled to fit, and centered.  Text in SVG images is not rendered, so it
+    /// should be converted to paths.
+    pub fn render(&self, size: u32) -> crate::Result<image::DynamicImage> {
+        match self {
+            IconSource::Svg(tree) => render_svg(tree, size),
+            IconSource::Image(image) => {
+                let resized = image.resize(size, size, image::imageops::FilterType::Lanczos3);
+                if (resized.width(), resized.height()) == (size, size) {
+                    return Ok(resized);
+                }
+                let mut icon = image::RgbaImage::new(size, size);
+                image::imageops::overlay(
+                    &mut icon,
+                    &resized.to_rgba8(),
+                    i64::from((size - resized.width()) / 2),
+                    i64::from((size - resized.height()) / 2),
+                );
+                Ok(image::DynamicImage::ImageRgba8(icon))
+            }
+        }
+    }
+}
+
+fn render_svg(tree: &resvg::usvg::Tree, size: u32) -> crate::Result<image::DynamicImage> {
+    use resvg::tiny_skia;
+    let mut pixmap =
+        tiny_skia::Pixmap::new(size, size).with_context(|| format!("Invalid icon size {size}"))?;
+    let (width, height) = (tree.size().width(), tree.size().height());
+    let scale = size as f32 / width.max(height);
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+        (size as f32 - width * scale) / 2.0,
+        (size as f32 - height * scale) / 2.0,
+    );
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    let image = image::RgbaImage::from_raw(size, size, pixmap.take_demultiplied())
+        .context("Rendered SVG image has an unexpected size")?;
+    Ok(image::DynamicImage::ImageRgba8(image))
+}
+
+/// Opens the bundle's `icon_source`, if any, and warns if the image isn't
+/// square, or is too small to generate the largest icons without upscaling.
+pub fn open_icon_source(settings: &Settings) -> crate::Result<Option<IconSource>> {
+    let Some(path) = settings.icon_source() else {
+        return Ok(None);
+    };
+    let source = IconSource::open(path).context("Failed to open the icon source")?;
+    let (width, height) = source.dimensions();
+    if width != height {
+        print_warning(&format!(
+            "The icon source {path:?} is not square ({width}x{height}), so it will be \
+             centered in the generated icons"
+        ))?;
+    }
+    if let IconSource::Image(image) = &source
+        && image.width().min(image.height()) < MIN_ICON_SOURCE_SIZE
+    {
+        print_warning(&format!(
+            "The icon source {path:?} is smaller than {MIN_ICON_SOURCE_SIZE}x{MIN_ICON_SOURCE_SIZE}, \
+             so the larger icons generated from it will be blurry"
+        ))?;
+    }
+    Ok(Some(source))
+}
+
+/// Builds a Windows icon with images of several sizes.  The images are stored
+/// as 32-bit bitmaps, except for 256x256 images, which are PNG-compressed (as
+/// Windows expects) to keep the icon small.
+#[derive(Default)]
+pub struct IcoBuilder {
+    images: BTreeMap<u32, image::RgbaImage>,
+}
+
+impl IcoBuilder {
+    /// Adds a square image of up to 256x256 pixels, unless the icon already
+    /// has an image of that size.  Other images are ignored.
+    pub fn add_image(&mut self, image: &image::DynamicImage) {
+        let size = image.width();
+        if image.height() == size && (1..=256).contains(&size) {
+            self.images.entry(size).or_insert_with(|| image.to_rgba8());
+        }
+    }
+
+    /// Generates each of the usual icon sizes that the icon doesn't have yet
+    /// from `source`.
+    pub fn add_source(&mut self, source: &IconSource) -> crate::Result<()> {
+        for size in ICO_SIZES {
+            if let btree_map::Entry::Vacant(entry) = self.images.entry(size) {
+                entry.insert(source.render(size)?.into_rgba8());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if no images have been added to the icon.
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+
+    /// Writes the icon, with its images from smallest to largest.
+    pub fn write<W: Write>(&self, writer: W) -> crate::Result<()> {
+        let mut frames = Vec::new();
+        for (&size, image) in &self.images {
+            let frame = if size == 256 {
+                IcoFrame::as_png(image.as_raw(), size, size, ExtendedColorType::Rgba8)?
+            } else {
+                IcoFrame::with_encoded(ico_bitmap(image), size, size, ExtendedColorType::Rgba8)?
+            };
+            frames.push(frame);
+        }
+        IcoEncoder::new(writer).encode_images(&frames)?;
+        Ok(())
+    }
+}
+
+/// Encodes an image as the 32-bit bitmap of an icon image: a
+/// `BITMAPINFOHEADER` (whose height counts both the image and the mask),
+/// followed by the bottom-up BGRA rows of the image and of its 1-bit
+/// transparency mask.
+fn ico_bitmap(image: &image::RgbaImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let mask_row_len = width.div_ceil(32) * 4;
+    let mut data = Vec::new();
+    data.extend(40u32.to_le_bytes());
+    data.extend(width.to_le_bytes());
+    data.extend((height * 2).to_le_bytes());
+    data.extend(1u16.to_le_bytes()); // planes
+    data.extend(32u16.to_le_bytes()); // bits per pixel
+    data.extend(0u32.to_le_bytes()); // no compression
+    data.extend(((width * 4 + mask_row_len) * height).to_le_bytes());
+    data.extend([0; 16]); // resolution and palette size
+    for row in image.rows().rev() {
+        for pixel in row {
+            let [red, green, blue, alpha] = pixel.0;
+            data.extend([blue, green, red, alpha]);
+        }
+    }
+    for row in image.rows().rev() {
+        let mut mask = vec![0u8; mask_row_len as usize];
+        for (x, pixel) in row.enumerate() {
+            if pixel.0[3] == 0 {
+                mask[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        data.extend(mask);
+    }
+    data
+}
+
+/// Writes the app's Windows icon.  An ICO file among the `icon` files is used
+/// as it is.  Otherwise, the icon is built from the images of the `icon` files
+/// (including those in ICNS files), and the usual sizes that they don't
+/// provide are generated from SVG icons, then from the `icon_source`, and then
+/// by downscaling the largest image.  Returns false if there are no icons.
+pub fn write_app_ico<W: Write>(settings: &Settings, mut writer: W) -> crate::Result<bool> {
+    for icon_path in settings.icon_files() {
+        let icon_path = icon_path?;
+        if icon_path.extension() == Some(OsStr::new("ico")) {
+            io::copy(&mut File::open(icon_path)?, &mut writer)?;
+            return Ok(true);
+        }
+    }
+
+    let mut icon = IcoBuilder::default();
+    let mut svg_paths = Vec::new();
+    let mut largest_image: Option<image::DynamicImage> = None;
+    let mut add_image = |image: image::DynamicImage| {
+        icon.add_image(&image);
+        let size = |image: &image::DynamicImage| image.width().min(image.height());
+        if largest_image
+            .as_ref()
+            .is_none_or(|largest| size(largest) < size(&image))
+        {
+            largest_image = Some(image);
+        }
+    };
+    for icon_path in settings.icon_files() {
+        let icon_path = icon_path?;
+        if icon_path.extension() == Some(OsStr::new("svg")) {
+            svg_paths.push(icon_path);
+        } else if icon_path.extension() == Some(OsStr::new("icns")) {
+            let icon_family = icns::IconFamily::read(File::open(&icon_path)?)?;
+            for icon_type in icon_family.available_icons() {
+                let image = icon_family
+                    .get_icon_with_type(icon_type)?
+                    .convert_to(icns::PixelFormat::RGBA);
+                let image = image::RgbaImage::from_raw(
+                    image.width(),
+                    image.height(),
+                    image.into_data().into(),
+                )
+                .with_context(|| format!("Invalid image in {icon_path:?}"))?;
+                add_image(image::DynamicImage::ImageRgba8(image));
+            }
+        } else {
+            let image = image::open(&icon_path)
+                .with_context(|| format!("Failed to read icon {icon_path:?}"))?;
+            add_image(image);
+        }
+    }
+
+    for svg_path in svg_paths {
+        icon.add_source(&IconSource::open(&svg_path)?)?;
+    }
+    if let Some(source) = open_icon_source(settings)? {
+        icon.add_source(&source)?;
+    }
+    if let Some(image) = largest_image {
+        let largest_size = image.width().min(image.height());
+        let source = IconSource::Image(image);
+        for size in ICO_SIZES {
+            if size <= largest_size && !icon.images.contains_key(&size) {
+                icon.add_image(&source.render(size)?);
+            }
+        }
+    }
+
+    if icon.is_empty() {
+        return Ok(false);
+    }
+    icon.write(writer)?;
+    Ok(true)
+}
+
 /// Creates a new file at the given path, creating any parent directories as
 /// needed.
 pub fn create_file(path: &Path) -> crate::Result<BufWriter<File>> {
@@ -129,12 +398,74 @@ pub fn resource_relpath(path: &Path) -> PathBuf {
     dest
 }
 
+/// Returns the timestamp given by the `SOURCE_DATE_EPOCH` environment
+/// variable, if set.  For reproducible builds, this is used instead of the
+/// current time, and as an upper bound for file modification times; see
+/// https://reproducible-builds.org/specs/source-date-epoch/
+pub fn source_date_epoch() -> crate::Result<Option<u64>> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => match value.parse() {
+            Ok(timestamp) => Ok(Some(timestamp)),
+            Err(_) => anyhow::bail!("Invalid SOURCE_DATE_EPOCH {value:?}"),
+        },
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Returns the Windows programmatic identifier (ProgID) under which a file
+/// association is registered, e.g. `MyApp.foo`.  ProgIDs may only contain
+/// letters, digits and periods, and must not start with a digit.  The type is
+/// named after the association's first extension, or else its MIME subtype,
+/// or else its `index` in the `file_associations` list (e.g. `MyApp.Type2`),
+/// so that it never ends with a bare period.
+pub fn windows_prog_id(bundle_name: &str, index: usize, association: &FileAssociation) -> String {
+    let alphanumeric =
+        |name: &str| -> String { name.chars().filter(char::is_ascii_alphanumeric).collect() };
+    let mut app = alphanumeric(bundle_name);
+    if !app.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        app.insert(0, 'A');
+    }
+    let mut extension = alphanumeric(association.extensions().next().unwrap_or(""));
+    if extension.is_empty()
+        && let Some((_, subtype)) = association
+            .mime_type
+            .as_deref()
+            .and_then(|mime| mime.split_once('/'))
+    {
+        extension = alphanumeric(subtype);
+    }
+    if extension.is_empty() {
+        extension = format!("Type{}", index + 1);
+    }
+    format!("{app}.{extension}")
+}
+
 /// Prints a message to stderr, in the same format that `cargo` uses,
 /// indicating that we are creating a bundle with the given filename.
 pub fn print_bundling(filename: &str) -> crate::Result<()> {
     print_progress("Bundling", filename)
 }
 
+/// Prints a message to stderr, in the same format that `cargo` uses,
+/// indicating that we have started indexing the given repository.
+pub fn print_indexing(repository: &str) -> crate::Result<()> {
+    print_progress("Indexing", repository)
+}
+
+/// Prints a message to stderr, in the same format that `cargo` uses,
+/// indicating that we have started signing the given bundle.
+pub fn print_signing(filename: &str) -> crate::Result<()> {
+    print_progress("Signing", filename)
+}
+
+/// Prints a message to stderr, in the same format that `cargo` uses,
+/// indicating that the given file has a valid signature by `signer`.
+pub fn print_verified(filename: &str, signer: &str) -> crate::Result<()> {
+    print_progress("Verified", &format!("{filename} (signed by {signer})"))
+}
+
 /// Prints a message to stderr, in the same format that `cargo` uses,
 /// indicating that we have finished the the given bundles.
 pub fn print_finished(output_paths: &Vec<PathBuf>) -> crate::Result<()> {
@@ -204,6 +535,34 @@ pub fn print_warning(message: &str) -> crate::Result<()> {
     }
 }
 
+/// Prints a problem found by checking a package to stderr, labelled with its
+/// severity and tag (e.g. `warning[no-md5sums-control-file]:`).
+pub fn print_lint_finding(severity: &str, tag: &str, message: &str) -> crate::Result<()> {
+    let color = match severity {
+        "error" => term::color::RED,
+        "warning" => term::color::YELLOW,
+        _ => term::color::CYAN,
+    };
+    if let Some(mut output) = term::stderr() {
+        safe_term_attr(&mut output, term::Attr::Bold)?;
+        if output.supports_color() {
+            output.fg(color)?;
+        }
+        write!(output, "{severity}[{tag}]:")?;
+        if output.supports_reset() {
+            output.reset()?;
+        }
+        writeln!(output, " {message}")?;
+        output.flush()?;
+        Ok(())
+    } else {
+        let mut output = io::stderr();
+        writeln!(output, "{severity}[{tag}]: {message}")?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
 /// Prints an error to stderr, in the same format that `cargo` uses.
 pub fn print_error(error: &anyhow::Error) -> crate::Result<()> {
     if let Some(mut output) = term::stderr() {
@@ -243,11 +602,84 @@ pub fn print_error(error: &anyhow::Error) -> crate::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::{copy_dir, create_file, is_retina, read_file, resource_relpath, symlink_file};
+    use super::{
+        IcoBuilder, IconSource, copy_dir, create_file, is_retina, read_file, resource_relpath,
+        symlink_file, windows_prog_id,
+    };
+    use crate::bundle::{FileAssociation, FileAssociationRole};
 
     use std::io::Write;
     use std::path::{Path, PathBuf};
 
+    #[test]
+    fn render_icons_from_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        // A red 20x10 rectangle, which is centered vertically when rendered.
+        let svg_path = tmp.path().join("icon.svg");
+        std::fs::write(
+            &svg_path,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"20\" height=\"10\">\
+             <rect width=\"20\" height=\"10\" fill=\"#ff0000\"/></svg>",
+        )
+        .unwrap();
+        let png_path = tmp.path().join("icon.png");
+        image::RgbaImage::from_pixel(200, 100, image::Rgba([255, 0, 0, 255]))
+            .save(&png_path)
+            .unwrap();
+        for path in [&svg_path, &png_path] {
+            let source = IconSource::open(path).unwrap();
+            assert_eq!(source.dimensions().0, 2.0 * source.dimensions().1);
+            let image = source.render(64).unwrap().into_rgba8();
+            assert_eq!(image.dimensions(), (64, 64));
+            assert_eq!(image.get_pixel(32, 32).0, [255, 0, 0, 255]);
+            assert_eq!(image.get_pixel(32, 4).0[3], 0);
+            assert_eq!(image.get_pixel(32, 60).0[3], 0);
+        }
+
+        std::fs::write(&svg_path, "not an svg").unwrap();
+        assert!(IconSource::open(&svg_path).is_err());
+    }
+
+    #[test]
+    fn ico_builder() {
+        let image = |size, alpha| {
+            let pixel = image::Rgba([0, 0, 255, alpha]);
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(size, size, pixel))
+        };
+        let mut icon = IcoBuilder::default();
+        assert!(icon.is_empty());
+        icon.add_image(&image(48, 0));
+        icon.add_image(&image(48, 255)); // Ignored, as there is a 48x48 image.
+        icon.add_image(&image(300, 255)); // Ignored, as it is too large.
+        icon.add_image(&image(32, 255));
+        let mut ico = Vec::new();
+        icon.write(&mut ico).unwrap();
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 2, 0]);
+        // The 32x32 image is first, and stored as a bitmap, with a header
+        // counting the mask in its height.
+        assert_eq!((ico[6], ico[22]), (32, 48));
+        assert_eq!(&ico[38..50], &[40, 0, 0, 0, 32, 0, 0, 0, 64, 0, 0, 0]);
+        let decoded = image::load_from_memory_with_format(&ico, image::ImageFormat::Ico)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(decoded.dimensions(), (48, 48));
+        assert_eq!(decoded.get_pixel(0, 0).0[3], 0);
+
+        let source = IconSource::Image(image(1024, 255));
+        icon.add_source(&source).unwrap();
+        let mut ico = Vec::new();
+        icon.write(&mut ico).unwrap();
+        // 16, 24, 32, 48, 64 and 256 pixel images, the last one as a PNG.
+        assert_eq!(ico[4], 6);
+        let offset = u32::from_le_bytes(ico[6 + 5 * 16 + 12..6 + 5 * 16 + 16].try_into().unwrap());
+        assert_eq!(&ico[offset as usize..][..4], b"\x89PNG");
+        let decoded = image::load_from_memory_with_format(&ico, image::ImageFormat::Ico)
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(decoded.dimensions(), (256, 256));
+        assert_eq!(decoded.get_pixel(128, 128).0, [0, 0, 255, 255]);
+    }
+
     #[test]
     fn create_file_with_parent_dirs() {
         let tmp = tempfile::tempdir().unwrap();
@@ -350,4 +782,31 @@ mod tests {
         // Find dir instead of file
         assert!(read_file(&tmp.path().join(Path::new(FILE).parent().unwrap())).is_err());
     }
+
+    #[test]
+    fn prog_ids() {
+        let association = FileAssociation {
+            extensions: vec![".tar.gz".to_string()],
+            mime_type: None,
+            description: None,
+            icon: None,
+            role: FileAssociationRole::Viewer,
+        };
+        assert_eq!(windows_prog_id("My App", 0, &association), "MyApp.targz");
+        assert_eq!(windows_prog_id("7-Zip", 0, &association), "A7Zip.targz");
+
+        let association = FileAssociation {
+            extensions: vec!["+".to_string()],
+            mime_type: Some("application/x-my-app".to_string()),
+            ..association
+        };
+        assert_eq!(windows_prog_id("My App", 0, &association), "MyApp.xmyapp");
+        let association = FileAssociation {
+            extensions: Vec::new(),
+            mime_type: None,
+            ..association
+        };
+        assert_eq!(windows_prog_id("My App", 0, &association), "MyApp.Type1");
+        assert_eq!(windows_prog_id("My App", 1, &association), "MyApp.Type2");
+    }
 }
@@ -8,7 +8,7 @@
 // See https://developer.apple.com/go/?id=bundle-structure for a full
 // explanation.
 
-use super::common;
+use super::common::{self, IconSource};
 use crate::Settings;
 use anyhow::Context;
 use image::{self, GenericImageView};
@@ -51,6 +51,22 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     Ok(vec![bundle_dir])
 }
 
+/// The sizes (in points) and densities of the icons that are generated from
+/// SVG icons and the icon source: the iPhone and iPad app, Spotlight, Settings and notification icons,
+/// and the App Store icon.
+const IOS_ICON_SIZES: [(u32, bool); 10] = [
+    (20, false),
+    (20, true),
+    (29, false),
+    (29, true),
+    (40, false),
+    (40, true),
+    (60, true),
+    (76, false),
+    (76, true),
+    (1024, false),
+];
+
 /// Generate the icon files and store them under the `bundle_dir`.
 fn generate_icon_files(bundle_dir: &Path, settings: &Settings) -> crate::Result<Vec<String>> {
     let mut filenames = Vec::new();
@@ -103,7 +119,8 @@ fn generate_icon_files(bundle_dir: &Path, settings: &Settings) -> crate::Result<
                     }
                 }
             } else if icon_path.extension() == Some(OsStr::new("svg")) {
-                // TODO: convert svg to appropriate format?
+                let source = IconSource::open(&icon_path)?;
+                generate_icon_files_from_source(&source, &mut sizes, &mut get_dest_path)?;
             } else {
                 let icon = image::open(&icon_path)?;
                 let (width, height) = icon.dimensions();
@@ -116,10 +133,33 @@ fn generate_icon_files(bundle_dir: &Path, settings: &Settings) -> crate::Result<
                 }
             }
         }
+        // Generate any remaining sizes from the icon source.
+        if let Some(source) = common::open_icon_source(settings)? {
+            generate_icon_files_from_source(&source, &mut sizes, &mut get_dest_path)?;
+        }
     }
     Ok(filenames)
 }
 
+/// Generates the icons of `IOS_ICON_SIZES` that are missing from `sizes` from
+/// `source`, writing each to the path returned by `get_dest_path`.
+fn generate_icon_files_from_source(
+    source: &IconSource,
+    sizes: &mut BTreeSet<(u32, u32, bool)>,
+    get_dest_path: &mut impl FnMut(u32, u32, bool) -> PathBuf,
+) -> crate::Result<()> {
+    for (size, is_retina) in IOS_ICON_SIZES {
+        if sizes.insert((size, size, is_retina)) {
+            let dest_path = get_dest_path(size, size, is_retina);
+            let pixel_size = if is_retina { size * 2 } else { size };
+            let icon = source.render(pixel_size)?;
+            let mut file = common::create_file(&dest_path)?;
+            icon.write_to(&mut file, image::ImageFormat::Png)?;
+        }
+    }
+    Ok(())
+}
+
 fn generate_info_plist(
     bundle_dir: &Path,
     settings: &Settings,
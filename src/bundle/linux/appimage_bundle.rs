@@ -1,16 +1,30 @@
 use anyhow::Context;
+use sha2::{Digest, Sha256};
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
+    cmp::Reverse,
     fs::File,
     io::{BufReader, BufWriter, Write},
-    path::PathBuf,
-    process::Command,
+    path::{Path, PathBuf},
 };
 
-use crate::bundle::{Settings, common};
+use crate::bundle::{AppRun, Settings, common};
 
-use super::common::{generate_desktop_file, generate_icon_files};
+use super::appimage_signature::{sign_appimage, verify_appimage};
+use super::appstream::generate_metainfo_file;
+use super::common::{
+    create_file_with_data, generate_desktop_file, generate_icon_files, set_file_mode,
+    transfer_resource_files,
+};
+use super::elf::{section_range, set_runpath};
+use super::mime_info::generate_mime_info_file;
+use super::shared_libs::collect_libraries;
+use super::squashfs::create_squashfs;
+use super::zsync::write_zsync_file;
+
+/// The transports that AppImageUpdate understands in update information.
+const UPDATE_INFO_TRANSPORTS: &[&str] = &["zsync", "gh-releases-zsync", "pling-v1-zsync"];
 
 pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     let package_base_name = format!(
@@ -30,29 +44,41 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     }
     let package_path = base_dir.join(&package_name);
 
+    let has_systemd_files = settings.linux_systemd_units().next().is_some()
+        || settings.linux_sysusers().next().is_some()
+        || settings.linux_tmpfiles().next().is_some();
+    if has_systemd_files {
+        common::print_warning(
+            "AppImages cannot install system services; ignoring systemd units, sysusers and tmpfiles",
+        )?;
+    }
+
     let app_dir = package_dir.join("AppDir");
     let binary_dest_rel = PathBuf::from("usr/bin").join(settings.binary_name());
     let binary_dest_abs = app_dir.join(binary_dest_rel.clone());
     common::copy_file(settings.binary_path(), &binary_dest_abs)?;
+    bundle_shared_libraries(settings, &app_dir, &binary_dest_abs)?;
+    transfer_resource_files(settings, &app_dir).with_context(|| "Failed to copy resource files")?;
     generate_icon_files(settings, &app_dir)?;
     generate_desktop_file(settings, &app_dir)?;
+    generate_metainfo_file(settings, &app_dir)
+        .with_context(|| "Failed to create AppStream metainfo file")?;
+    generate_mime_info_file(settings, &app_dir)
+        .with_context(|| "Failed to create shared MIME info file")?;
 
-    // TODO Symlinks (AppRun, .DirIcon, .desktop)
-    common::symlink_file(&binary_dest_rel, &app_dir.join("AppRun"))?;
+    generate_app_run(settings, &app_dir, &binary_dest_rel)?;
+    link_desktop_file_and_icon(settings, &app_dir)?;
 
-    // Download the AppImage runtime
-    let runtime = fetch_runtime(settings.binary_arch())?;
+    // Get the AppImage runtime
+    let mut runtime = appimage_runtime(settings)?;
+    if let Some(update_info) = settings.appimage_update_info() {
+        embed_update_info(&mut runtime, update_info)?;
+    }
 
     // Make the squashfs
     let squashfs = base_dir.join(format!("{package_name}.squashfs"));
-    let _status = Command::new("mksquashfs")
-        .arg(&app_dir)
-        .arg(&squashfs)
-        .arg("-root-owned")
-        .arg("-noappend")
-        .arg("-quiet")
-        .status()
-        .with_context(|| "Failed to make sqaushfs, does the mksquashfs binary exist?")?;
+    create_squashfs(&app_dir, &squashfs, settings.appimage_compression())
+        .with_context(|| "Failed to create squashfs image")?;
 
     // Write the runtime and the fs to the .AppImage file
     let mut squashfs = BufReader::new(File::open(squashfs)?);
@@ -60,6 +86,8 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     let mut out = BufWriter::new(&mut f);
     out.write_all(&runtime)?;
     std::io::copy(&mut squashfs, &mut out)?;
+    out.flush()?;
+    drop(out);
 
     #[allow(unused_mut)]
     let mut perms = std::fs::metadata(&package_path)?.permissions();
@@ -67,18 +95,383 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     perms.set_mode(0o755);
     std::fs::set_permissions(&package_path, perms)?;
 
+    if let Some(secret_key) = settings.appimage_sign_key() {
+        common::print_signing(&package_name)?;
+        sign_appimage(&package_path, secret_key)?;
+        // Check the signature we just made, so that a broken one is never
+        // published.
+        verify_appimage(&package_path)
+            .with_context(|| "Failed to verify the AppImage signature")?;
+    }
+
+    // The zsync file describes the final AppImage, so it must be generated
+    // last.  It lives next to the AppImage, so it refers to it by name.
+    if settings.appimage_update_info().is_some() {
+        write_zsync_file(&package_path, &package_name)
+            .with_context(|| "Failed to create zsync file")?;
+    }
+
     Ok(vec![package_path])
 }
 
+/// Writes the update information into the runtime's `.upd_info` section,
+/// where AppImageUpdate looks for it.
+fn embed_update_info(runtime: &mut [u8], update_info: &str) -> crate::Result<()> {
+    let transport = update_info.split('|').next().unwrap_or_default();
+    if !UPDATE_INFO_TRANSPORTS.contains(&transport) {
+        common::print_warning(&format!(
+            "Unknown AppImage update information type {transport:?}"
+        ))?;
+    }
+    let range = section_range(runtime, ".upd_info")?
+        .ok_or_else(|| anyhow::anyhow!("The AppImage runtime has no .upd_info section"))?;
+    // The section must keep a terminating NUL.
+    if update_info.len() >= range.len() {
+        anyhow::bail!(
+            "appimage_update_info is too long: it must be shorter than {} bytes",
+            range.len()
+        );
+    }
+    let section = &mut runtime[range];
+    section.fill(0);
+    section[..update_info.len()].copy_from_slice(update_info.as_bytes());
+    Ok(())
+}
+
+/// Copies the shared libraries that the binary needs (other than those every
+/// system provides) into `usr/lib` in the AppDir, and points the binary's
+/// (and the libraries') runpath at them.
+fn bundle_shared_libraries(
+    settings: &Settings,
+    app_dir: &Path,
+    binary_dest: &Path,
+) -> crate::Result<()> {
+    let libraries = collect_libraries(settings.binary_path(), &settings.appimage_library_path())
+        .with_context(|| "Failed to collect the shared libraries to bundle")?;
+    if libraries.is_empty() {
+        return Ok(());
+    }
+    let lib_dir = app_dir.join("usr/lib");
+    for library in &libraries {
+        let dest = lib_dir.join(library.file_name().unwrap());
+        common::copy_file(library, &dest)?;
+        set_runpath(&dest, "$ORIGIN")
+            .with_context(|| format!("Failed to set the runpath of {dest:?}"))?;
+    }
+    set_runpath(binary_dest, "$ORIGIN/../lib")
+        .with_context(|| format!("Failed to set the runpath of {binary_dest:?}"))?;
+    Ok(())
+}
+
+/// Creates the `AppRun` entry point of the AppDir, which the AppImage runtime
+/// executes when the AppImage is run.
+fn generate_app_run(
+    settings: &Settings,
+    app_dir: &Path,
+    binary_dest_rel: &Path,
+) -> crate::Result<()> {
+    let app_run = app_dir.join("AppRun");
+    match settings.appimage_apprun() {
+        AppRun::Symlink => common::symlink_file(binary_dest_rel, &app_run)?,
+        AppRun::Script => {
+            create_file_with_data(&app_run, &app_run_script(settings.binary_name()))?;
+            set_file_mode(&app_run, 0o755)?;
+        }
+        AppRun::File(launcher) => {
+            common::copy_file(&launcher, &app_run)
+                .with_context(|| format!("Failed to copy AppRun launcher {launcher:?}"))?;
+            set_file_mode(&app_run, 0o755)?;
+        }
+    }
+    Ok(())
+}
+
+/// A shell script that runs `binary_name` from the AppDir containing it, with
+/// the AppDir's directories added to the search paths, and passes on its
+/// arguments.
+fn app_run_script(binary_name: &str) -> String {
+    format!(
+        r#"#!/bin/sh
+# The AppImage runtime sets APPDIR; fall back to this script's directory when
+# the AppDir is run directly.
+if [ -z "$APPDIR" ]; then
+    APPDIR="$(dirname "$(readlink -f "$0")")"
+fi
+export APPDIR
+export PATH="$APPDIR/usr/bin${{PATH:+:$PATH}}"
+export LD_LIBRARY_PATH="$APPDIR/usr/lib${{LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}}"
+export XDG_DATA_DIRS="$APPDIR/usr/share:${{XDG_DATA_DIRS:-/usr/local/share:/usr/share}}"
+exec "$APPDIR/usr/bin/{binary_name}" "$@"
+"#
+    )
+}
+
+/// Links the desktop file and the app's icon into the root of the AppDir, and
+/// the icon to `.DirIcon`, where appimagetool and desktop integration tools
+/// expect to find them.
+fn link_desktop_file_and_icon(settings: &Settings, app_dir: &Path) -> crate::Result<()> {
+    let binary_name = settings.binary_name();
+    let desktop_file = format!("{binary_name}.desktop");
+    common::symlink_file(
+        &Path::new("usr/share/applications").join(&desktop_file),
+        &app_dir.join(&desktop_file),
+    )?;
+    let Some(icon) = find_app_dir_icon(app_dir, binary_name) else {
+        common::print_warning(
+            "No icon is set for the AppImage; desktop integration tools will show a generic icon",
+        )?;
+        return Ok(());
+    };
+    // The icon at the root must be named after the desktop file's `Icon` key.
+    let extension = icon.extension().unwrap_or_default().to_string_lossy();
+    let root_icon = format!("{binary_name}.{extension}");
+    common::symlink_file(&icon, &app_dir.join(&root_icon))?;
+    common::symlink_file(Path::new(&root_icon), &app_dir.join(".DirIcon"))?;
+    Ok(())
+}
+
+/// Returns the path (relative to the AppDir) of the best of the app's icons
+/// in the hicolor theme: the largest PNG, or the SVG if there are no PNGs.
+/// Between PNGs of the same size in pixels, the unscaled one (e.g. `128x128`
+/// rather than `64x64@2x`) is preferred, and then the one whose directory
+/// name sorts first, so that the choice doesn't depend on the order in which
+/// the directory is read.
+fn find_app_dir_icon(app_dir: &Path, binary_name: &str) -> Option<PathBuf> {
+    let hicolor = Path::new("usr/share/icons/hicolor");
+    let icon_path = |size_dir: &str| {
+        hicolor
+            .join(size_dir)
+            .join(format!("apps/{binary_name}.png"))
+    };
+    // The best icon's size in pixels, scale and size directory.
+    let mut best: Option<(u32, Reverse<u32>, Reverse<String>)> = None;
+    for entry in std::fs::read_dir(app_dir.join(hicolor)).ok()?.flatten() {
+        let size_dir = entry.file_name().to_string_lossy().into_owned();
+        // Size directories are named like `256x256` or `128x128@2x`.
+        let (size, scale) = size_dir.split_once('@').unwrap_or((&size_dir, "1x"));
+        let (Some(width), Some(scale)) = (
+            size.split_once('x')
+                .and_then(|(width, _)| width.parse::<u32>().ok()),
+            scale
+                .strip_suffix('x')
+                .and_then(|scale| scale.parse::<u32>().ok()),
+        ) else {
+            continue;
+        };
+        let rank = (width * scale, Reverse(scale), Reverse(size_dir));
+        if app_dir.join(icon_path(&rank.2.0)).is_file() && best.as_ref() < Some(&rank) {
+            best = Some(rank);
+        }
+    }
+    let svg = hicolor.join(format!("scalable/apps/{binary_name}.svg"));
+    best.map(|(_, _, Reverse(size_dir))| icon_path(&size_dir))
+        .or_else(|| app_dir.join(&svg).is_file().then_some(svg))
+}
+
+/// Returns the AppImage runtime to use: the user-supplied one if configured,
+/// and otherwise a cached download (fetching it if it isn't cached yet).  If
+/// the runtime is pinned with `appimage_runtime_sha256`, its checksum is
+/// verified.
+fn appimage_runtime(settings: &Settings) -> crate::Result<Vec<u8>> {
+    let expected_sha256 = settings
+        .appimage_runtime_sha256()
+        .map(str::to_ascii_lowercase);
+    if let Some(path) = settings.appimage_runtime() {
+        let runtime = std::fs::read(&path)
+            .with_context(|| format!("Failed to read AppImage runtime {path:?}"))?;
+        if !runtime.starts_with(b"\x7fELF") {
+            anyhow::bail!("AppImage runtime {path:?} is not an ELF executable");
+        }
+        if let Some(expected) = &expected_sha256 {
+            verify_sha256(&runtime, expected, &path.display().to_string())?;
+        }
+        return Ok(runtime);
+    }
+
+    let arch = settings.binary_arch();
+    let cache_dir = dirs::cache_dir()
+        .map(|dir| dir.join("cargo-bundle/appimage-runtime"))
+        .ok_or_else(|| anyhow::anyhow!("Failed to find the user's cache directory"))?;
+    let cache_path = cache_dir.join(format!("runtime-{arch}"));
+    if let Some(runtime) = read_cached_runtime(&cache_path)? {
+        match &expected_sha256 {
+            Some(expected) if sha256_hex(&runtime) != *expected => {
+                common::print_warning(&format!(
+                    "Cached AppImage runtime {cache_path:?} doesn't match appimage_runtime_sha256"
+                ))?;
+            }
+            _ => return Ok(runtime),
+        }
+    }
+
+    if settings.offline() {
+        anyhow::bail!(
+            "No AppImage runtime for {arch} is cached in {cache_dir:?}, and the network may not be \
+             used in offline mode; pass --appimage-runtime or set appimage_runtime"
+        );
+    }
+    let runtime = fetch_runtime(arch)?;
+    if let Some(expected) = &expected_sha256 {
+        verify_sha256(&runtime, expected, "Downloaded AppImage runtime")?;
+    }
+    write_cached_runtime(&cache_path, &runtime)
+        .with_context(|| format!("Failed to cache AppImage runtime in {cache_dir:?}"))?;
+    Ok(runtime)
+}
+
+/// Reads the runtime cached at `path`, checking it against the checksum that
+/// was recorded next to it when it was downloaded.  Returns `None` if there is
+/// no valid cached runtime.
+fn read_cached_runtime(path: &Path) -> crate::Result<Option<Vec<u8>>> {
+    let checksum_path = path.with_extension("sha256");
+    let (Ok(runtime), Ok(checksum)) =
+        (std::fs::read(path), std::fs::read_to_string(&checksum_path))
+    else {
+        return Ok(None);
+    };
+    if sha256_hex(&runtime) != checksum.trim() {
+        common::print_warning(&format!(
+            "Cached AppImage runtime {path:?} is corrupt, downloading it again"
+        ))?;
+        return Ok(None);
+    }
+    Ok(Some(runtime))
+}
+
+fn write_cached_runtime(path: &Path, runtime: &[u8]) -> crate::Result<()> {
+    let dir = path.parent().unwrap();
+    std::fs::create_dir_all(dir)?;
+    // The checksum is written first, so that a concurrent build reading the
+    // cache in between finds a runtime that doesn't match it (and downloads
+    // it again) rather than an unchecked one.
+    write_cache_file(
+        dir,
+        &path.with_extension("sha256"),
+        sha256_hex(runtime).as_bytes(),
+    )?;
+    write_cache_file(dir, path, runtime)
+}
+
+/// Writes `data` to a uniquely named temporary file in `dir`, then renames
+/// it to `path`, so that concurrent builds never see a partially written
+/// file.
+fn write_cache_file(dir: &Path, path: &Path, data: &[u8]) -> crate::Result<()> {
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn verify_sha256(data: &[u8], expected: &str, what: &str) -> crate::Result<()> {
+    let actual = sha256_hex(data);
+    if actual != expected {
+        anyhow::bail!("{what} has SHA-256 checksum {actual}, but {expected} was expected");
+    }
+    Ok(())
+}
+
 fn fetch_runtime(arch: &str) -> crate::Result<Vec<u8>> {
     let url = format!(
         "https://github.com/AppImage/type2-runtime/releases/download/continuous/runtime-{arch}"
     );
 
     let response = reqwest::blocking::get(url)
+        .with_context(|| "Failed to get appimage runtime")?
+        .error_for_status()
         .with_context(|| "Failed to get appimage runtime")?
         .bytes()
         .with_context(|| "Failed to ready bytes")?;
 
     Ok(response.to_vec())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_runtime_is_verified() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("cache/runtime-x86_64");
+        assert!(read_cached_runtime(&path).unwrap().is_none());
+
+        write_cached_runtime(&path, b"\x7fELF runtime").unwrap();
+        // No temporary files are left behind.
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            2
+        );
+        assert_eq!(
+            read_cached_runtime(&path).unwrap().as_deref(),
+            Some(&b"\x7fELF runtime"[..])
+        );
+
+        std::fs::write(&path, b"\x7fELF truncated").unwrap();
+        assert!(read_cached_runtime(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn app_dir_icon_prefers_largest_png() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let app_dir = temp_dir.path();
+        assert_eq!(find_app_dir_icon(app_dir, "foo"), None);
+
+        let hicolor = app_dir.join("usr/share/icons/hicolor");
+        let touch = |path: &str| {
+            let path = hicolor.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        };
+        touch("scalable/apps/foo.svg");
+        assert_eq!(
+            find_app_dir_icon(app_dir, "foo").unwrap(),
+            Path::new("usr/share/icons/hicolor/scalable/apps/foo.svg")
+        );
+        touch("32x32/apps/foo.png");
+        touch("256x256/apps/bar.png");
+        // Of icons with the same number of pixels, the unscaled one wins,
+        // whichever order they are created (and so likely listed) in.
+        touch("64x64@2x/apps/foo.png");
+        touch("128x128/apps/foo.png");
+        touch("32x32@4x/apps/foo.png");
+        assert_eq!(
+            find_app_dir_icon(app_dir, "foo").unwrap(),
+            Path::new("usr/share/icons/hicolor/128x128/apps/foo.png")
+        );
+        // Otherwise, the directory name that sorts first wins.
+        touch("512x512@1x/apps/foo.png");
+        touch("512x512/apps/foo.png");
+        touch("256x256@2x/apps/foo.png");
+        assert_eq!(
+            find_app_dir_icon(app_dir, "foo").unwrap(),
+            Path::new("usr/share/icons/hicolor/512x512/apps/foo.png")
+        );
+    }
+
+    #[test]
+    fn app_run_script_execs_binary() {
+        let script = app_run_script("foo");
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.ends_with("exec \"$APPDIR/usr/bin/foo\" \"$@\"\n"));
+        assert!(script.contains("export XDG_DATA_DIRS=\"$APPDIR/usr/share:${XDG_DATA_DIRS:-"));
+    }
+
+    #[test]
+    fn update_info_needs_runtime_section() {
+        let mut not_a_runtime = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let error = embed_update_info(&mut not_a_runtime, "zsync|https://example.com/App.zsync")
+            .unwrap_err();
+        assert!(error.to_string().contains(".upd_info"));
+    }
+
+    #[test]
+    fn sha256_verification() {
+        let expected = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify_sha256(b"hello", expected, "test").is_ok());
+        assert!(verify_sha256(b"hello!", expected, "test").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+// Embedded AppImage signatures, as described in the "Signing" section of the
+// AppImage specification (https://github.com/AppImage/AppImageSpec/blob/master/draft.md).
+// The type 2 runtime reserves two sections for them:
+//
+// .sha256_sig    An ASCII-armored, detached OpenPGP signature of the image's
+//                SHA-256 digest (as a lowercase hex string)
+// .sig_key       The signer's ASCII-armored OpenPGP public key
+//
+// The digest is computed over the whole image with both sections zeroed, so
+// the signature can be embedded (and checked) in place.  The OpenPGP work is
+// done by gpg, in a temporary home directory (see gpg.rs).
+
+use super::elf::section_range;
+use super::gpg::GpgHome;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::path::Path;
+
+/// The environment variable holding the passphrase of the signing key, if it
+/// has one.
+const PASSPHRASE_VAR: &str = "APPIMAGE_SIGN_PASSPHRASE";
+
+/// Signs the AppImage at `path` with the OpenPGP secret key in the file
+/// `secret_key`, embedding the signature and the public key in the image.
+pub fn sign_appimage(path: &Path, secret_key: &Path) -> crate::Result<()> {
+    let mut image = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let sections = SignatureSections::find(&image)?;
+    let gpg_home = GpgHome::new()?;
+    gpg_home
+        .import(secret_key)
+        .with_context(|| format!("Failed to import the signing key {secret_key:?}"))?;
+
+    let digest_path = gpg_home.dir.join("digest");
+    let signature_path = gpg_home.dir.join("digest.asc");
+    std::fs::write(&digest_path, sections.digest(&image))?;
+    gpg_home
+        .sign(
+            [
+                OsStr::new("--armor"),
+                OsStr::new("--output"),
+                signature_path.as_os_str(),
+                OsStr::new("--detach-sign"),
+                digest_path.as_os_str(),
+            ],
+            PASSPHRASE_VAR,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to sign the AppImage (if the key has a passphrase, set it in {PASSPHRASE_VAR})"
+            )
+        })?;
+    let signature = std::fs::read(&signature_path)?;
+    let public_key = gpg_home
+        .gpg(["--armor", "--export"], None)
+        .with_context(|| "Failed to export the public key")?;
+
+    fill_section(&mut image, sections.signature, &signature, ".sha256_sig")?;
+    fill_section(&mut image, sections.key, &public_key, ".sig_key")?;
+    std::fs::write(path, &image).with_context(|| format!("Failed to write {path:?}"))?;
+    Ok(())
+}
+
+/// Checks the signature embedded in the AppImage at `path` against the
+/// public key embedded with it, returning the fingerprint of the key that
+/// made the signature.
+pub fn verify_appimage(path: &Path) -> crate::Result<String> {
+    let image = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let sections = SignatureSections::find(&image)?;
+    let signature = section_contents(&image, &sections.signature);
+    let public_key = section_contents(&image, &sections.key);
+    if signature.is_empty() || public_key.is_empty() {
+        anyhow::bail!("{path:?} is not signed");
+    }
+
+    let gpg_home = GpgHome::new()?;
+    let key_path = gpg_home.dir.join("key.asc");
+    let digest_path = gpg_home.dir.join("digest");
+    let signature_path = gpg_home.dir.join("digest.asc");
+    std::fs::write(&key_path, public_key)?;
+    std::fs::write(&digest_path, sections.digest(&image))?;
+    std::fs::write(&signature_path, signature)?;
+    gpg_home
+        .import(&key_path)
+        .with_context(|| "Failed to import the AppImage's public key")?;
+    let status = gpg_home
+        .gpg(
+            [
+                OsStr::new("--status-fd"),
+                OsStr::new("1"),
+                OsStr::new("--verify"),
+                signature_path.as_os_str(),
+                digest_path.as_os_str(),
+            ],
+            None,
+        )
+        .with_context(|| format!("The signature of {path:?} is not valid"))?;
+    // See doc/DETAILS in the GnuPG sources for the status lines.
+    String::from_utf8_lossy(&status)
+        .lines()
+        .find_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .and_then(|args| args.split_whitespace().next())
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("The signature of {path:?} is not valid"))
+}
+
+/// The file ranges of the signature sections of an AppImage's runtime.
+struct SignatureSections {
+    signature: Range<usize>,
+    key: Range<usize>,
+}
+
+impl SignatureSections {
+    fn find(image: &[u8]) -> crate::Result<SignatureSections> {
+        let find = |name| {
+            section_range(image, name)?.ok_or_else(|| {
+                anyhow::anyhow!("The AppImage runtime has no {name} section for signatures")
+            })
+        };
+        Ok(SignatureSections {
+            signature: find(".sha256_sig")?,
+            key: find(".sig_key")?,
+        })
+    }
+
+    /// Returns the SHA-256 digest (in hex) of `image`, with the signature
+    /// sections treated as zeros.
+    fn digest(&self, image: &[u8]) -> String {
+        let mut ranges = [&self.signature, &self.key];
+        ranges.sort_by_key(|range| range.start);
+        let mut hasher = Sha256::new();
+        let mut offset = 0;
+        for range in ranges {
+            hasher.update(&image[offset..range.start]);
+            hasher.update(vec![0; range.len()]);
+            offset = range.end;
+        }
+        hasher.update(&image[offset..]);
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Returns the contents of a section, up to the first NUL.
+fn section_contents<'a>(image: &'a [u8], range: &Range<usize>) -> &'a [u8] {
+    let section = &image[range.clone()];
+    let end = section
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(section.len());
+    &section[..end]
+}
+
+fn fill_section(
+    image: &mut [u8],
+    range: Range<usize>,
+    contents: &[u8],
+    name: &str,
+) -> crate::Result<()> {
+    // Keep a terminating NUL.
+    if contents.len() >= range.len() {
+        anyhow::bail!(
+            "The {name} section of the AppImage runtime is too small ({} bytes) for {} bytes",
+            range.len(),
+            contents.len()
+        );
+    }
+    let section = &mut image[range];
+    section.fill(0);
+    section[..contents.len()].copy_from_slice(contents);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_zeroes_signature_sections() {
+        let sections = SignatureSections {
+            signature: 4..6,
+            key: 1..3,
+        };
+        let image = b"abcdefgh";
+        let zeroed = b"a\0\0d\0\0gh";
+        assert_eq!(
+            sections.digest(image),
+            format!("{:x}", Sha256::digest(zeroed))
+        );
+    }
+
+    #[test]
+    fn section_contents_stop_at_nul() {
+        let mut image = vec![b'x'; 16];
+        fill_section(&mut image, 2..10, b"sig", ".sha256_sig").unwrap();
+        assert_eq!(section_contents(&image, &(2..10)), b"sig");
+        assert_eq!(&image[10..], b"xxxxxx");
+        assert!(fill_section(&mut image, 2..10, b"too long", ".sha256_sig").is_err());
+    }
+}
@@ -0,0 +1,650 @@
+// Generates the AppStream metainfo file that software centers (GNOME
+// Software, KDE Discover, Flathub) use to list an app.  The file is installed
+// as `usr/share/metainfo/<id>.metainfo.xml`; its format is described at
+// https://www.freedesktop.org/software/appstream/docs/chap-Metadata.html.
+//
+// The generated XML is read back and checked against the structural rules of
+// the specification (required elements, the component ID's format, release
+// dates, OARS content rating values, etc.), so that mistakes in the settings
+// are caught when bundling rather than when the package is published.
+
+use super::common::create_file_with_data;
+use crate::bundle::{Settings, common};
+use quick_xml::events::Event;
+use quick_xml::se::Serializer;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The license of the metainfo file itself, which must be a permissive one.
+const METADATA_LICENSE: &str = "CC0-1.0";
+
+/// The content rating attributes of OARS 1.1 (https://hughsie.github.io/oars/).
+const OARS_ATTRIBUTES: &[&str] = &[
+    "violence-cartoon",
+    "violence-fantasy",
+    "violence-realistic",
+    "violence-bloodshed",
+    "violence-sexual",
+    "violence-desecration",
+    "violence-slavery",
+    "violence-worship",
+    "drugs-alcohol",
+    "drugs-narcotics",
+    "drugs-tobacco",
+    "sex-nudity",
+    "sex-themes",
+    "sex-homosexuality",
+    "sex-prostitution",
+    "sex-adultery",
+    "sex-appearance",
+    "language-profanity",
+    "language-humor",
+    "language-discrimination",
+    "social-chat",
+    "social-info",
+    "social-audio",
+    "social-location",
+    "social-contacts",
+    "money-purchasing",
+    "money-gambling",
+];
+
+const OARS_VALUES: &[&str] = &["none", "mild", "moderate", "intense"];
+
+/// Generates the AppStream metainfo file and stores it under the `data_dir`,
+/// returning its path (or `None` if the bundle lacks the metadata that every
+/// metainfo file needs).
+pub fn generate_metainfo_file(
+    settings: &Settings,
+    data_dir: &Path,
+) -> crate::Result<Option<PathBuf>> {
+    let id = settings.bundle_identifier();
+    if let Some(reason) = missing_metadata(&id, settings.short_description()) {
+        common::print_warning(&format!(
+            "{reason}, so no AppStream metainfo file will be generated"
+        ))?;
+        return Ok(None);
+    }
+    let xml = metainfo_xml(settings)?;
+    for warning in validate_metainfo(&xml)? {
+        common::print_warning(&format!("AppStream metainfo: {warning}"))?;
+    }
+    let path = data_dir
+        .join("usr/share/metainfo")
+        .join(format!("{id}.metainfo.xml"));
+    create_file_with_data(&path, &xml)?;
+    Ok(Some(path))
+}
+
+/// Checks for the metadata that a metainfo file can't do without, but that
+/// other kinds of bundles don't need: a component ID (the bundle identifier)
+/// in reverse-DNS form, and a summary, which is also the description if there
+/// is no long description.  Returns why the metainfo file can't be generated
+/// if any of it is missing.
+fn missing_metadata(id: &str, summary: &str) -> Option<String> {
+    if id.is_empty() {
+        Some("No bundle identifier is set".to_string())
+    } else if let Err(error) = check_component_id(id) {
+        Some(format!("The bundle identifier {id:?} {error}"))
+    } else if summary.trim().is_empty() {
+        Some("No short_description (or package description) is set".to_string())
+    } else {
+        None
+    }
+}
+
+/// Builds the metainfo XML from the bundle settings.
+fn metainfo_xml(settings: &Settings) -> crate::Result<String> {
+    let id = settings.bundle_identifier();
+    let summary = settings.short_description();
+    let description = settings
+        .long_description()
+        .filter(|description| !description.trim().is_empty())
+        .unwrap_or(summary);
+    let authors: Vec<&str> = settings
+        .author_names()
+        .iter()
+        .map(|author| author.split('<').next().unwrap().trim())
+        .filter(|name| !name.is_empty())
+        .collect();
+    let categories: Vec<&str> = settings
+        .app_category()
+        .map(|category| category.gnome_desktop_categories())
+        .unwrap_or("")
+        .split(';')
+        .filter(|category| !category.is_empty())
+        .collect();
+    let screenshots: Vec<ScreenshotElement> = settings
+        .linux_screenshots()
+        .iter()
+        .enumerate()
+        .map(|(index, screenshot)| ScreenshotElement {
+            kind: (index == 0).then_some("default"),
+            caption: screenshot.caption.as_deref(),
+            image: &screenshot.url,
+        })
+        .collect();
+    let releases: Vec<ReleaseElement> = settings
+        .linux_releases()
+        .iter()
+        .map(|release| ReleaseElement {
+            version: &release.version,
+            date: &release.date,
+            description: release.description.as_deref().map(Description::new),
+        })
+        .collect();
+    let content_attributes = settings
+        .linux_content_rating()
+        .into_iter()
+        .flatten()
+        .map(|(id, value)| ContentAttribute { id, value })
+        .collect();
+
+    let component = Component {
+        kind: "desktop-application",
+        id: &id,
+        metadata_license: METADATA_LICENSE,
+        project_license: settings.license(),
+        name: settings.bundle_name(),
+        summary,
+        description: Description::new(description),
+        launchable: Launchable {
+            kind: "desktop-id",
+            desktop_id: format!("{}.desktop", settings.binary_name()),
+        },
+        developer_name: (!authors.is_empty()).then(|| authors.join(", ")),
+        url: (!settings.homepage_url().is_empty()).then(|| Url {
+            kind: "homepage",
+            url: settings.homepage_url(),
+        }),
+        categories: (!categories.is_empty()).then_some(Categories {
+            category: categories,
+        }),
+        screenshots: (!screenshots.is_empty()).then_some(Screenshots {
+            screenshot: screenshots,
+        }),
+        content_rating: ContentRating {
+            kind: "oars-1.1",
+            content_attribute: content_attributes,
+        },
+        releases: (!releases.is_empty()).then_some(Releases { release: releases }),
+    };
+
+    let mut buffer = String::new();
+    let mut serializer = Serializer::new(&mut buffer);
+    serializer.indent(' ', 2);
+    component.serialize(serializer)?;
+    Ok(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{buffer}\n"
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(rename = "component")]
+struct Component<'a> {
+    #[serde(rename = "@type")]
+    kind: &'a str,
+    id: &'a str,
+    metadata_license: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project_license: Option<&'a str>,
+    name: &'a str,
+    summary: &'a str,
+    description: Description,
+    launchable: Launchable,
+    // AppStream 1.0 replaces this with <developer><name>, which older
+    // software centers don't understand.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    developer_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<Url<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    categories: Option<Categories<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    screenshots: Option<Screenshots<'a>>,
+    content_rating: ContentRating<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    releases: Option<Releases<'a>>,
+}
+
+/// A `<description>`: paragraphs and bulleted lists.
+#[derive(Serialize)]
+struct Description {
+    #[serde(rename = "$value")]
+    blocks: Vec<Block>,
+}
+
+#[derive(Serialize)]
+enum Block {
+    #[serde(rename = "p")]
+    Paragraph(String),
+    #[serde(rename = "ul")]
+    List { li: Vec<String> },
+}
+
+impl Description {
+    /// Converts plain text to a description.  Paragraphs are separated by
+    /// blank lines, and a paragraph whose lines start with `-` or `*` is a
+    /// bulleted list (lines that don't start with one continue the previous
+    /// item).
+    fn new(text: &str) -> Description {
+        let mut blocks = Vec::new();
+        let mut lines = Vec::new();
+        for line in text.lines().map(str::trim).chain([""]) {
+            if !line.is_empty() {
+                lines.push(line);
+                continue;
+            }
+            if lines.is_empty() {
+                continue;
+            }
+            if list_item(lines[0]).is_some() {
+                let mut items: Vec<String> = Vec::new();
+                for line in lines.drain(..) {
+                    match (list_item(line), items.last_mut()) {
+                        (None, Some(item)) => {
+                            item.push(' ');
+                            item.push_str(line);
+                        }
+                        (item, _) => items.push(item.unwrap_or(line).to_string()),
+                    }
+                }
+                blocks.push(Block::List { li: items });
+            } else {
+                blocks.push(Block::Paragraph(lines.join(" ")));
+                lines.clear();
+            }
+        }
+        Description { blocks }
+    }
+}
+
+/// Returns the text of a bulleted list item, if `line` is one.
+fn list_item(line: &str) -> Option<&str> {
+    line.strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .map(str::trim_start)
+}
+
+#[derive(Serialize)]
+struct Launchable {
+    #[serde(rename = "@type")]
+    kind: &'static str,
+    #[serde(rename = "$text")]
+    desktop_id: String,
+}
+
+#[derive(Serialize)]
+struct Url<'a> {
+    #[serde(rename = "@type")]
+    kind: &'a str,
+    #[serde(rename = "$text")]
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct Categories<'a> {
+    category: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Screenshots<'a> {
+    screenshot: Vec<ScreenshotElement<'a>>,
+}
+
+#[derive(Serialize)]
+struct ScreenshotElement<'a> {
+    #[serde(rename = "@type", skip_serializing_if = "Option::is_none")]
+    kind: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caption: Option<&'a str>,
+    image: &'a str,
+}
+
+#[derive(Serialize)]
+struct ContentRating<'a> {
+    #[serde(rename = "@type")]
+    kind: &'a str,
+    content_attribute: Vec<ContentAttribute<'a>>,
+}
+
+#[derive(Serialize)]
+struct ContentAttribute<'a> {
+    #[serde(rename = "@id")]
+    id: &'a str,
+    #[serde(rename = "$text")]
+    value: &'a str,
+}
+
+#[derive(Serialize)]
+struct Releases<'a> {
+    release: Vec<ReleaseElement<'a>>,
+}
+
+#[derive(Serialize)]
+struct ReleaseElement<'a> {
+    #[serde(rename = "@version")]
+    version: &'a str,
+    #[serde(rename = "@date")]
+    date: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<Description>,
+}
+
+/// An element of a parsed XML document.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Parses an XML document into its root element.
+fn parse_xml(xml: &str) -> crate::Result<Element> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    // The elements being read, the innermost last, with their raw text.
+    let mut stack: Vec<(Element, String)> = vec![(Element::default(), String::new())];
+    loop {
+        let (start, empty) = match reader.read_event()? {
+            Event::Start(start) => (Some(start), false),
+            Event::Empty(start) => (Some(start), true),
+            Event::End(_) => (None, true),
+            Event::Text(text) => {
+                stack.last_mut().unwrap().1.push_str(&text.xml_content()?);
+                continue;
+            }
+            Event::GeneralRef(reference) => {
+                let text = &mut stack.last_mut().unwrap().1;
+                text.push('&');
+                text.push_str(&reference.decode()?);
+                text.push(';');
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        if let Some(start) = start {
+            let mut element = Element {
+                name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+                ..Element::default()
+            };
+            for attribute in start.attributes() {
+                let attribute = attribute?;
+                element.attributes.push((
+                    String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                    attribute.unescape_value()?.into_owned(),
+                ));
+            }
+            stack.push((element, String::new()));
+        }
+        if empty {
+            // Text is only trimmed once the whole element (including
+            // references like `&amp;`, which are separate events) is read.
+            let (mut element, text) = stack.pop().unwrap();
+            element.text = quick_xml::escape::unescape(&text)?.trim().to_string();
+            stack.last_mut().unwrap().0.children.push(element);
+        }
+    }
+    let (mut document, _) = stack.pop().unwrap();
+    match document.children.len() {
+        1 => Ok(document.children.remove(0)),
+        _ => anyhow::bail!("The document must have exactly one root element"),
+    }
+}
+
+/// Checks the structure of a metainfo document, returning warnings about
+/// things that software centers will accept but handle poorly, or an error
+/// listing the problems that make the document invalid.
+fn validate_metainfo(xml: &str) -> crate::Result<Vec<String>> {
+    let component = parse_xml(xml)?;
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    if component.name != "component" {
+        errors.push(format!(
+            "the root element is <{}>, not <component>",
+            component.name
+        ));
+    }
+    if component.attribute("type") != Some("desktop-application") {
+        errors.push("the component's type is not \"desktop-application\"".to_string());
+    }
+    for name in [
+        "id",
+        "name",
+        "summary",
+        "metadata_license",
+        "description",
+        "launchable",
+    ] {
+        match component.child(name) {
+            None => errors.push(format!("<{name}> is missing")),
+            Some(element) if element.text.is_empty() && element.children.is_empty() => {
+                errors.push(format!("<{name}> is empty"))
+            }
+            Some(_) => {}
+        }
+    }
+    if let Some(id) = component.child("id")
+        && let Err(error) = check_component_id(&id.text)
+    {
+        errors.push(format!("the component ID {:?} {error}", id.text));
+    }
+    if let Some(summary) = component.child("summary")
+        && summary.text.ends_with('.')
+    {
+        warnings.push("the summary should not end with a full stop".to_string());
+    }
+    if component.child("project_license").is_none() {
+        warnings.push("no project license is set (the package's `license`)".to_string());
+    }
+    for screenshot in component
+        .children("screenshots")
+        .flat_map(|screenshots| screenshots.children("screenshot"))
+    {
+        let url = screenshot.child("image").map_or("", |image| &image.text);
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            errors.push(format!("the screenshot URL {url:?} is not an HTTP(S) URL"));
+        }
+    }
+    match component.child("content_rating") {
+        Some(rating) if rating.attribute("type") == Some("oars-1.1") => {
+            for attribute in rating.children("content_attribute") {
+                let id = attribute.attribute("id").unwrap_or("");
+                if !OARS_ATTRIBUTES.contains(&id) {
+                    errors.push(format!("{id:?} is not an OARS 1.1 content attribute"));
+                } else if !OARS_VALUES.contains(&attribute.text.as_str()) {
+                    errors.push(format!(
+                        "the content rating of {id:?} is {:?}, not one of {}",
+                        attribute.text,
+                        OARS_VALUES.join(", ")
+                    ));
+                }
+            }
+        }
+        _ => errors.push("there is no OARS 1.1 <content_rating>".to_string()),
+    }
+    let mut last_date = None;
+    for release in component
+        .children("releases")
+        .flat_map(|releases| releases.children("release"))
+    {
+        let version = release.attribute("version").unwrap_or("");
+        if version.is_empty() {
+            errors.push("a release has no version".to_string());
+        }
+        let date = release.attribute("date").unwrap_or("");
+        match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => {
+                if last_date.is_some_and(|last_date| date > last_date) {
+                    warnings.push(format!(
+                        "release {version} is listed after an older release; \
+                         releases should be listed newest first"
+                    ));
+                }
+                last_date = Some(date);
+            }
+            Err(_) => errors.push(format!(
+                "the date {date:?} of release {version} is not in YYYY-MM-DD format"
+            )),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("Invalid AppStream metainfo: {}", errors.join("; "));
+    }
+    Ok(warnings)
+}
+
+/// Checks that a component ID is a reverse-DNS name, like `org.example.App`.
+fn check_component_id(id: &str) -> Result<(), &'static str> {
+    let segments: Vec<&str> = id.split('.').collect();
+    if segments.len() < 2 {
+        return Err("is not a reverse-DNS name (e.g. \"org.example.App\")");
+    }
+    for segment in segments {
+        if segment.is_empty() {
+            return Err("has an empty segment");
+        }
+        if !segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err("may only contain ASCII letters, digits, '_', '-' and '.'");
+        }
+        if segment.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err("has a segment starting with a digit");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn description_paragraphs_and_lists() {
+        let description = Description::new(
+            "First paragraph,\n  continued.\n\n\n- One\n- Two,\n  continued\n* Three\n\nLast.",
+        );
+        let mut serialized = String::new();
+        description
+            .serialize(Serializer::new(&mut serialized))
+            .unwrap();
+        assert_eq!(
+            serialized,
+            "<Description><p>First paragraph, continued.</p>\
+             <ul><li>One</li><li>Two, continued</li><li>Three</li></ul>\
+             <p>Last.</p></Description>"
+        );
+    }
+
+    #[test]
+    fn metainfo_needs_id_and_summary() {
+        assert_eq!(missing_metadata("org.example.App", "Does things"), None);
+        assert!(
+            missing_metadata("", "Does things")
+                .unwrap()
+                .contains("No bundle identifier")
+        );
+        assert!(
+            missing_metadata("com.example.2048", "Plays 2048")
+                .unwrap()
+                .contains("\"com.example.2048\" has a segment starting with a digit")
+        );
+        // Without a summary, there is no description either.
+        assert!(
+            missing_metadata("org.example.App", " \n")
+                .unwrap()
+                .contains("No short_description")
+        );
+    }
+
+    #[test]
+    fn component_ids() {
+        assert!(check_component_id("org.example.App").is_ok());
+        assert!(check_component_id("hello.example.cargo-bundle").is_ok());
+        assert!(check_component_id("app").is_err());
+        assert!(check_component_id("org..app").is_err());
+        assert!(check_component_id("org.example.my app").is_err());
+        assert!(check_component_id("org.7zip.App").is_err());
+    }
+
+    const VALID: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>org.example.App</id>
+  <metadata_license>CC0-1.0</metadata_license>
+  <project_license>MIT</project_license>
+  <name>App</name>
+  <summary>Does things &amp; stuff</summary>
+  <description><p>Text</p></description>
+  <launchable type="desktop-id">app.desktop</launchable>
+  <content_rating type="oars-1.1">
+    <content_attribute id="violence-cartoon">mild</content_attribute>
+  </content_rating>
+  <releases>
+    <release version="1.1" date="2024-02-01"/>
+    <release version="1.0" date="2024-01-01"/>
+  </releases>
+</component>
+"#;
+
+    #[test]
+    fn parse_document() {
+        let component = parse_xml(VALID).unwrap();
+        assert_eq!(component.name, "component");
+        assert_eq!(component.attribute("type"), Some("desktop-application"));
+        assert_eq!(
+            component.child("summary").unwrap().text,
+            "Does things & stuff"
+        );
+        assert_eq!(
+            component
+                .child("releases")
+                .unwrap()
+                .children("release")
+                .count(),
+            2
+        );
+        assert!(parse_xml("<a><b></a>").is_err());
+    }
+
+    #[test]
+    fn validate_document() {
+        assert_eq!(validate_metainfo(VALID).unwrap(), Vec::<String>::new());
+
+        let unsorted = VALID.replace("2024-02-01", "2023-12-01");
+        let warnings = validate_metainfo(&unsorted).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("newest first"));
+
+        let error = validate_metainfo(
+            &VALID
+                .replace("<id>org.example.App</id>", "")
+                .replace(">mild<", ">lots<")
+                .replace("2024-01-01", "1st January"),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("<id> is missing"), "{error}");
+        assert!(error.contains("\"lots\""), "{error}");
+        assert!(error.contains("\"1st January\""), "{error}");
+    }
+}
@@ -0,0 +1,372 @@
+// A flat-file APT repository, as served by any static web server, looks like:
+//
+// repo/
+//     pool/main/f/foobar/foobar_1.2.3_amd64.deb    # The packages themselves
+//     dists/stable/                                # One directory per suite
+//         Release                                  # Checksums of the indices below
+//         InRelease                                # The Release file, clearsigned
+//         main/binary-amd64/Packages               # The control files of all packages,
+//         main/binary-amd64/Packages.gz            # plus their paths and checksums
+//
+// Packages are copied into the pool, and then the suite's directory is
+// regenerated from scratch from the packages in the pool, so that a repository
+// can accumulate packages (and versions) across several runs.  See
+// https://wiki.debian.org/DebianRepository/Format for the full format.
+
+use super::common::{DEFAULT_GZIP_LEVEL, gzip_encoder};
+use super::deb_reader::{DebPackage, parse_fields};
+use super::gpg::GpgHome;
+use super::shlibdeps::compare_versions;
+use crate::bundle::common;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const COMPONENT: &str = "main";
+
+/// The environment variable holding the passphrase of the signing key, if it
+/// has one.
+const PASSPHRASE_VAR: &str = "APT_SIGN_PASSPHRASE";
+
+/// A package in the repository's pool.
+struct PoolPackage {
+    name: String,
+    version: String,
+    architecture: String,
+    control: String,
+    /// The path of the package relative to the repository root.
+    filename: String,
+    size: u64,
+    md5: String,
+    sha256: String,
+}
+
+/// Adds the given `.deb` packages to the APT repository in `repo_dir` and
+/// regenerates its indices for `suite`.  If `sign_key` is given, the
+/// `Release` file is signed into `InRelease` with the OpenPGP secret key in
+/// that file.
+pub fn update_apt_repository(
+    repo_dir: &Path,
+    suite: &str,
+    sign_key: Option<&Path>,
+    debs: &[PathBuf],
+) -> crate::Result<()> {
+    let valid_suite = !suite.is_empty()
+        && !suite.starts_with('.')
+        && suite
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid_suite {
+        anyhow::bail!("Invalid APT suite name {suite:?}");
+    }
+    if debs.is_empty() {
+        anyhow::bail!("No deb packages were bundled to add to the APT repository");
+    }
+    common::print_indexing(&repo_dir.display().to_string())?;
+
+    for deb in debs {
+        let fields = parse_fields(&DebPackage::open(deb)?.control()?);
+        let name = field(&fields, "Package")
+            .with_context(|| format!("Failed to read control file of {deb:?}"))?;
+        let file_name = deb
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("{deb:?} has no file name"))?;
+        let dest = repo_dir
+            .join("pool")
+            .join(COMPONENT)
+            .join(pool_prefix(name))
+            .join(name)
+            .join(file_name);
+        common::copy_file(deb, &dest)
+            .with_context(|| format!("Failed to copy {deb:?} into the repository pool"))?;
+    }
+
+    let packages = read_pool(repo_dir)?;
+    rebuild_suite(&repo_dir.join("dists"), suite, &packages, sign_key)
+}
+
+/// Replaces `dists_dir/<suite>` with freshly generated indices of
+/// `packages`, so that nothing is left over from earlier runs (such as the
+/// index of an architecture that is no longer in the pool, or a stale
+/// signature).  The new suite is written next to the old one, which is only
+/// replaced once everything (including signing) has succeeded.
+fn rebuild_suite(
+    dists_dir: &Path,
+    suite: &str,
+    packages: &[PoolPackage],
+    sign_key: Option<&Path>,
+) -> crate::Result<()> {
+    // Suite names can't start with a dot, so this can't be another suite.
+    let new_suite_dir = dists_dir.join(format!(".{suite}.new"));
+    if new_suite_dir.exists() {
+        fs::remove_dir_all(&new_suite_dir)
+            .with_context(|| format!("Failed to remove {new_suite_dir:?}"))?;
+    }
+    let written = write_suite(&new_suite_dir, suite, packages).and_then(|()| match sign_key {
+        Some(key) => sign_release(
+            &new_suite_dir.join("Release"),
+            &new_suite_dir.join("InRelease"),
+            key,
+        ),
+        None => Ok(()),
+    });
+    if let Err(error) = written {
+        let _ = fs::remove_dir_all(&new_suite_dir);
+        return Err(error);
+    }
+    let suite_dir = dists_dir.join(suite);
+    if suite_dir.exists() {
+        fs::remove_dir_all(&suite_dir)
+            .with_context(|| format!("Failed to remove the old indices in {suite_dir:?}"))?;
+    }
+    fs::rename(&new_suite_dir, &suite_dir)
+        .with_context(|| format!("Failed to move the new indices to {suite_dir:?}"))?;
+    Ok(())
+}
+
+/// Writes the `Packages` indices and `Release` file of `packages` for
+/// `suite` into `suite_dir`.
+fn write_suite(suite_dir: &Path, suite: &str, packages: &[PoolPackage]) -> crate::Result<()> {
+    let mut architectures: Vec<&str> = packages
+        .iter()
+        .map(|package| package.architecture.as_str())
+        .filter(|&arch| arch != "all")
+        .collect();
+    architectures.sort_unstable();
+    architectures.dedup();
+    if architectures.is_empty() {
+        architectures.push("all");
+    }
+
+    let mut index_files = Vec::new();
+    for &arch in &architectures {
+        let mut stanzas = Vec::new();
+        for package in packages {
+            if package.architecture == arch || package.architecture == "all" {
+                stanzas.push(package_stanza(package));
+            }
+        }
+        let index = stanzas.join("\n");
+        let index_dir = format!("{COMPONENT}/binary-{arch}");
+        let packages_path = format!("{index_dir}/Packages");
+        fs::create_dir_all(suite_dir.join(&index_dir))?;
+        fs::write(suite_dir.join(&packages_path), &index)
+            .with_context(|| format!("Failed to write {packages_path}"))?;
+        let mut encoder = gzip_encoder(Vec::new(), DEFAULT_GZIP_LEVEL)?;
+        encoder.write_all(index.as_bytes())?;
+        let compressed = encoder.finish()?;
+        fs::write(suite_dir.join(format!("{packages_path}.gz")), &compressed)
+            .with_context(|| format!("Failed to write {packages_path}.gz"))?;
+        index_files.push((packages_path.clone(), index.into_bytes()));
+        index_files.push((format!("{packages_path}.gz"), compressed));
+    }
+
+    fs::write(
+        suite_dir.join("Release"),
+        release_file(suite, &architectures, &index_files)?,
+    )
+    .with_context(|| "Failed to write Release file")
+}
+
+/// Returns the value of the control field `name`, or an error if it is
+/// missing.
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> crate::Result<&'a str> {
+    fields
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing {name} field"))
+}
+
+/// Packages are spread over subdirectories of the pool by their first letter,
+/// or the first four letters for libraries, as in Debian's own archive.
+fn pool_prefix(name: &str) -> &str {
+    let len = if name.starts_with("lib") && name.len() > 3 {
+        4
+    } else {
+        1
+    };
+    name.get(..len).unwrap_or(name)
+}
+
+/// Reads the control data and checksums of all packages in the pool, sorted
+/// by name, version and architecture.
+fn read_pool(repo_dir: &Path) -> crate::Result<Vec<PoolPackage>> {
+    let mut packages = Vec::new();
+    for entry in WalkDir::new(repo_dir.join("pool")).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().is_none_or(|ext| ext != "deb") {
+            continue;
+        }
+        let control = DebPackage::open(path)?
+            .control()
+            .with_context(|| format!("Failed to read control file of {path:?}"))?;
+        let fields = parse_fields(&control);
+        let field =
+            |name| field(&fields, name).with_context(|| format!("Invalid package {path:?}"));
+        let filename = path
+            .strip_prefix(repo_dir)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = fs::read(path)?;
+        packages.push(PoolPackage {
+            name: field("Package")?.to_string(),
+            version: field("Version")?.to_string(),
+            architecture: field("Architecture")?.to_string(),
+            control: control.trim_end().to_string(),
+            filename,
+            size: contents.len() as u64,
+            md5: format!("{:x}", md5::compute(&contents)),
+            sha256: format!("{:x}", Sha256::digest(&contents)),
+        });
+    }
+    packages.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| compare_versions(&a.version, &b.version))
+            .then_with(|| a.architecture.cmp(&b.architecture))
+            .then_with(|| a.filename.cmp(&b.filename))
+    });
+    Ok(packages)
+}
+
+/// The entry for `package` in a `Packages` index: its control file, plus the
+/// location and checksums of the package file.
+fn package_stanza(package: &PoolPackage) -> String {
+    format!(
+        "{}\nFilename: {}\nSize: {}\nMD5sum: {}\nSHA256: {}\n",
+        package.control, package.filename, package.size, package.md5, package.sha256
+    )
+}
+
+fn release_file(
+    suite: &str,
+    architectures: &[&str],
+    index_files: &[(String, Vec<u8>)],
+) -> crate::Result<String> {
+    let timestamp = match common::source_date_epoch()? {
+        Some(timestamp) => chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .ok_or_else(|| anyhow::anyhow!("SOURCE_DATE_EPOCH is out of range"))?,
+        None => chrono::Utc::now(),
+    };
+    let mut release = format!(
+        "Suite: {suite}\n\
+         Codename: {suite}\n\
+         Date: {}\n\
+         Architectures: {}\n\
+         Components: {COMPONENT}\n",
+        timestamp.format("%a, %d %b %Y %H:%M:%S UTC"),
+        architectures.join(" "),
+    );
+    release.push_str("MD5Sum:\n");
+    for (path, contents) in index_files {
+        let md5 = md5::compute(contents);
+        release.push_str(&format!(" {md5:x} {:>16} {path}\n", contents.len()));
+    }
+    release.push_str("SHA256:\n");
+    for (path, contents) in index_files {
+        let sha256 = Sha256::digest(contents);
+        release.push_str(&format!(" {sha256:x} {:>16} {path}\n", contents.len()));
+    }
+    Ok(release)
+}
+
+/// Clearsigns the `Release` file into `InRelease` with the OpenPGP secret
+/// key in the file `secret_key`.
+fn sign_release(
+    release_path: &Path,
+    in_release_path: &Path,
+    secret_key: &Path,
+) -> crate::Result<()> {
+    let gpg_home = GpgHome::new()?;
+    gpg_home
+        .import(secret_key)
+        .with_context(|| format!("Failed to import the signing key {secret_key:?}"))?;
+    gpg_home
+        .sign(
+            [
+                OsStr::new("--digest-algo"),
+                OsStr::new("SHA256"),
+                OsStr::new("--output"),
+                in_release_path.as_os_str(),
+                OsStr::new("--clearsign"),
+                release_path.as_os_str(),
+            ],
+            PASSPHRASE_VAR,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to sign the Release file (if the key has a passphrase, set it in \
+                 {PASSPHRASE_VAR})"
+            )
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_prefixes() {
+        assert_eq!(pool_prefix("foobar"), "f");
+        assert_eq!(pool_prefix("libfoo1"), "libf");
+        assert_eq!(pool_prefix("lib"), "l");
+    }
+
+    fn pool_package(name: &str, architecture: &str) -> PoolPackage {
+        PoolPackage {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            architecture: architecture.to_string(),
+            control: format!("Package: {name}\nArchitecture: {architecture}"),
+            filename: format!("pool/main/f/{name}/{name}_1.0_{architecture}.deb"),
+            size: 0,
+            md5: String::new(),
+            sha256: String::new(),
+        }
+    }
+
+    #[test]
+    fn rebuilt_suite_drops_stale_indices() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dists_dir = temp_dir.path().join("dists");
+        let suite_dir = dists_dir.join("stable");
+        let packages = [pool_package("foo", "amd64"), pool_package("foo", "arm64")];
+        rebuild_suite(&dists_dir, "stable", &packages, None).unwrap();
+        assert!(suite_dir.join("main/binary-arm64/Packages").is_file());
+        std::fs::write(suite_dir.join("InRelease"), "old signature").unwrap();
+
+        rebuild_suite(&dists_dir, "stable", &packages[..1], None).unwrap();
+        assert!(suite_dir.join("main/binary-amd64/Packages.gz").is_file());
+        assert!(!suite_dir.join("main/binary-arm64").exists());
+        assert!(!suite_dir.join("InRelease").exists());
+        let release = std::fs::read_to_string(suite_dir.join("Release")).unwrap();
+        assert!(release.contains("Architectures: amd64\n"));
+        assert!(!release.contains("binary-arm64"));
+        assert_eq!(std::fs::read_dir(&dists_dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn release_checksums() {
+        let index_files = vec![(
+            "main/binary-amd64/Packages".to_string(),
+            b"Package: foo\n".to_vec(),
+        )];
+        let release = release_file("stable", &["amd64"], &index_files).unwrap();
+        assert!(release.starts_with("Suite: stable\nCodename: stable\nDate: "));
+        assert!(release.contains("Architectures: amd64\nComponents: main\n"));
+        let md5 = format!("{:x}", md5::compute(b"Package: foo\n"));
+        assert!(release.contains(&format!(
+            "MD5Sum:\n {md5}               13 main/binary-amd64/Packages\n"
+        )));
+        assert!(release.contains("SHA256:\n "));
+    }
+}
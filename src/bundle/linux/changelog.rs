@@ -0,0 +1,129 @@
+// A parser for changelogs in the Keep a Changelog format
+// (https://keepachangelog.com/en/1.1.0/), which look like:
+//
+// # Changelog
+//
+// ## [Unreleased]
+//
+// ## [1.1.0] - 2019-02-15
+// ### Added
+// - Support for frobnicating widgets.
+// ### Fixed
+// - A crash when no widgets are
+//   configured.
+//
+// [1.1.0]: https://example.com/compare/v1.0.0...v1.1.0
+
+use chrono::NaiveDate;
+
+/// A released version listed in a changelog.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Release {
+    pub version: String,
+    pub date: Option<NaiveDate>,
+    /// The changes in this release, grouped by their type (`Added`, `Fixed`,
+    /// etc.), in order.  Multi-line items are joined into a single line.
+    pub changes: Vec<(String, Vec<String>)>,
+}
+
+/// Parses the releases in a Keep a Changelog formatted file, newest first as
+/// they appear in the file.  The `Unreleased` section is skipped.
+pub fn parse_changelog(contents: &str) -> Vec<Release> {
+    let mut releases: Vec<Release> = Vec::new();
+    let mut in_release = false;
+    for line in contents.lines() {
+        if let Some(heading) = line.strip_prefix("## ") {
+            let (version, date) = match heading.split_once(" - ") {
+                Some((version, date)) => (version, date.trim()),
+                None => (heading, ""),
+            };
+            let version = version.trim().trim_start_matches('[').trim_end_matches(']');
+            in_release = !version.eq_ignore_ascii_case("unreleased");
+            if in_release {
+                releases.push(Release {
+                    version: version.trim_start_matches('v').to_string(),
+                    date: NaiveDate::parse_from_str(date, "%Y-%m-%d").ok(),
+                    changes: Vec::new(),
+                });
+            }
+            continue;
+        }
+        let Some(release) = releases.last_mut().filter(|_| in_release) else {
+            continue;
+        };
+        if let Some(heading) = line.strip_prefix("### ") {
+            release
+                .changes
+                .push((heading.trim().to_string(), Vec::new()));
+        } else if let Some(item) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            if release.changes.is_empty() {
+                release.changes.push((String::new(), Vec::new()));
+            }
+            let (_, items) = release.changes.last_mut().unwrap();
+            items.push(item.trim().to_string());
+        } else if line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+            // A continuation of the previous item.
+            if let Some(item) = release
+                .changes
+                .last_mut()
+                .and_then(|(_, items)| items.last_mut())
+            {
+                item.push(' ');
+                item.push_str(line.trim());
+            }
+        }
+    }
+    releases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keep_a_changelog() {
+        let releases = parse_changelog(
+            "# Changelog\n\
+             \n\
+             ## [Unreleased]\n\
+             ### Added\n\
+             - Not yet released.\n\
+             \n\
+             ## [1.1.0] - 2019-02-15\n\
+             ### Added\n\
+             - Support for frobnicating widgets.\n\
+             ### Fixed\n\
+             - A crash when no widgets are\n\
+             \x20 configured.\n\
+             \n\
+             ## 1.0.0\n\
+             * Initial release.\n\
+             \n\
+             [1.1.0]: https://example.com/compare/v1.0.0...v1.1.0\n",
+        );
+        assert_eq!(
+            releases,
+            vec![
+                Release {
+                    version: "1.1.0".to_string(),
+                    date: NaiveDate::from_ymd_opt(2019, 2, 15),
+                    changes: vec![
+                        (
+                            "Added".to_string(),
+                            vec!["Support for frobnicating widgets.".to_string()]
+                        ),
+                        (
+                            "Fixed".to_string(),
+                            vec!["A crash when no widgets are configured.".to_string()]
+                        ),
+                    ],
+                },
+                Release {
+                    version: "1.0.0".to_string(),
+                    date: None,
+                    changes: vec![(String::new(), vec!["Initial release.".to_string()])],
+                },
+            ]
+        );
+    }
+}
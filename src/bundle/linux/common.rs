@@ -1,92 +1,289 @@
-use crate::bundle::{Settings, common};
+use super::desktop_entry::{
+    escape_list, escape_string, localized_entries, quote_exec_arg, validate_desktop_entry,
+};
+use crate::bundle::common::{self, IconSource};
+use crate::bundle::{Compression, FileAssociationRole, LinuxFile, Settings};
+use anyhow::Context;
+use flate2::write::GzEncoder;
 use image::GenericImageView;
-use libflate::gzip;
 use md5::Digest;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use walkdir::WalkDir;
 
+/// Returns the name used for the Linux package (e.g. the `Package` field of a
+/// deb control file), derived from the bundle name.
+pub fn package_name(settings: &Settings) -> String {
+    str::replace(settings.bundle_name(), " ", "-").to_ascii_lowercase()
+}
+
 /// Generate the application desktop file and store it under the `data_dir`.
 pub fn generate_desktop_file(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
-    let bin_name = settings.binary_name();
-    let desktop_file_name = format!("{bin_name}.desktop");
+    let desktop_file_name = format!("{}.desktop", settings.binary_name());
     let desktop_file_path = data_dir
         .join("usr/share/applications")
-        .join(desktop_file_name);
-    let file = &mut common::create_file(&desktop_file_path)?;
-    let mime_types = settings
-        .linux_mime_types()
-        .iter()
-        .fold("".to_owned(), |acc, s| format!("{acc}{s};"));
+        .join(&desktop_file_name);
+    let contents = desktop_file_contents(settings)?;
+    let warnings = validate_desktop_entry(&contents)
+        .with_context(|| format!("Generated {desktop_file_name} is not valid"))?;
+    for warning in warnings {
+        common::print_warning(&format!("{desktop_file_name}: {warning}"))?;
+    }
+    create_file_with_data(&desktop_file_path, &contents)
+}
+
+fn desktop_file_contents(settings: &Settings) -> crate::Result<String> {
+    let bin_name = settings.binary_name();
+    let mut mime_types: Vec<String> = settings.linux_mime_types().to_vec();
+    for association in settings.file_associations() {
+        if let Some(mime_type) = &association.mime_type
+            && association.role != FileAssociationRole::None
+            && !mime_types.contains(mime_type)
+        {
+            mime_types.push(mime_type.clone());
+        }
+    }
+    for scheme in settings.url_schemes() {
+        let mime_type = format!("x-scheme-handler/{scheme}");
+        if !mime_types.contains(&mime_type) {
+            mime_types.push(mime_type);
+        }
+    }
+    let exec = exec_command(bin_name, settings.linux_exec_args(), &mime_types);
     // For more information about the format of this file, see
-    // https://developer.gnome.org/integration-guide/stable/desktop-files.html.en
-    writeln!(file, "[Desktop Entry]")?;
-    writeln!(file, "Encoding=UTF-8")?;
-    if let Some(category) = settings.app_category() {
-        writeln!(file, "Categories={}", category.gnome_desktop_categories())?;
+    // https://specifications.freedesktop.org/desktop-entry-spec/latest/
+    let mut contents = String::new();
+    writeln!(contents, "[Desktop Entry]")?;
+    writeln!(contents, "Type=Application")?;
+    writeln!(contents, "Name={}", escape_string(settings.bundle_name()))?;
+    if let Some(names) = settings.linux_localized_name() {
+        contents.push_str(&localized_entries("Name", names));
+    }
+    if let Some(generic_name) = settings.linux_generic_name() {
+        writeln!(contents, "GenericName={}", escape_string(generic_name))?;
     }
     if !settings.short_description().is_empty() {
-        writeln!(file, "Comment={}", settings.short_description())?;
+        writeln!(
+            contents,
+            "Comment={}",
+            escape_string(settings.short_description())
+        )?;
     }
-    let exec = match settings.linux_exec_args() {
-        Some(args) => format!("{bin_name} {args}"),
-        None => bin_name.to_owned(),
-    };
-    writeln!(file, "Exec={exec}")?;
-    writeln!(file, "Icon={bin_name}")?;
-    writeln!(file, "Name={}", settings.bundle_name())?;
+    if let Some(comments) = settings.linux_localized_comment() {
+        contents.push_str(&localized_entries("Comment", comments));
+    }
+    if !settings.linux_keywords().is_empty() {
+        let keywords = settings.linux_keywords().iter().map(String::as_str);
+        writeln!(contents, "Keywords={}", escape_list(keywords))?;
+    }
+    writeln!(contents, "Icon={}", escape_string(bin_name))?;
+    writeln!(contents, "Exec={}", escape_string(&exec))?;
     writeln!(
-        file,
+        contents,
         "Terminal={}",
         settings.linux_use_terminal().unwrap_or(false)
     )?;
-    writeln!(file, "Type=Application")?;
-    writeln!(file, "MimeType={mime_types}")?;
+    if let Some(startup_notify) = settings.linux_startup_notify() {
+        writeln!(contents, "StartupNotify={startup_notify}")?;
+    }
+    if let Some(wm_class) = settings.linux_startup_wm_class() {
+        writeln!(contents, "StartupWMClass={}", escape_string(wm_class))?;
+    }
+    if let Some(no_display) = settings.linux_no_display() {
+        writeln!(contents, "NoDisplay={no_display}")?;
+    }
+    if let Some(category) = settings.app_category() {
+        writeln!(
+            contents,
+            "Categories={}",
+            category.gnome_desktop_categories()
+        )?;
+    }
+    if !mime_types.is_empty() {
+        let mime_types = mime_types.iter().map(String::as_str);
+        writeln!(contents, "MimeType={}", escape_list(mime_types))?;
+    }
+    let actions = settings.linux_desktop_actions();
+    if !actions.is_empty() {
+        let ids = actions.iter().map(|action| action.id.as_str());
+        writeln!(contents, "Actions={}", escape_list(ids))?;
+    }
     // The `Version` field is omitted on pupose. See `generate_control_file` for specifying
     // the application version.
-    Ok(())
+    for action in actions {
+        writeln!(contents)?;
+        writeln!(contents, "[Desktop Action {}]", action.id)?;
+        writeln!(contents, "Name={}", escape_string(&action.name))?;
+        writeln!(contents, "Exec={}", escape_string(&action.exec))?;
+        if let Some(icon) = &action.icon {
+            writeln!(contents, "Icon={}", escape_string(icon))?;
+        }
+    }
+    Ok(contents)
 }
 
-/// Creates a `.tar.gz` file from the given directory (placing the new file
-/// within the given directory's parent directory), then deletes the original
-/// directory and returns the path to the new file.
-pub fn tar_and_gzip_dir<P: AsRef<Path>>(src_dir: P) -> crate::Result<PathBuf> {
+/// Creates a compressed tar file (e.g. `.tar.gz`) from the given directory
+/// (placing the new file within the given directory's parent directory), and
+/// returns the path to the new file.  `level` overrides the default
+/// compression level for gzip (0-9), xz (0-9) and zstd (1-22), and `modes`
+/// the permission bits of the given files (see [`create_tar_from_dir`]).
+pub fn tar_and_compress_dir<P: AsRef<Path>>(
+    src_dir: P,
+    compression: Compression,
+    level: Option<u32>,
+    modes: &BTreeMap<PathBuf, u32>,
+) -> crate::Result<PathBuf> {
     let src_dir = src_dir.as_ref();
-    let dest_path = src_dir.with_extension("tar.gz");
+    let dest_path = src_dir.with_extension(compression.tar_extension());
     let dest_file = common::create_file(&dest_path)?;
-    let gzip_encoder = gzip::Encoder::new(dest_file)?;
-    let gzip_encoder = create_tar_from_dir(src_dir, gzip_encoder)?;
-    let mut dest_file = gzip_encoder.finish().into_result()?;
+    let mut dest_file = match compression {
+        Compression::Gzip => {
+            let level = level.unwrap_or(DEFAULT_GZIP_LEVEL);
+            let gzip_encoder = gzip_encoder(dest_file, level)?;
+            create_tar_from_dir(src_dir, gzip_encoder, modes)?.finish()?
+        }
+        Compression::Xz => {
+            let level = level.unwrap_or(6);
+            if level > 9 {
+                anyhow::bail!("Invalid xz compression level {level}, expected 0-9");
+            }
+            let xz_encoder = xz2::write::XzEncoder::new(dest_file, level);
+            create_tar_from_dir(src_dir, xz_encoder, modes)?.finish()?
+        }
+        Compression::Zstd => {
+            let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL as u32);
+            if !(1..=22).contains(&level) {
+                anyhow::bail!("Invalid zstd compression level {level}, expected 1-22");
+            }
+            let zstd_encoder = zstd::Encoder::new(dest_file, level as i32)?;
+            create_tar_from_dir(src_dir, zstd_encoder, modes)?.finish()?
+        }
+        Compression::None => create_tar_from_dir(src_dir, dest_file, modes)?,
+    };
     dest_file.flush()?;
     Ok(dest_path)
 }
 
 /// Writes a tar file to the given writer containing the given directory.
-pub fn create_tar_from_dir<P: AsRef<Path>, W: Write>(src_dir: P, dest_file: W) -> crate::Result<W> {
+/// The archive only depends on the directory's contents: entries are sorted by
+/// name and owned by root, with normalized permissions (see
+/// [`normalized_mode`]) and modification times clamped to
+/// `SOURCE_DATE_EPOCH`, if set.  The permissions of the files in `modes`
+/// (keyed by their path relative to `src_dir`) are set explicitly instead.
+pub fn create_tar_from_dir<P: AsRef<Path>, W: Write>(
+    src_dir: P,
+    dest_file: W,
+    modes: &BTreeMap<PathBuf, u32>,
+) -> crate::Result<W> {
     let src_dir = src_dir.as_ref();
+    let source_date_epoch = common::source_date_epoch()?;
     let mut tar_builder = tar::Builder::new(dest_file);
-    for entry in WalkDir::new(src_dir) {
+    for entry in WalkDir::new(src_dir).sort_by_file_name() {
         let entry = entry?;
         let src_path = entry.path();
         if src_path == src_dir {
             continue;
         }
         let dest_path = src_path.strip_prefix(src_dir).unwrap();
+        let metadata = entry.metadata()?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+        let mode = modes
+            .get(dest_path)
+            .copied()
+            .unwrap_or_else(|| normalized_mode(&metadata));
+        header.set_mode(mode);
+        header.set_mtime(modification_time(&metadata, source_date_epoch));
+        header.set_username("root")?;
+        header.set_groupname("root")?;
         if entry.file_type().is_dir() {
-            tar_builder.append_dir(dest_path, src_path)?;
+            tar_builder.append_data(&mut header, dest_path, io::empty())?;
+        } else if entry.file_type().is_symlink() {
+            let target = std::fs::read_link(src_path)?;
+            tar_builder.append_link(&mut header, dest_path, target)?;
         } else {
-            let mut src_file = File::open(src_path)?;
-            tar_builder.append_file(dest_path, &mut src_file)?;
+            let src_file = File::open(src_path)?;
+            tar_builder.append_data(&mut header, dest_path, src_file)?;
         }
     }
     let dest_file = tar_builder.into_inner()?;
     Ok(dest_file)
 }
 
+/// The gzip compression level used when none is configured, as with
+/// dpkg-deb and rpmbuild.
+pub const DEFAULT_GZIP_LEVEL: u32 = 9;
+
+/// Creates a gzip encoder with the given compression `level` (0-9), whose
+/// header doesn't record the current time, so that the output only depends
+/// on the data written to it.
+pub fn gzip_encoder<W: Write>(writer: W, level: u32) -> crate::Result<GzEncoder<W>> {
+    if level > 9 {
+        anyhow::bail!("Invalid gzip compression level {level}, expected 0-9");
+    }
+    let timestamp = common::source_date_epoch()?.unwrap_or(0);
+    Ok(flate2::GzBuilder::new()
+        .mtime(timestamp as u32)
+        .write(writer, flate2::Compression::new(level)))
+}
+
+/// Returns the `Exec` command line of the desktop file: the binary, followed
+/// by `exec_args` if given.  Otherwise, if the app handles any `mime_types`,
+/// the files (`%U`) or URL (`%u`) being opened are passed to it, as launchers
+/// won't offer to open them with the app if its command line has no place
+/// for them.
+fn exec_command(bin_name: &str, exec_args: Option<&str>, mime_types: &[String]) -> String {
+    let mut exec = quote_exec_arg(bin_name).into_owned();
+    if let Some(args) = exec_args {
+        exec = format!("{exec} {args}");
+    } else if mime_types
+        .iter()
+        .any(|mime_type| !mime_type.starts_with("x-scheme-handler/"))
+    {
+        exec.push_str(" %U");
+    } else if !mime_types.is_empty() {
+        exec.push_str(" %u");
+    }
+    exec
+}
+
+/// Returns the permission bits that a file should be packaged with: 0755 for
+/// directories and files executable by their owner, and 0644 for anything
+/// else.
+pub fn normalized_mode(metadata: &std::fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    let executable = {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o100 != 0
+    };
+    #[cfg(not(unix))]
+    let executable = false;
+    if metadata.is_dir() || executable {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// Returns the modification time of a file in seconds since the Unix epoch,
+/// clamped to `source_date_epoch` if given.
+pub fn modification_time(metadata: &std::fs::Metadata, source_date_epoch: Option<u64>) -> u64 {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+    match source_date_epoch {
+        Some(epoch) => mtime.min(epoch),
+        None => mtime,
+    }
+}
+
 /// Create an empty file at the given path, creating any parent directories as
 /// needed, then write `data` into the file.
 pub fn create_file_with_data<P: AsRef<Path>>(path: P, data: &str) -> crate::Result<()> {
@@ -96,6 +293,19 @@ pub fn create_file_with_data<P: AsRef<Path>>(path: P, data: &str) -> crate::Resu
     Ok(())
 }
 
+/// Sets the Unix permission bits of the given file.  This is a no-op on other
+/// platforms.
+pub fn set_file_mode(path: &Path, mode: u32) -> crate::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
 /// Computes the total size, in bytes, of the given directory and all of its
 /// contents.
 pub fn total_dir_size(dir: &Path) -> crate::Result<u64> {
@@ -183,11 +393,16 @@ fn generate_icon_files_non_png(
     Ok(sizes.to_owned())
 }
 
+/// The sizes of the hicolor theme's fixed-size icon directories that icons are
+/// generated in from SVG icons and the icon source.
+const HICOLOR_SIZES: [u32; 9] = [16, 22, 24, 32, 48, 64, 128, 256, 512];
+
 /// Generate the icon files and store them under the `data_dir`.
 pub fn generate_icon_files(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
     let base_dir = data_dir.join("usr/share/icons/hicolor");
 
     let mut sizes: BTreeSet<(u32, u32, bool)> = BTreeSet::new();
+    let mut svg_paths = Vec::new();
 
     for icon_path in settings.icon_files() {
         let icon_path = icon_path?;
@@ -196,6 +411,7 @@ pub fn generate_icon_files(settings: &Settings, data_dir: &Path) -> crate::Resul
             std::fs::create_dir_all(&scalable_dir)?;
             let dest_path = scalable_dir.join(format!("{}.svg", settings.binary_name()));
             common::copy_file(&icon_path, &dest_path)?;
+            svg_paths.push(icon_path);
         } else if icon_path.extension() == Some(OsStr::new("png")) {
             let new_sizes = generate_icon_files_png(
                 &icon_path,
@@ -217,9 +433,166 @@ pub fn generate_icon_files(settings: &Settings, data_dir: &Path) -> crate::Resul
         }
     }
 
+    // Generate the sizes that no other icon file provides from SVG icons (for
+    // desktops and tools that don't use scalable icons), and then from the
+    // icon source.
+    let mut sources = Vec::new();
+    for svg_path in &svg_paths {
+        sources.push(IconSource::open(svg_path)?);
+    }
+    if let Some(source) = common::open_icon_source(settings)? {
+        // An SVG icon source is also installed as the scalable icon.
+        if let Some(source_path) = settings.icon_source()
+            && source_path.extension() == Some(OsStr::new("svg"))
+            && svg_paths.is_empty()
+        {
+            let dest_path = base_dir.join(format!("scalable/apps/{}.svg", settings.binary_name()));
+            common::copy_file(source_path, &dest_path)?;
+        }
+        sources.push(source);
+    }
+    for source in sources {
+        for size in HICOLOR_SIZES {
+            if sizes.insert((size, size, false)) {
+                let icon = source.render(size)?;
+                let dest_path = get_dest_path(size, size, false, &base_dir, settings.binary_name());
+                let mut file = common::create_file(&dest_path)?;
+                icon.write_to(&mut file, image::ImageFormat::Png)?;
+            }
+        }
+    }
+
     Ok(())
 }
 
+/// Copy the bundle's resource files into an appropriate directory under the
+/// `data_dir`.
+pub fn transfer_resource_files(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
+    let resource_dir = data_dir.join("usr/lib").join(settings.binary_name());
+    for src in settings.resource_files() {
+        let src = src?;
+        let dest = resource_dir.join(common::resource_relpath(&src));
+        common::copy_file(&src, &dest)
+            .with_context(|| format!("Failed to copy resource file {src:?}"))?;
+    }
+    Ok(())
+}
+
+/// A file installed from the bundle's `linux_files` table.
+pub struct PackageFile {
+    /// The path of the file, relative to the root of the data directory.
+    pub path: PathBuf,
+    /// The permission bits of the file, if specified.
+    pub mode: Option<u32>,
+    /// Whether the file is a configuration file.
+    pub conffile: bool,
+}
+
+impl PackageFile {
+    /// The absolute path that the file is installed to.
+    pub fn install_path(&self) -> String {
+        format!("/{}", self.path.to_string_lossy())
+    }
+}
+
+/// Copy the files listed in the bundle's `linux_files` table to their
+/// destinations under the `data_dir`, returning the list of copied files.
+pub fn transfer_linux_files(
+    settings: &Settings,
+    data_dir: &Path,
+) -> crate::Result<Vec<PackageFile>> {
+    let mut files = Vec::new();
+    for entry in settings.linux_files() {
+        files.extend(transfer_linux_file(entry, data_dir)?);
+    }
+    Ok(files)
+}
+
+/// Copies the files matched by a single `linux_files` entry.
+fn transfer_linux_file(entry: &LinuxFile, data_dir: &Path) -> crate::Result<Vec<PackageFile>> {
+    let dest = Path::new(&entry.dest);
+    let dest_rel = match dest.strip_prefix("/") {
+        Ok(dest_rel)
+            if dest_rel
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))) =>
+        {
+            dest_rel
+        }
+        _ => anyhow::bail!(
+            "linux_files destination {:?} must be an absolute path",
+            entry.dest
+        ),
+    };
+    if let Some(mode) = entry.mode
+        && mode > 0o7777
+    {
+        anyhow::bail!(
+            "Invalid mode {mode:#o} for linux_files entry {:?}",
+            entry.src
+        );
+    }
+    let matches = glob::glob(&entry.src)?.collect::<Result<Vec<_>, _>>()?;
+    // Pairs of (source path, destination path relative to `data_dir`).
+    let mut copies = Vec::new();
+    for src in &matches {
+        if src.is_dir() {
+            for walk_entry in WalkDir::new(src).sort_by_file_name() {
+                let walk_entry = walk_entry?;
+                if !walk_entry.file_type().is_dir() {
+                    let rel_path = walk_entry.path().strip_prefix(src).unwrap();
+                    copies.push((walk_entry.path().to_path_buf(), dest_rel.join(rel_path)));
+                }
+            }
+        } else {
+            let file_name = src.file_name().unwrap().to_owned();
+            copies.push((src.clone(), dest_rel.join(file_name)));
+        }
+    }
+    if copies.is_empty() {
+        anyhow::bail!("linux_files source {:?} matched no files", entry.src);
+    }
+    if !entry.dest.ends_with('/') {
+        // Only a single file (not one found in a matched directory) can
+        // be installed under a name of its own.
+        match matches.as_slice() {
+            [src] if !src.is_dir() => copies[0].1 = dest_rel.to_path_buf(),
+            _ => anyhow::bail!(
+                "linux_files source {:?} matches a directory or multiple files, so its \
+                     destination {:?} must be a directory ending with '/'",
+                entry.src,
+                entry.dest
+            ),
+        }
+    }
+    let mut files = Vec::new();
+    for (src, path) in copies {
+        let dest_path = data_dir.join(&path);
+        common::copy_file(&src, &dest_path).with_context(|| format!("Failed to copy {src:?}"))?;
+        if entry.conffile && !path.starts_with("etc") {
+            common::print_warning(&format!(
+                "Configuration file /{} is not under /etc",
+                path.display()
+            ))?;
+        }
+        files.push(PackageFile {
+            path,
+            mode: entry.mode,
+            conffile: entry.conffile,
+        });
+    }
+    Ok(files)
+}
+
+/// Returns the permission bits specified for the given files, keyed by their
+/// paths relative to the data directory.
+pub fn mode_overrides(files: &[PackageFile]) -> BTreeMap<PathBuf, u32> {
+    files
+        .iter()
+        .filter_map(|file| Some((file.path.clone(), file.mode?)))
+        .collect()
+}
+
 /// Compute the md5 hash of the given file.
 pub fn generate_md5sum(file_path: &Path) -> crate::Result<Digest> {
     let mut file = File::open(file_path)?;
@@ -245,7 +618,12 @@ mod tests {
             .unwrap()
             .write_all(b"test")
             .unwrap();
-        let tar_gz_file = tar_and_gzip_dir(temp_dir.path().join("foo"));
+        let tar_gz_file = tar_and_compress_dir(
+            temp_dir.path().join("foo"),
+            Compression::Gzip,
+            None,
+            &BTreeMap::new(),
+        );
         assert!(tar_gz_file.is_ok());
         let tar_gz_file = tar_gz_file.unwrap();
 
@@ -253,6 +631,95 @@ mod tests {
         assert!(tar_gz_file.metadata().unwrap().len() > 0);
     }
 
+    #[test]
+    fn test_tar_and_compress_dir() {
+        let temp_dir = tempdir().unwrap();
+        let src_dir = temp_dir.path().join("foo");
+        std::fs::create_dir(&src_dir).unwrap();
+        std::fs::write(src_dir.join("file.txt"), b"test").unwrap();
+
+        let tar_xz_file =
+            tar_and_compress_dir(&src_dir, Compression::Xz, Some(9), &BTreeMap::new()).unwrap();
+        assert_eq!(tar_xz_file, temp_dir.path().join("foo.tar.xz"));
+        let decoder = xz2::read::XzDecoder::new(File::open(&tar_xz_file).unwrap());
+        let mut archive = tar::Archive::new(decoder);
+        let names: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(names, vec![PathBuf::from("file.txt")]);
+
+        let tar_zst_file =
+            tar_and_compress_dir(&src_dir, Compression::Zstd, None, &BTreeMap::new()).unwrap();
+        assert_eq!(tar_zst_file, temp_dir.path().join("foo.tar.zst"));
+        let decoder = zstd::Decoder::new(File::open(&tar_zst_file).unwrap()).unwrap();
+        assert_eq!(tar::Archive::new(decoder).entries().unwrap().count(), 1);
+
+        let tar_file =
+            tar_and_compress_dir(&src_dir, Compression::None, None, &BTreeMap::new()).unwrap();
+        assert_eq!(tar_file, temp_dir.path().join("foo.tar"));
+
+        let tar_gz_file =
+            tar_and_compress_dir(&src_dir, Compression::Gzip, Some(9), &BTreeMap::new()).unwrap();
+        let decoder = flate2::read::GzDecoder::new(File::open(&tar_gz_file).unwrap());
+        assert_eq!(tar::Archive::new(decoder).entries().unwrap().count(), 1);
+
+        assert!(
+            tar_and_compress_dir(&src_dir, Compression::Gzip, Some(10), &BTreeMap::new()).is_err()
+        );
+        assert!(
+            tar_and_compress_dir(&src_dir, Compression::Xz, Some(10), &BTreeMap::new()).is_err()
+        );
+        assert!(
+            tar_and_compress_dir(&src_dir, Compression::Zstd, Some(0), &BTreeMap::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_create_tar_from_dir_is_normalized() {
+        let temp_dir = tempdir().unwrap();
+        let src_dir = temp_dir.path().join("foo");
+        std::fs::create_dir_all(src_dir.join("b")).unwrap();
+        std::fs::write(src_dir.join("c.txt"), b"c").unwrap();
+        std::fs::write(src_dir.join("a.sh"), b"#!/bin/sh\n").unwrap();
+        std::fs::write(src_dir.join("b/d.txt"), b"d").unwrap();
+        set_file_mode(&src_dir.join("a.sh"), 0o4775).unwrap();
+        set_file_mode(&src_dir.join("c.txt"), 0o666).unwrap();
+
+        let modes = BTreeMap::from([(PathBuf::from("b/d.txt"), 0o600)]);
+        let tar = create_tar_from_dir(&src_dir, Vec::new(), &modes).unwrap();
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let header = entry.unwrap().header().clone();
+                assert_eq!(header.uid().unwrap(), 0);
+                assert_eq!(header.gid().unwrap(), 0);
+                assert_eq!(header.username().unwrap(), Some("root"));
+                assert_eq!(header.groupname().unwrap(), Some("root"));
+                (
+                    header.path().unwrap().to_string_lossy().into_owned(),
+                    header.mode().unwrap(),
+                )
+            })
+            .collect();
+        #[cfg(unix)]
+        let script_mode = 0o755;
+        #[cfg(not(unix))]
+        let script_mode = 0o644;
+        assert_eq!(
+            entries,
+            vec![
+                ("a.sh".to_string(), script_mode),
+                ("b".to_string(), 0o755),
+                ("b/d.txt".to_string(), 0o600),
+                ("c.txt".to_string(), 0o644),
+            ]
+        );
+    }
+
     #[test]
     fn test_create_file_with_data() {
         let temp_dir = tempdir().unwrap();
@@ -280,4 +747,85 @@ mod tests {
 
         assert_eq!(md5_str, "098f6bcd4621d373cade4e832627b4f6".to_string());
     }
+
+    #[test]
+    fn exec_command_arguments() {
+        let mime_types = |types: &[&str]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(exec_command("my app", None, &[]), "\"my app\"");
+        assert_eq!(
+            exec_command("app", None, &mime_types(&["image/png"])),
+            "app %U"
+        );
+        assert_eq!(
+            exec_command("app", None, &mime_types(&["x-scheme-handler/app"])),
+            "app %u"
+        );
+        assert_eq!(
+            exec_command(
+                "app",
+                None,
+                &mime_types(&["x-scheme-handler/app", "text/plain"])
+            ),
+            "app %U"
+        );
+        assert_eq!(
+            exec_command("app", Some("--open %f"), &mime_types(&["image/png"])),
+            "app --open %f"
+        );
+    }
+
+    fn linux_file(src: &Path, dest: &str) -> LinuxFile {
+        LinuxFile {
+            src: src.to_str().unwrap().to_string(),
+            dest: dest.to_string(),
+            mode: None,
+            conffile: false,
+        }
+    }
+
+    fn installed_paths(files: &[PackageFile]) -> Vec<String> {
+        files.iter().map(PackageFile::install_path).collect()
+    }
+
+    #[test]
+    fn transfer_linux_file_destinations() {
+        let src_dir = tempdir().unwrap();
+        let data_dir = tempdir().unwrap();
+        for path in ["a.dat", "b.dat", "conf/main.toml"] {
+            let path = src_dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "data").unwrap();
+        }
+        let transfer = |src: &str, dest: &str| {
+            transfer_linux_file(
+                &linux_file(&src_dir.path().join(src), dest),
+                data_dir.path(),
+            )
+        };
+
+        // A single file is installed under the destination's name, or into
+        // the destination if that ends with '/'.
+        let files = transfer("a.dat", "/usr/share/example/data.dat").unwrap();
+        assert_eq!(installed_paths(&files), ["/usr/share/example/data.dat"]);
+        assert!(data_dir.path().join("usr/share/example/data.dat").is_file());
+        let files = transfer("a.dat", "/usr/share/example/").unwrap();
+        assert_eq!(installed_paths(&files), ["/usr/share/example/a.dat"]);
+
+        // Globs and directories need a destination ending with '/', even if
+        // they only match a single file.
+        let files = transfer("*.dat", "/usr/share/example/").unwrap();
+        assert_eq!(
+            installed_paths(&files),
+            ["/usr/share/example/a.dat", "/usr/share/example/b.dat"]
+        );
+        assert!(transfer("*.dat", "/usr/share/example").is_err());
+        let files = transfer("conf", "/etc/example/").unwrap();
+        assert_eq!(installed_paths(&files), ["/etc/example/main.toml"]);
+        assert!(transfer("conf", "/etc/example/config.toml").is_err());
+        assert!(transfer("*.missing", "/usr/share/example/").is_err());
+
+        // Destinations must be absolute.
+        assert!(transfer("a.dat", "usr/share/example/").is_err());
+        assert!(transfer("a.dat", "/usr/share/../example/").is_err());
+    }
 }
@@ -5,32 +5,77 @@
 //     control.tar.gz          # Contains files controlling the installation:
 //         control                  # Basic package metadata
 //         md5sums                  # Checksums for files in data.tar.gz below
+//         conffiles                # List of configuration files (optional)
+//         preinst                  # Pre-installation script (optional)
 //         postinst                 # Post-installation script (optional)
 //         prerm                    # Pre-uninstallation script (optional)
+//         postrm                   # Post-uninstallation script (optional)
+//         triggers                 # Trigger declarations (optional)
 //     data.tar.gz             # Contains files to be installed:
 //         usr/bin/foobar                            # Binary executable file
 //         usr/share/applications/foobar.desktop     # Desktop file (for apps)
 //         usr/share/icons/hicolor/...               # Icon files (for apps)
+//         usr/share/doc/foobar/copyright            # Copyright and licenses
+//         usr/share/doc/foobar/changelog.Debian.gz  # Debian changelog
 //         usr/lib/foobar/...                        # Other resource files
+//         usr/lib/systemd/system/...                # systemd units
+//         etc/foobar/...                            # Configuration files, etc.
 //
-// For cargo-bundle, we put bundle resource files under /usr/lib/package_name/,
-// and then generate the desktop file and control file from the bundle
-// metadata, as well as generating the md5sums file.  Currently we do not
-// generate postinst or prerm files.
+// For cargo-bundle, we put bundle resource files under /usr/lib/package_name/
+// and the files from the `linux_files` table at their requested destinations
+// (listing those marked as configuration files in conffiles), and then generate
+// the desktop file and control file from the bundle metadata, as well as
+// generating the md5sums file.  Maintainer scripts are taken from the bundle
+// settings; unless disabled, postinst and postrm also refresh the icon cache
+// and desktop database so that the app's icon shows up without the user having
+// to log out and back in, and they enable, start and stop any systemd units as
+// dh_installsystemd would.  The control and data archives are gzipped by
+// default, but may instead be compressed with xz or zstd (as data.tar.xz, etc.)
+// or left uncompressed.
 
 use crate::bundle::{
-    Settings, common,
+    DebScript, Settings, common,
     linux::common::{
-        create_file_with_data, generate_desktop_file, generate_icon_files, generate_md5sum,
-        tar_and_gzip_dir, total_dir_size,
+        DEFAULT_GZIP_LEVEL, PackageFile, create_file_with_data, generate_desktop_file,
+        generate_icon_files, generate_md5sum, gzip_encoder, mode_overrides, package_name,
+        set_file_mode, tar_and_compress_dir, total_dir_size, transfer_linux_files,
+        transfer_resource_files,
+    },
+    linux::{
+        appstream::generate_metainfo_file,
+        changelog::{Release, parse_changelog},
+        elf, license_texts,
+        mime_info::generate_mime_info_file,
+        shared_libs, shlibdeps,
+        systemd::SystemdFiles,
     },
 };
 use anyhow::Context;
 
+use std::collections::BTreeMap;
+use std::fs::File;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+/// The values allowed in the `Priority` field of a control file.
+const PRIORITIES: &[&str] = &["required", "important", "standard", "optional", "extra"];
+
+/// Token which, when present in a user-supplied maintainer script, is replaced
+/// with the commands generated by cargo-bundle (like debhelper does).
+const SCRIPT_TOKEN: &str = "#DEBHELPER#";
+
+/// Commands run by `postinst` and `postrm` to make newly installed (or
+/// removed) icons and desktop files visible to the desktop environment.
+const REFRESH_CACHES_SCRIPT: &str = "\
+if command -v gtk-update-icon-cache >/dev/null 2>&1; then
+	gtk-update-icon-cache -q -t -f /usr/share/icons/hicolor || true
+fi
+if command -v update-desktop-database >/dev/null 2>&1; then
+	update-desktop-database -q /usr/share/applications || true
+fi
+";
+
 pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     let arch = match settings.binary_arch() {
         "x86" => "i386",
@@ -64,12 +109,29 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
         .with_context(|| "Failed to copy resource files")?;
     generate_icon_files(settings, &data_dir).with_context(|| "Failed to create icon files")?;
     generate_desktop_file(settings, &data_dir).with_context(|| "Failed to create desktop file")?;
+    generate_metainfo_file(settings, &data_dir)
+        .with_context(|| "Failed to create AppStream metainfo file")?;
+    generate_mime_info_file(settings, &data_dir)
+        .with_context(|| "Failed to create shared MIME info file")?;
+    let linux_files =
+        transfer_linux_files(settings, &data_dir).with_context(|| "Failed to copy linux_files")?;
+    let systemd_files = SystemdFiles::install(settings, &data_dir)
+        .with_context(|| "Failed to copy systemd files")?;
+    generate_copyright_file(settings, &data_dir)
+        .with_context(|| "Failed to create copyright file")?;
+    generate_changelog_file(settings, &data_dir)
+        .with_context(|| "Failed to create changelog file")?;
 
     // Generate control files.
     let control_dir = package_dir.join("control");
     generate_control_file(settings, arch, &control_dir, &data_dir)
         .with_context(|| "Failed to create control file")?;
-    generate_md5sums(&control_dir, &data_dir).with_context(|| "Failed to create md5sums file")?;
+    generate_md5sums(&control_dir, &data_dir, &linux_files)
+        .with_context(|| "Failed to create md5sums file")?;
+    generate_conffiles(&control_dir, &linux_files)
+        .with_context(|| "Failed to create conffiles file")?;
+    generate_maintainer_scripts(settings, &systemd_files, &control_dir)
+        .with_context(|| "Failed to create maintainer scripts")?;
 
     // Generate `debian-binary` file; see
     // http://www.tldp.org/HOWTO/Debian-Binary-Package-Building-HOWTO/x60.html#AEN66
@@ -77,13 +139,16 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     create_file_with_data(&debian_binary_path, "2.0\n")
         .with_context(|| "Failed to create debian-binary file")?;
 
-    // Apply tar/gzip/ar to create the final package file.
-    let control_tar_gz_path =
-        tar_and_gzip_dir(control_dir).with_context(|| "Failed to tar/gzip control directory")?;
-    let data_tar_gz_path =
-        tar_and_gzip_dir(data_dir).with_context(|| "Failed to tar/gzip data directory")?;
+    // Apply tar/compression/ar to create the final package file.
+    let compression = settings.deb_compression();
+    let level = settings.deb_compression_level();
+    let control_tar_path = tar_and_compress_dir(control_dir, compression, level, &BTreeMap::new())
+        .with_context(|| "Failed to tar/compress control directory")?;
+    let data_tar_path =
+        tar_and_compress_dir(data_dir, compression, level, &mode_overrides(&linux_files))
+            .with_context(|| "Failed to tar/compress data directory")?;
     create_archive(
-        vec![debian_binary_path, control_tar_gz_path, data_tar_gz_path],
+        vec![debian_binary_path, control_tar_path, data_tar_path],
         &package_path,
     )
     .with_context(|| "Failed to create package archive")?;
@@ -100,11 +165,7 @@ fn generate_control_file(
     // https://www.debian.org/doc/debian-policy/ch-controlfields.html
     let dest_path = control_dir.join("control");
     let mut file = common::create_file(&dest_path)?;
-    writeln!(
-        &mut file,
-        "Package: {}",
-        str::replace(settings.bundle_name(), " ", "-").to_ascii_lowercase()
-    )?;
+    writeln!(&mut file, "Package: {}", package_name(settings))?;
     writeln!(&mut file, "Version: {}", settings.version_string())?;
     writeln!(&mut file, "Architecture: {arch}")?;
     // deb Installed-Size is size in bytes / 1024
@@ -121,12 +182,48 @@ fn generate_control_file(
         .filter(|a| !a.trim().is_empty())
         .unwrap_or_else(|| "Unknown <unknown@localhost>".to_string());
     writeln!(&mut file, "Maintainer: {maintainer}")?;
+    if let Some(section) = settings.deb_section() {
+        writeln!(&mut file, "Section: {section}")?;
+    }
+    let priority = settings.deb_priority();
+    if !PRIORITIES.contains(&priority) {
+        anyhow::bail!("Invalid deb_priority {priority:?}, expected one of {PRIORITIES:?}");
+    }
+    writeln!(&mut file, "Priority: {priority}")?;
     if !settings.homepage_url().is_empty() {
         writeln!(&mut file, "Homepage: {}", settings.homepage_url())?;
     }
-    let dependencies = settings.debian_dependencies();
-    if !dependencies.is_empty() {
-        writeln!(&mut file, "Depends: {}", dependencies.join(", "))?;
+    let mut dependencies = settings.debian_dependencies().to_vec();
+    if settings.deb_auto_depends() {
+        for dependency in shared_library_dependencies(settings, arch)? {
+            let package = relation_package_name(&dependency);
+            if !dependencies
+                .iter()
+                .any(|existing| relation_package_name(existing) == package)
+            {
+                dependencies.push(dependency);
+            }
+        }
+    }
+    let relationships = [
+        ("Pre-Depends", settings.deb_pre_depends()),
+        ("Depends", &dependencies),
+        ("Recommends", settings.deb_recommends()),
+        ("Suggests", settings.deb_suggests()),
+        ("Enhances", settings.deb_enhances()),
+        ("Breaks", settings.deb_breaks()),
+        ("Conflicts", settings.deb_conflicts()),
+        ("Replaces", settings.deb_replaces()),
+        ("Provides", settings.deb_provides()),
+    ];
+    for (field, entries) in relationships {
+        if entries.is_empty() {
+            continue;
+        }
+        for entry in entries {
+            validate_relationship(field, entry)?;
+        }
+        writeln!(&mut file, "{field}: {}", entries.join(", "))?;
     }
     let mut short_description = settings.short_description().trim();
     if short_description.is_empty() {
@@ -149,21 +246,177 @@ fn generate_control_file(
     Ok(())
 }
 
+/// Works out the packages providing the shared libraries that the binary
+/// links against, using the local dpkg database.
+fn shared_library_dependencies(settings: &Settings, arch: &str) -> crate::Result<Vec<String>> {
+    let binary_path = settings.binary_path();
+    let Some(info) = elf::read_dynamic_info(binary_path)? else {
+        common::print_warning(&format!(
+            "{binary_path:?} is not an ELF file; cannot detect its dependencies"
+        ))?;
+        return Ok(Vec::new());
+    };
+    let library_paths = shared_libs::locate_needed(binary_path, &info)?;
+    let admin_dir = shlibdeps::dpkg_admin_dir(settings.deb_dpkg_admin_dir().as_deref());
+    shlibdeps::resolve_dependencies(&info, &library_paths, &admin_dir, arch)
+        .with_context(|| "Failed to detect shared library dependencies")
+}
+
+/// Returns the name of the (first) package in a relationship entry.
+fn relation_package_name(entry: &str) -> &str {
+    let end = entry
+        .find(|c: char| c.is_whitespace() || "(:|".contains(c))
+        .unwrap_or(entry.len());
+    &entry[..end]
+}
+
+/// Checks that `entry` is a valid entry of the given relationship field
+/// (`Depends`, `Provides`, etc.) in a binary package's control file.  See
+/// https://www.debian.org/doc/debian-policy/ch-relationships.html
+fn validate_relationship(field: &str, entry: &str) -> crate::Result<()> {
+    let alternatives: Vec<&str> = entry.split('|').collect();
+    let allows_alternatives =
+        matches!(field, "Pre-Depends" | "Depends" | "Recommends" | "Suggests");
+    if alternatives.len() > 1 && !allows_alternatives {
+        anyhow::bail!("Invalid {field} entry {entry:?}: alternatives are not allowed in {field}");
+    }
+    for relation in alternatives {
+        validate_relation(field, relation.trim())
+            .with_context(|| format!("Invalid {field} entry {entry:?}"))?;
+    }
+    Ok(())
+}
+
+/// Checks a single `name[:arch] [(op version)]` relation.
+fn validate_relation(field: &str, relation: &str) -> crate::Result<()> {
+    let (name, constraint) = match relation.split_once('(') {
+        Some((name, rest)) => {
+            let (constraint, trailing) = rest
+                .split_once(')')
+                .ok_or_else(|| anyhow::anyhow!("missing closing parenthesis"))?;
+            if !trailing.trim().is_empty() {
+                validate_no_restrictions(trailing)?;
+                anyhow::bail!("unexpected {:?} after version constraint", trailing.trim());
+            }
+            (name.trim_end(), Some(constraint.trim()))
+        }
+        None => (relation, None),
+    };
+    validate_no_restrictions(name)?;
+    let (name, arch) = match name.split_once(':') {
+        Some((name, arch)) => (name, Some(arch)),
+        None => (name, None),
+    };
+    let mut chars = name.chars();
+    let name_ok = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.len() >= 2
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+    if !name_ok {
+        anyhow::bail!(
+            "{name:?} is not a valid package name (lowercase letters, digits, '+', '-' and '.')"
+        );
+    }
+    if let Some(arch) = arch
+        && (arch.is_empty()
+            || !arch
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'))
+    {
+        anyhow::bail!("{arch:?} is not a valid architecture qualifier");
+    }
+    if let Some(constraint) = constraint {
+        let operator_len = constraint
+            .find(|c| !"<=>".contains(c))
+            .unwrap_or(constraint.len());
+        let (operator, version) = constraint.split_at(operator_len);
+        if !["<<", "<=", "=", ">=", ">>"].contains(&operator) {
+            anyhow::bail!(
+                "{operator:?} is not a valid version relation (expected <<, <=, =, >= or >>)"
+            );
+        }
+        if field == "Provides" && operator != "=" {
+            anyhow::bail!("only `=` may be used in a versioned Provides");
+        }
+        validate_version(version.trim())?;
+    }
+    Ok(())
+}
+
+/// Architecture restrictions (`[amd64]`) and build profiles (`<!nocheck>`)
+/// are only meaningful in source packages, so reject them here.
+fn validate_no_restrictions(text: &str) -> crate::Result<()> {
+    if text.contains('[') || text.contains('<') {
+        anyhow::bail!(
+            "architecture and build profile restrictions are not allowed in binary packages"
+        );
+    }
+    Ok(())
+}
+
+/// Checks that `version` has the form `[epoch:]upstream_version[-revision]`.
+fn validate_version(version: &str) -> crate::Result<()> {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) => (Some(epoch), rest),
+        None => (None, version),
+    };
+    if let Some(epoch) = epoch
+        && (epoch.is_empty() || !epoch.chars().all(|c| c.is_ascii_digit()))
+    {
+        anyhow::bail!("{version:?} has an invalid epoch");
+    }
+    let (upstream, revision) = match rest.rsplit_once('-') {
+        Some((upstream, revision)) => (upstream, Some(revision)),
+        None => (rest, None),
+    };
+    let upstream_ok = upstream.starts_with(|c: char| c.is_ascii_digit())
+        && upstream.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || ".+~".contains(c)
+                || (c == '-' && revision.is_some())
+                || (c == ':' && epoch.is_some())
+        });
+    if !upstream_ok {
+        anyhow::bail!("{version:?} is not a valid version");
+    }
+    if let Some(revision) = revision
+        && (revision.is_empty()
+            || !revision
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+.~".contains(c)))
+    {
+        anyhow::bail!("{version:?} has an invalid Debian revision");
+    }
+    Ok(())
+}
+
 /// Create an `md5sums` file in the `control_dir` containing the MD5 checksums
-/// for each file within the `data_dir`.
-fn generate_md5sums(control_dir: &Path, data_dir: &Path) -> crate::Result<()> {
+/// for each file within the `data_dir`, except for configuration files (whose
+/// checksums dpkg tracks separately).
+fn generate_md5sums(
+    control_dir: &Path,
+    data_dir: &Path,
+    linux_files: &[PackageFile],
+) -> crate::Result<()> {
     let md5sums_path = control_dir.join("md5sums");
     let mut md5sums_file = common::create_file(&md5sums_path)?;
-    for entry in WalkDir::new(data_dir) {
+    for entry in WalkDir::new(data_dir).sort_by_file_name() {
         let entry = entry?;
         let path = entry.path();
         if path.is_dir() {
             continue;
         }
+        let rel_path = path.strip_prefix(data_dir).unwrap();
+        if linux_files
+            .iter()
+            .any(|file| file.conffile && file.path == rel_path)
+        {
+            continue;
+        }
         for byte in generate_md5sum(path)?.iter() {
             write!(md5sums_file, "{byte:02x}")?;
         }
-        let rel_path = path.strip_prefix(data_dir).unwrap();
         let path_str = rel_path.to_str().ok_or_else(|| {
             let msg = format!("Non-UTF-8 path: {rel_path:?}");
             io::Error::new(io::ErrorKind::InvalidData, msg)
@@ -173,26 +426,585 @@ fn generate_md5sums(control_dir: &Path, data_dir: &Path) -> crate::Result<()> {
     Ok(())
 }
 
-/// Copy the bundle's resource files into an appropriate directory under the
-/// `data_dir`.
-fn transfer_resource_files(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
-    let resource_dir = data_dir.join("usr/lib").join(settings.binary_name());
-    for src in settings.resource_files() {
-        let src = src?;
-        let dest = resource_dir.join(common::resource_relpath(&src));
-        common::copy_file(&src, &dest)
-            .with_context(|| format!("Failed to copy resource file {src:?}"))?;
+/// Create a `conffiles` file in the `control_dir` listing the configuration
+/// files of the package, if there are any.  dpkg preserves local changes to
+/// these files when the package is upgraded or removed.
+fn generate_conffiles(control_dir: &Path, linux_files: &[PackageFile]) -> crate::Result<()> {
+    let mut conffiles: Vec<String> = linux_files
+        .iter()
+        .filter(|file| file.conffile)
+        .map(PackageFile::install_path)
+        .collect();
+    if conffiles.is_empty() {
+        return Ok(());
     }
+    conffiles.sort();
+    conffiles.dedup();
+    let mut contents = conffiles.join("\n");
+    contents.push('\n');
+    create_file_with_data(control_dir.join("conffiles"), &contents)
+}
+
+/// The licenses whose full text Debian ships in /usr/share/common-licenses,
+/// by their short names in machine-readable copyright files.
+const COMMON_LICENSES: &[&str] = &[
+    "Apache-2.0",
+    "Artistic",
+    "CC0-1.0",
+    "GFDL-1.2",
+    "GFDL-1.3",
+    "GPL-1",
+    "GPL-2",
+    "GPL-3",
+    "LGPL-2",
+    "LGPL-2.1",
+    "LGPL-3",
+    "MPL-1.1",
+    "MPL-2.0",
+];
+
+/// Create a machine-readable copyright file (see
+/// https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/) in
+/// the `data_dir`, from the package's license, authors and copyright.
+fn generate_copyright_file(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
+    let dest_path = data_dir
+        .join("usr/share/doc")
+        .join(package_name(settings))
+        .join("copyright");
+    let mut file = common::create_file(&dest_path)?;
+    writeln!(
+        &mut file,
+        "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/"
+    )?;
+    writeln!(&mut file, "Upstream-Name: {}", package_name(settings))?;
+    let authors = settings.author_names();
+    if !authors.is_empty() {
+        writeln!(
+            &mut file,
+            "Upstream-Contact: {}",
+            field_value(&authors.join("\n"))
+        )?;
+    }
+    if !settings.homepage_url().is_empty() {
+        writeln!(&mut file, "Source: {}", settings.homepage_url())?;
+    }
+
+    let copyright = match settings.copyright_string() {
+        Some(copyright) => copyright.to_string(),
+        None if !authors.is_empty() => authors.join("\n"),
+        None => "Unknown".to_string(),
+    };
+    // `license_content` falls back to the license expression itself, which
+    // isn't the text of a license.
+    let license_text = settings
+        .license_content()
+        .filter(|content| Some(content.as_str()) != settings.license());
+    let (expression, licenses) = match settings.license() {
+        Some(spdx) => dep5_license(spdx),
+        None if license_text.is_some() => {
+            ("custom".to_string(), vec![("custom".to_string(), None)])
+        }
+        None => ("unknown".to_string(), Vec::new()),
+    };
+    writeln!(&mut file)?;
+    writeln!(&mut file, "Files: *")?;
+    writeln!(&mut file, "Copyright: {}", field_value(&copyright))?;
+    writeln!(&mut file, "License: {expression}")?;
+
+    // Each license named above gets a standalone paragraph with its text.
+    for (name, text) in license_paragraphs(&licenses, license_text.as_deref())? {
+        writeln!(&mut file)?;
+        writeln!(&mut file, "License: {name}\n {}", field_value(&text))?;
+    }
+    file.flush()?;
     Ok(())
 }
 
+/// Returns the short name and text of each of the `licenses` (short names
+/// and SPDX identifiers) for the copyright file's standalone `License`
+/// paragraphs.  The package's own `license_text` is used for the only
+/// license, or for the only license not found elsewhere; the common licenses
+/// refer to their copies in /usr/share/common-licenses; and the usual
+/// permissive licenses get their full texts.  Debian policy requires the full
+/// text of any other license, so a warning is printed for those.
+fn license_paragraphs(
+    licenses: &[(String, Option<String>)],
+    license_text: Option<&str>,
+) -> crate::Result<Vec<(String, String)>> {
+    let mut texts: Vec<Option<String>> = licenses
+        .iter()
+        .map(|(name, spdx)| match license_text {
+            Some(text) if licenses.len() == 1 => Some(text.to_string()),
+            _ => {
+                let common_name = name.trim_end_matches('+');
+                if COMMON_LICENSES.contains(&common_name) {
+                    Some(format!(
+                        "On Debian systems, the complete text of the {common_name} license can\n\
+                         be found in \"/usr/share/common-licenses/{common_name}\"."
+                    ))
+                } else {
+                    let spdx = spdx.as_deref().unwrap_or(name);
+                    license_texts::license_text(spdx).map(str::to_string)
+                }
+            }
+        })
+        .collect();
+    let mut missing = texts.iter_mut().filter(|text| text.is_none());
+    if let (Some(text), Some(missing_text), None) = (license_text, missing.next(), missing.next()) {
+        *missing_text = Some(text.to_string());
+    }
+    let mut paragraphs = Vec::new();
+    for ((name, spdx), text) in licenses.iter().zip(texts) {
+        let spdx = spdx.as_deref().unwrap_or(name);
+        let text = match text {
+            Some(text) => text,
+            None => {
+                common::print_warning(&format!(
+                    "The full text of the {spdx} license is not known; set `license-file` in \
+                     Cargo.toml so that the copyright file can include it, as Debian policy \
+                     requires"
+                ))?;
+                format!(
+                    "The complete text of this license can be found at\n\
+                     https://spdx.org/licenses/{spdx}.html"
+                )
+            }
+        };
+        paragraphs.push((name.clone(), text));
+    }
+    Ok(paragraphs)
+}
+
+/// Converts an SPDX license expression into the syntax used by
+/// machine-readable copyright files, also returning the short name (and SPDX
+/// identifier) of each license it names.
+fn dep5_license(spdx: &str) -> (String, Vec<(String, Option<String>)>) {
+    let mut words = Vec::new();
+    let mut licenses: Vec<(String, Option<String>)> = Vec::new();
+    // Cargo accepts the legacy `MIT/Apache-2.0` syntax too.
+    let spdx = spdx.replace('/', " OR ");
+    let mut after_with = false;
+    for word in spdx.split_whitespace() {
+        match word {
+            "OR" | "AND" | "WITH" => {
+                after_with = word == "WITH";
+                words.push(word.to_lowercase());
+            }
+            _ if after_with => {
+                after_with = false;
+                words.push(word.to_string());
+            }
+            _ => {
+                let id = word.trim_matches(|c| c == '(' || c == ')');
+                let name = dep5_license_name(id);
+                words.push(word.replace(id, &name));
+                if !licenses.iter().any(|(existing, _)| *existing == name) {
+                    licenses.push((name, Some(id.to_string())));
+                }
+            }
+        }
+    }
+    (words.join(" "), licenses)
+}
+
+/// Converts an SPDX license identifier into the short name used by
+/// machine-readable copyright files, e.g. `GPL-2.0-or-later` into `GPL-2+`.
+fn dep5_license_name(id: &str) -> String {
+    let (base, or_later) = if let Some(base) = id.strip_suffix("-or-later") {
+        (base, true)
+    } else if let Some(base) = id.strip_suffix('+') {
+        (base, true)
+    } else {
+        (id.strip_suffix("-only").unwrap_or(id), false)
+    };
+    let gnu = ["GPL-", "LGPL-", "AGPL-", "GFDL-"]
+        .iter()
+        .any(|prefix| base.starts_with(prefix));
+    let base = match base.strip_suffix(".0") {
+        Some(stripped) if gnu => stripped,
+        _ => base,
+    };
+    if or_later {
+        format!("{base}+")
+    } else {
+        base.to_string()
+    }
+}
+
+/// Formats a (possibly multi-line) value for a control file field: the
+/// continuation lines are indented, with empty lines written as ` .`.
+fn field_value(text: &str) -> String {
+    let mut lines = text.trim_end().lines();
+    let mut value = lines.next().unwrap_or("").trim_end().to_string();
+    for line in lines {
+        let line = line.trim_end();
+        value.push_str("\n ");
+        value.push_str(if line.is_empty() { "." } else { line });
+    }
+    value
+}
+
+/// Create a gzipped changelog in Debian's format in the `data_dir`, converted
+/// from the `deb_changelog` file or, without one, with a single entry for the
+/// current version.
+fn generate_changelog_file(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
+    let version = settings.version_string().to_string();
+    let mut releases = Vec::new();
+    if let Some(changelog) = settings.deb_changelog() {
+        let base_dir = settings
+            .manifest_path()
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        let path = base_dir.join(changelog);
+        let contents =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        releases = parse_changelog(&contents);
+        if !releases.iter().any(|release| release.version == version) {
+            common::print_warning(&format!(
+                "{changelog} has no entry for version {version}, adding a placeholder entry"
+            ))?;
+        }
+    }
+    if !releases.iter().any(|release| release.version == version) {
+        releases.insert(
+            0,
+            Release {
+                version: version.clone(),
+                date: None,
+                changes: Vec::new(),
+            },
+        );
+    }
+
+    let default_date = match common::source_date_epoch()? {
+        Some(timestamp) => chrono::DateTime::from_timestamp(timestamp as i64, 0)
+            .ok_or_else(|| anyhow::anyhow!("SOURCE_DATE_EPOCH is out of range"))?,
+        None => chrono::Utc::now(),
+    };
+    let maintainer = match settings.author_names().first() {
+        Some(author) if author.contains('<') => author.clone(),
+        Some(author) => format!("{author} <unknown@localhost>"),
+        None => "Unknown <unknown@localhost>".to_string(),
+    };
+    let mut changelog = String::new();
+    for release in &releases {
+        changelog.push_str(&format!(
+            "{} ({}) unstable; urgency=medium\n\n",
+            package_name(settings),
+            release.version
+        ));
+        if release.changes.iter().all(|(_, items)| items.is_empty()) {
+            changelog.push_str(&format!("  * Release {}.\n", release.version));
+        }
+        for (kind, items) in &release.changes {
+            for item in items {
+                let item = if kind.is_empty() {
+                    item.clone()
+                } else {
+                    format!("{kind}: {item}")
+                };
+                changelog.push_str(&wrap_changelog_item(&item));
+            }
+        }
+        let date = match release.date {
+            Some(date) => date.and_time(chrono::NaiveTime::MIN).and_utc(),
+            None => default_date,
+        };
+        changelog.push_str(&format!(
+            "\n -- {maintainer}  {}\n\n",
+            date.format("%a, %d %b %Y %H:%M:%S +0000")
+        ));
+    }
+
+    let dest_path = data_dir
+        .join("usr/share/doc")
+        .join(package_name(settings))
+        .join("changelog.Debian.gz");
+    let mut encoder = gzip_encoder(common::create_file(&dest_path)?, DEFAULT_GZIP_LEVEL)?;
+    encoder.write_all(changelog.trim_end().as_bytes())?;
+    encoder.write_all(b"\n")?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// Formats a changelog item as a `  * ` bullet, wrapped to 80 columns.
+fn wrap_changelog_item(item: &str) -> String {
+    const WIDTH: usize = 80;
+    let mut wrapped = String::from("  *");
+    let mut line_len = wrapped.len();
+    for word in item.split_whitespace() {
+        if line_len + 1 + word.len() > WIDTH && line_len > 4 {
+            wrapped.push_str("\n   ");
+            line_len = 3;
+        }
+        wrapped.push(' ');
+        wrapped.push_str(word);
+        line_len += 1 + word.len();
+    }
+    wrapped.push('\n');
+    wrapped
+}
+
+/// Write the `preinst`, `postinst`, `prerm`, `postrm` and `triggers` files
+/// into the `control_dir`, as configured in the bundle settings.
+fn generate_maintainer_scripts(
+    settings: &Settings,
+    systemd_files: &SystemdFiles,
+    control_dir: &Path,
+) -> crate::Result<()> {
+    let refresh_caches = if settings.deb_refresh_caches() {
+        REFRESH_CACHES_SCRIPT
+    } else {
+        ""
+    };
+    let scripts = [
+        ("preinst", settings.deb_preinst(), String::new()),
+        (
+            "postinst",
+            settings.deb_postinst(),
+            systemd_files.deb_postinst() + refresh_caches,
+        ),
+        ("prerm", settings.deb_prerm(), systemd_files.deb_prerm()),
+        (
+            "postrm",
+            settings.deb_postrm(),
+            systemd_files.deb_postrm() + refresh_caches,
+        ),
+    ];
+    let base_dir = settings
+        .manifest_path()
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    for (name, setting, generated) in scripts {
+        let user_script = match setting {
+            Some(script) => Some(
+                read_script_setting(base_dir, script)
+                    .with_context(|| format!("Failed to read {name} script"))?,
+            ),
+            None => None,
+        };
+        if let Some(contents) = assemble_script(user_script.as_deref(), &generated) {
+            let dest_path = control_dir.join(name);
+            create_file_with_data(&dest_path, &contents)?;
+            set_file_mode(&dest_path, 0o755)?;
+        }
+    }
+    if let Some(script) = settings.deb_triggers() {
+        let mut contents = read_script_setting(base_dir, script)
+            .with_context(|| "Failed to read triggers file")?;
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        let dest_path = control_dir.join("triggers");
+        create_file_with_data(&dest_path, &contents)?;
+        set_file_mode(&dest_path, 0o644)?;
+    }
+    Ok(())
+}
+
+/// Returns the contents of a maintainer script or triggers setting, reading
+/// it from its file (relative to `base_dir`) unless it was given inline.
+fn read_script_setting(base_dir: &Path, script: &DebScript) -> crate::Result<String> {
+    match script {
+        DebScript::File(path) => {
+            let path = base_dir.join(path);
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))
+        }
+        DebScript::Inline(contents) => Ok(contents.clone()),
+    }
+}
+
+/// Combines a user-supplied maintainer script with the commands generated by
+/// cargo-bundle.  The generated commands replace the `#DEBHELPER#` token if the
+/// script contains it, and otherwise run before the rest of the script.
+/// Returns `None` if there is nothing to put in the script.
+fn assemble_script(user_script: Option<&str>, generated: &str) -> Option<String> {
+    const PREAMBLE: &str = "#!/bin/sh\nset -e\n";
+    let mut script = match user_script {
+        None if generated.is_empty() => return None,
+        None => format!("{PREAMBLE}{generated}"),
+        Some(script) if script.contains(SCRIPT_TOKEN) => {
+            script.replace(SCRIPT_TOKEN, generated.trim_end())
+        }
+        Some(script) if script.starts_with("#!") => {
+            let (shebang, body) = script.split_once('\n').unwrap_or((script, ""));
+            format!("{shebang}\n{generated}{body}")
+        }
+        Some(script) => format!("{PREAMBLE}{generated}{script}"),
+    };
+    if !script.ends_with('\n') {
+        script.push('\n');
+    }
+    Some(script)
+}
+
 /// Creates an `ar` archive from the given source files and writes it to the
-/// given destination path.
+/// given destination path.  The members are owned by root with mode 0644, and
+/// timestamped with `SOURCE_DATE_EPOCH` (or zero, if it isn't set).
 fn create_archive(srcs: Vec<PathBuf>, dest: &Path) -> crate::Result<()> {
+    let timestamp = common::source_date_epoch()?.unwrap_or(0);
     let mut builder = ar::Builder::new(common::create_file(dest)?);
     for path in &srcs {
-        builder.append_path(path)?;
+        let mut file = File::open(path)?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("{path:?} has no file name"))?;
+        let mut header = ar::Header::new(
+            name.to_string_lossy().into_owned().into_bytes(),
+            file.metadata()?.len(),
+        );
+        header.set_mtime(timestamp);
+        header.set_mode(0o100644);
+        builder.append(&header, &mut file)?;
     }
     builder.into_inner()?.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dep5_licenses() {
+        let (expression, licenses) = dep5_license("MIT OR Apache-2.0");
+        assert_eq!(expression, "MIT or Apache-2.0");
+        assert_eq!(licenses.len(), 2);
+        let (expression, licenses) =
+            dep5_license("(GPL-2.0-or-later WITH Classpath-exception-2.0) AND LGPL-2.1-only");
+        assert_eq!(
+            expression,
+            "(GPL-2+ with Classpath-exception-2.0) and LGPL-2.1"
+        );
+        assert_eq!(
+            licenses[0],
+            ("GPL-2+".to_string(), Some("GPL-2.0-or-later".to_string()))
+        );
+        assert_eq!(dep5_license("MIT/Apache-2.0").0, "MIT or Apache-2.0");
+        assert_eq!(dep5_license_name("GPL-3.0"), "GPL-3");
+        assert_eq!(dep5_license_name("MPL-2.0"), "MPL-2.0");
+    }
+
+    #[test]
+    fn license_paragraph_texts() {
+        let (_, licenses) = dep5_license("MIT OR Apache-2.0");
+        let paragraphs = license_paragraphs(&licenses, None).unwrap();
+        assert_eq!(paragraphs[0].0, "MIT");
+        assert!(paragraphs[0].1.starts_with("Permission is hereby granted"));
+        assert_eq!(paragraphs[1].0, "Apache-2.0");
+        assert!(
+            paragraphs[1]
+                .1
+                .contains("/usr/share/common-licenses/Apache-2.0")
+        );
+
+        // The package's license file covers the only license without a text.
+        let (_, licenses) = dep5_license("BSD-3-Clause AND LicenseRef-Foo");
+        let paragraphs = license_paragraphs(&licenses, Some("Foo license text")).unwrap();
+        assert!(
+            paragraphs[0]
+                .1
+                .contains("Neither the name of the copyright holder")
+        );
+        assert_eq!(paragraphs[1].1, "Foo license text");
+
+        let (_, licenses) = dep5_license("LicenseRef-Foo OR LicenseRef-Bar");
+        let paragraphs = license_paragraphs(&licenses, None).unwrap();
+        assert!(
+            paragraphs[0]
+                .1
+                .ends_with("https://spdx.org/licenses/LicenseRef-Foo.html")
+        );
+    }
+
+    #[test]
+    fn changelog_items_are_wrapped() {
+        assert_eq!(wrap_changelog_item("Fixed: a bug."), "  * Fixed: a bug.\n");
+        let wrapped = wrap_changelog_item(&"word ".repeat(30));
+        assert!(wrapped.lines().all(|line| line.len() <= 80));
+        assert!(wrapped.lines().nth(1).unwrap().starts_with("    word"));
+    }
+
+    #[test]
+    fn valid_relationships() {
+        for entry in [
+            "libc6",
+            "libc6 (>= 2.17)",
+            "libsdl2-2.0-0 (>= 2.0.5)",
+            "libgl1-mesa-glx | libgl1",
+            "python3:any (>= 3.6~)",
+            "foo (= 1:2.0-1ubuntu1)",
+            "libstdc++6 (<< 13)",
+        ] {
+            assert!(validate_relationship("Depends", entry).is_ok(), "{entry}");
+        }
+        assert!(validate_relationship("Provides", "foo-api (= 1.0)").is_ok());
+    }
+
+    #[test]
+    fn invalid_relationships() {
+        for entry in [
+            "",
+            "Foo",
+            "f",
+            "foo (>= )",
+            "foo (> 1.0)",
+            "foo >= 1.0",
+            "foo (>= 1.0",
+            "foo (>= 1.0) [amd64]",
+            "foo <!nocheck>",
+            "foo (>= a1.0)",
+            "foo (>= 1.0-)",
+            "foo:",
+        ] {
+            assert!(validate_relationship("Depends", entry).is_err(), "{entry}");
+        }
+        assert!(validate_relationship("Conflicts", "foo | bar").is_err());
+        assert!(validate_relationship("Provides", "foo (>= 1.0)").is_err());
+    }
+
+    #[test]
+    fn script_settings_are_files_or_inline() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(temp_dir.path().join("postinst"), "#!/bin/sh\nldconfig\n").unwrap();
+        let file = DebScript::File(PathBuf::from("postinst"));
+        assert_eq!(
+            read_script_setting(temp_dir.path(), &file).unwrap(),
+            "#!/bin/sh\nldconfig\n"
+        );
+        // A one-word inline body isn't mistaken for a path...
+        let inline = DebScript::Inline("ldconfig".to_string());
+        assert_eq!(
+            read_script_setting(temp_dir.path(), &inline).unwrap(),
+            "ldconfig"
+        );
+        // ...and a mistyped path isn't mistaken for a body.
+        let missing = DebScript::File(PathBuf::from("debian/post inst"));
+        assert!(read_script_setting(temp_dir.path(), &missing).is_err());
+    }
+
+    #[test]
+    fn assemble_script_without_user_script() {
+        assert_eq!(assemble_script(None, ""), None);
+        assert_eq!(
+            assemble_script(None, "true\n").as_deref(),
+            Some("#!/bin/sh\nset -e\ntrue\n")
+        );
+    }
+
+    #[test]
+    fn assemble_script_with_user_script() {
+        assert_eq!(
+            assemble_script(Some("ldconfig"), "").as_deref(),
+            Some("#!/bin/sh\nset -e\nldconfig\n")
+        );
+        assert_eq!(
+            assemble_script(Some("#!/bin/bash\nldconfig\nexit 0\n"), "true\n").as_deref(),
+            Some("#!/bin/bash\ntrue\nldconfig\nexit 0\n")
+        );
+        assert_eq!(
+            assemble_script(Some("#!/bin/sh\nldconfig\n#DEBHELPER#\nexit 0"), "true\n").as_deref(),
+            Some("#!/bin/sh\nldconfig\ntrue\nexit 0\n")
+        );
+    }
+}
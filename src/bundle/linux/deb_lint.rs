@@ -0,0 +1,379 @@
+// Checks built .deb packages for common violations of Debian policy (see
+// https://www.debian.org/doc/debian-policy/), in the spirit of lintian.  Only
+// the problems that cargo-bundle's own settings can cause are checked for.
+
+use super::deb_reader::{DataEntry, DebPackage, parse_fields};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// The maximum length of the lines of a package's description.
+const MAX_DESCRIPTION_LINE_LENGTH: usize = 80;
+
+/// How serious a lint finding is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The package violates policy, and may be rejected or misbehave.
+    Error,
+    /// The package is probably not what was intended.
+    Warning,
+    /// A minor issue of style.
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        })
+    }
+}
+
+/// A problem found in a package.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub severity: Severity,
+    /// A short identifier of the check that failed, e.g. `no-copyright-file`.
+    pub tag: &'static str,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(severity: Severity, tag: &'static str, message: String) -> Finding {
+        Finding {
+            severity,
+            tag,
+            message,
+        }
+    }
+}
+
+/// Checks the `.deb` package at `path`, returning any problems found, most
+/// severe first.
+pub fn lint_deb(path: &Path) -> crate::Result<Vec<Finding>> {
+    let package = DebPackage::open(path)?;
+    let fields: BTreeMap<String, String> = parse_fields(&package.control()?)
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .collect();
+    let entries = package.data_entries()?;
+    let md5sums = package.control_file("md5sums")?;
+    let conffiles = package.control_file("conffiles")?.unwrap_or_default();
+
+    let mut findings = Vec::new();
+    let name = fields.get("package").map(String::as_str).unwrap_or("");
+    check_package_name(name, &mut findings);
+    check_maintainer(fields.get("maintainer").map(String::as_str), &mut findings);
+    check_description(fields.get("description").map(String::as_str), &mut findings);
+    check_copyright_file(name, &entries, &mut findings);
+    check_ownership(&entries, &mut findings);
+    check_permissions(&entries, &mut findings);
+    check_md5sums(md5sums.as_deref(), &conffiles, &entries, &mut findings);
+    findings.sort_by_key(|finding| finding.severity);
+    Ok(findings)
+}
+
+/// Package names must consist of lowercase letters, digits and `+-.`, be at
+/// least two characters long and start with an alphanumeric character.
+fn check_package_name(name: &str, findings: &mut Vec<Finding>) {
+    let valid = name.len() >= 2
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+    if !valid {
+        findings.push(Finding::new(
+            Severity::Error,
+            "invalid-package-name",
+            format!(
+                "{name:?} is not a valid package name (only lowercase letters, digits and `+-.` are allowed)"
+            ),
+        ));
+    }
+}
+
+fn check_maintainer(maintainer: Option<&str>, findings: &mut Vec<Finding>) {
+    let Some(maintainer) = maintainer.filter(|maintainer| !maintainer.is_empty()) else {
+        findings.push(Finding::new(
+            Severity::Error,
+            "no-maintainer-field",
+            "the control file has no Maintainer field".to_string(),
+        ));
+        return;
+    };
+    let address = maintainer
+        .split_once('<')
+        .and_then(|(_, rest)| rest.strip_suffix('>'))
+        .filter(|address| address.contains('@'));
+    match address {
+        None => findings.push(Finding::new(
+            Severity::Error,
+            "malformed-maintainer-field",
+            format!("{maintainer:?} should have the form `Full Name <email@example.com>`"),
+        )),
+        Some(address) if address.ends_with("@localhost") || maintainer.starts_with("Unknown ") => {
+            findings.push(Finding::new(
+                Severity::Warning,
+                "placeholder-maintainer",
+                format!(
+                    "the maintainer {maintainer:?} is a placeholder; set `authors` in Cargo.toml"
+                ),
+            ))
+        }
+        Some(_) => {}
+    }
+}
+
+fn check_description(description: Option<&str>, findings: &mut Vec<Finding>) {
+    let Some(description) = description else {
+        findings.push(Finding::new(
+            Severity::Error,
+            "no-description",
+            "the control file has no Description field".to_string(),
+        ));
+        return;
+    };
+    let mut lines = description.lines();
+    let synopsis = lines.next().unwrap_or("").trim();
+    if synopsis.is_empty() || synopsis == "(none)" {
+        findings.push(Finding::new(
+            Severity::Warning,
+            "placeholder-description",
+            "the package has no description; set `description` in Cargo.toml".to_string(),
+        ));
+    } else if synopsis.len() > MAX_DESCRIPTION_LINE_LENGTH {
+        findings.push(Finding::new(
+            Severity::Warning,
+            "description-synopsis-too-long",
+            format!(
+                "the first line of the description is {} characters long (the maximum is {MAX_DESCRIPTION_LINE_LENGTH})",
+                synopsis.len()
+            ),
+        ));
+    }
+    for (index, line) in lines.enumerate() {
+        if line.trim() == "(none)" {
+            findings.push(Finding::new(
+                Severity::Info,
+                "placeholder-extended-description",
+                "the package has no long description; set `long_description`".to_string(),
+            ));
+        } else if line.len() > MAX_DESCRIPTION_LINE_LENGTH {
+            findings.push(Finding::new(
+                Severity::Info,
+                "description-line-too-long",
+                format!(
+                    "line {} of the extended description is {} characters long (the maximum is {MAX_DESCRIPTION_LINE_LENGTH})",
+                    index + 1,
+                    line.len()
+                ),
+            ));
+        }
+    }
+}
+
+fn check_copyright_file(name: &str, entries: &[DataEntry], findings: &mut Vec<Finding>) {
+    let copyright_path = format!("usr/share/doc/{name}/copyright");
+    if !entries.iter().any(|entry| entry.path == copyright_path) {
+        findings.push(Finding::new(
+            Severity::Error,
+            "no-copyright-file",
+            format!("/{copyright_path} is missing"),
+        ));
+    }
+}
+
+fn check_ownership(entries: &[DataEntry], findings: &mut Vec<Finding>) {
+    for entry in entries {
+        let is_root = |name: &Option<String>| {
+            name.as_deref()
+                .is_none_or(|name| name.is_empty() || name == "root")
+        };
+        if entry.uid != 0
+            || entry.gid != 0
+            || !is_root(&entry.username)
+            || !is_root(&entry.groupname)
+        {
+            findings.push(Finding::new(
+                Severity::Error,
+                "wrong-file-owner",
+                format!(
+                    "/{} is owned by {}/{} ({}/{}) instead of root/root",
+                    entry.path,
+                    entry.username.as_deref().unwrap_or("?"),
+                    entry.groupname.as_deref().unwrap_or("?"),
+                    entry.uid,
+                    entry.gid
+                ),
+            ));
+        }
+    }
+}
+
+fn check_permissions(entries: &[DataEntry], findings: &mut Vec<Finding>) {
+    for entry in entries {
+        let is_symlink = entry.entry_type.is_symlink();
+        // World-writable directories are fine if they are sticky, like /tmp.
+        let is_sticky_dir = entry.entry_type.is_dir() && entry.mode & 0o1000 != 0;
+        if entry.mode & 0o002 != 0 && !is_symlink && !is_sticky_dir {
+            findings.push(Finding::new(
+                Severity::Error,
+                "world-writable-file",
+                format!(
+                    "/{} is world-writable (mode {:04o})",
+                    entry.path,
+                    entry.mode & 0o7777
+                ),
+            ));
+        }
+    }
+}
+
+fn check_md5sums(
+    md5sums: Option<&str>,
+    conffiles: &str,
+    entries: &[DataEntry],
+    findings: &mut Vec<Finding>,
+) {
+    let Some(md5sums) = md5sums else {
+        findings.push(Finding::new(
+            Severity::Warning,
+            "no-md5sums-control-file",
+            "the package has no md5sums file".to_string(),
+        ));
+        return;
+    };
+    let sums: BTreeMap<&str, &str> = md5sums
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(sum, path)| (path.trim_start_matches('/'), sum))
+        .collect();
+    for entry in entries {
+        let Some(md5) = &entry.md5 else {
+            continue;
+        };
+        let is_conffile = conffiles
+            .lines()
+            .any(|conffile| conffile.trim().trim_start_matches('/') == entry.path);
+        match sums.get(entry.path.as_str()) {
+            Some(sum) if sum != md5 => findings.push(Finding::new(
+                Severity::Error,
+                "md5sum-mismatch",
+                format!(
+                    "the md5sums entry for /{} doesn't match its contents",
+                    entry.path
+                ),
+            )),
+            None if !is_conffile => findings.push(Finding::new(
+                Severity::Warning,
+                "file-missing-in-md5sums",
+                format!("/{} is not listed in the md5sums file", entry.path),
+            )),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(findings: &[Finding]) -> Vec<&'static str> {
+        findings.iter().map(|finding| finding.tag).collect()
+    }
+
+    fn entry(path: &str, mode: u32, md5: Option<&str>) -> DataEntry {
+        DataEntry {
+            path: path.to_string(),
+            entry_type: if md5.is_some() {
+                tar::EntryType::Regular
+            } else {
+                tar::EntryType::Directory
+            },
+            mode,
+            uid: 0,
+            gid: 0,
+            username: Some("root".to_string()),
+            groupname: Some("root".to_string()),
+            md5: md5.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn control_fields() {
+        let mut findings = Vec::new();
+        check_package_name("hello", &mut findings);
+        check_package_name("libfoo2.0+dfsg", &mut findings);
+        check_maintainer(Some("Jane Doe <jane@example.com>"), &mut findings);
+        check_description(Some("A greeting\n A longer description."), &mut findings);
+        assert!(findings.is_empty(), "{findings:?}");
+
+        check_package_name("My_App", &mut findings);
+        check_maintainer(Some("Unknown <unknown@localhost>"), &mut findings);
+        check_maintainer(Some("Jane Doe"), &mut findings);
+        check_description(Some(&format!("(none)\n {}", "x".repeat(81))), &mut findings);
+        assert_eq!(
+            tags(&findings),
+            [
+                "invalid-package-name",
+                "placeholder-maintainer",
+                "malformed-maintainer-field",
+                "placeholder-description",
+                "description-line-too-long",
+            ]
+        );
+    }
+
+    #[test]
+    fn data_files() {
+        let mut root_entry = entry("usr/share/doc/hello/copyright", 0o644, Some("abc"));
+        let mut findings = Vec::new();
+        check_copyright_file("hello", std::slice::from_ref(&root_entry), &mut findings);
+        check_ownership(std::slice::from_ref(&root_entry), &mut findings);
+        check_permissions(
+            &[entry("var/tmp/hello", 0o1777, None), root_entry.clone()],
+            &mut findings,
+        );
+        assert!(findings.is_empty(), "{findings:?}");
+
+        root_entry.uid = 1000;
+        root_entry.mode = 0o666;
+        check_copyright_file("other", std::slice::from_ref(&root_entry), &mut findings);
+        check_ownership(std::slice::from_ref(&root_entry), &mut findings);
+        check_permissions(std::slice::from_ref(&root_entry), &mut findings);
+        assert_eq!(
+            tags(&findings),
+            [
+                "no-copyright-file",
+                "wrong-file-owner",
+                "world-writable-file"
+            ]
+        );
+    }
+
+    #[test]
+    fn md5sums() {
+        let entries = [
+            entry("usr/bin/hello", 0o755, Some("abc")),
+            entry("usr/share/hello/data", 0o644, Some("def")),
+            entry("etc/hello.conf", 0o644, Some("123")),
+            entry("usr/bin", 0o755, None),
+        ];
+        let mut findings = Vec::new();
+        check_md5sums(
+            Some("abc  usr/bin/hello\n000  usr/share/hello/data\n"),
+            "/etc/hello.conf\n",
+            &entries,
+            &mut findings,
+        );
+        assert_eq!(tags(&findings), ["md5sum-mismatch"]);
+        findings.clear();
+        check_md5sums(Some(""), "", &entries, &mut findings);
+        assert_eq!(findings.len(), 3);
+        findings.clear();
+        check_md5sums(None, "", &entries, &mut findings);
+        assert_eq!(tags(&findings), ["no-md5sums-control-file"]);
+    }
+}
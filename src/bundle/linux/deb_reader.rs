@@ -0,0 +1,167 @@
+// Reads back the contents of a .deb package (see deb_bundle.rs for the
+// layout).  This is used to index built packages into an APT repository and
+// to check them for policy violations.
+
+use anyhow::Context;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A file (or directory, symlink, etc.) in a package's `data.tar`.
+#[derive(Clone, Debug)]
+pub struct DataEntry {
+    /// The path of the entry, relative to the root directory.
+    pub path: String,
+    pub entry_type: tar::EntryType,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub username: Option<String>,
+    pub groupname: Option<String>,
+    /// The MD5 checksum of the contents of regular files.
+    pub md5: Option<String>,
+}
+
+/// The members of a .deb archive.
+pub struct DebPackage {
+    members: Vec<(String, Vec<u8>)>,
+}
+
+impl DebPackage {
+    /// Reads the `.deb` file at `path`.
+    pub fn open(path: &Path) -> crate::Result<DebPackage> {
+        let file = File::open(path).with_context(|| format!("Failed to open {path:?}"))?;
+        let mut archive = ar::Archive::new(file);
+        let mut members = Vec::new();
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry.with_context(|| format!("{path:?} is not a valid ar archive"))?;
+            let name = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            members.push((name, data));
+        }
+        match members.first() {
+            Some((name, data)) if name == "debian-binary" && data.starts_with(b"2.") => {}
+            _ => anyhow::bail!("{path:?} is not a Debian binary package"),
+        }
+        Ok(DebPackage { members })
+    }
+
+    /// Returns the contents of the package's `control` file.
+    pub fn control(&self) -> crate::Result<String> {
+        self.control_file("control")?
+            .ok_or_else(|| anyhow::anyhow!("Package has no control file"))
+    }
+
+    /// Returns the contents of the file `name` in the package's
+    /// `control.tar`, if it exists.
+    pub fn control_file(&self, name: &str) -> crate::Result<Option<String>> {
+        let mut archive = tar::Archive::new(self.decompressed_member("control.tar")?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if path.strip_prefix(".").unwrap_or(&path) == Path::new(name) {
+                let mut contents = String::new();
+                entry
+                    .read_to_string(&mut contents)
+                    .with_context(|| format!("Package {name} file is not valid UTF-8"))?;
+                return Ok(Some(contents));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Lists the entries of the package's `data.tar`, in archive order.
+    pub fn data_entries(&self) -> crate::Result<Vec<DataEntry>> {
+        let mut archive = tar::Archive::new(self.decompressed_member("data.tar")?);
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let path = path
+                .trim_start_matches("./")
+                .trim_end_matches('/')
+                .to_string();
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            let mut data_entry = DataEntry {
+                path,
+                entry_type,
+                mode: header.mode()?,
+                uid: header.uid()?,
+                gid: header.gid()?,
+                username: header.username().ok().flatten().map(str::to_string),
+                groupname: header.groupname().ok().flatten().map(str::to_string),
+                md5: None,
+            };
+            if entry_type.is_file() {
+                let mut hash = md5::Context::new();
+                io::copy(&mut entry, &mut hash)?;
+                data_entry.md5 = Some(format!("{:x}", hash.finalize()));
+            }
+            if !data_entry.path.is_empty() && data_entry.path != "." {
+                entries.push(data_entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns a reader over the decompressed contents of the `{prefix}`,
+    /// `{prefix}.gz`, `{prefix}.xz` or `{prefix}.zst` member.
+    fn decompressed_member(&self, prefix: &str) -> crate::Result<Box<dyn Read + '_>> {
+        for (name, data) in &self.members {
+            let Some(extension) = name.strip_prefix(prefix) else {
+                continue;
+            };
+            let data = data.as_slice();
+            return Ok(match extension {
+                "" => Box::new(data),
+                ".gz" => Box::new(flate2::read::GzDecoder::new(data)),
+                ".xz" => Box::new(xz2::read::XzDecoder::new(data)),
+                ".zst" => Box::new(zstd::Decoder::new(data)?),
+                _ => anyhow::bail!("Unsupported compression for {name}"),
+            });
+        }
+        anyhow::bail!("Package has no {prefix} member")
+    }
+}
+
+/// Parses the fields of a single deb822 stanza (such as a control file),
+/// in order.  Continuation lines are kept in the field's value.
+pub fn parse_fields(stanza: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in stanza.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push('\n');
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_control_fields() {
+        let fields = parse_fields(
+            "Package: foo\nVersion: 1.0\nDescription: A foo\n Longer text.\n .\n More.\n",
+        );
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0], ("Package".to_string(), "foo".to_string()));
+        assert_eq!(fields[2].1, "A foo\n Longer text.\n .\n More.");
+    }
+
+    #[test]
+    fn rejects_non_deb_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("foo.deb");
+        std::fs::write(&path, "not a package").unwrap();
+        assert!(DebPackage::open(&path).is_err());
+    }
+}
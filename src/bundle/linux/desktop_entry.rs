@@ -0,0 +1,549 @@
+// Helpers for writing and checking .desktop files, following the Desktop
+// Entry Specification (https://specifications.freedesktop.org/desktop-entry-spec/latest/).
+// Values are escaped as the specification requires (with a second level of
+// quoting for the arguments of `Exec` keys), and the generated file is checked
+// the way desktop-file-validate would check it: groups and keys, value types,
+// locales, escape sequences, `Exec` field codes and actions.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The keys of the `[Desktop Entry]` group, for version 1.5 of the
+/// specification.
+const KNOWN_KEYS: &[&str] = &[
+    "Type",
+    "Version",
+    "Name",
+    "GenericName",
+    "NoDisplay",
+    "Comment",
+    "Icon",
+    "Hidden",
+    "OnlyShowIn",
+    "NotShowIn",
+    "DBusActivatable",
+    "TryExec",
+    "Exec",
+    "Path",
+    "Terminal",
+    "Actions",
+    "MimeType",
+    "Categories",
+    "Implements",
+    "Keywords",
+    "StartupNotify",
+    "StartupWMClass",
+    "URL",
+    "PrefersNonDefaultGPU",
+    "SingleMainWindow",
+];
+
+/// Keys from older versions of the specification.
+const DEPRECATED_KEYS: &[&str] = &[
+    "Encoding",
+    "MiniIcon",
+    "TerminalOptions",
+    "Protocols",
+    "Extensions",
+    "BinaryPattern",
+    "MapNotify",
+    "SwallowTitle",
+    "SwallowExec",
+    "SortOrder",
+    "FilePattern",
+];
+
+const BOOLEAN_KEYS: &[&str] = &[
+    "NoDisplay",
+    "Hidden",
+    "DBusActivatable",
+    "Terminal",
+    "StartupNotify",
+    "PrefersNonDefaultGPU",
+    "SingleMainWindow",
+];
+
+const LOCALIZED_KEYS: &[&str] = &["Name", "GenericName", "Comment", "Icon", "Keywords"];
+
+const LIST_KEYS: &[&str] = &[
+    "OnlyShowIn",
+    "NotShowIn",
+    "Actions",
+    "MimeType",
+    "Categories",
+    "Implements",
+    "Keywords",
+];
+
+/// The characters that must be quoted in an argument of an `Exec` key.
+const EXEC_RESERVED: &[char] = &[
+    ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '`', '(',
+    ')',
+];
+
+/// Escapes a string value: backslashes and control characters are written as
+/// escape sequences, as are leading spaces (which would otherwise be
+/// trimmed).
+pub fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut leading = true;
+    for c in value.chars() {
+        match c {
+            ' ' if leading => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+        leading &= c == ' ';
+    }
+    escaped
+}
+
+/// Escapes a list of strings, terminating each with a `;`.
+pub fn escape_list<'a, I>(items: I) -> String
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    items
+        .into_iter()
+        .map(|item| format!("{};", escape_string(item).replace(';', "\\;")))
+        .collect()
+}
+
+/// Quotes a literal argument (e.g. the program name) of an `Exec` key, if it
+/// contains reserved characters.  The result is a string value, which must
+/// still be escaped with [`escape_string`].
+pub fn quote_exec_arg(arg: &str) -> Cow<'_, str> {
+    let arg = if arg.contains('%') {
+        Cow::Owned(arg.replace('%', "%%"))
+    } else {
+        Cow::Borrowed(arg)
+    };
+    if !arg.contains(EXEC_RESERVED) {
+        return arg;
+    }
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// A group of a desktop file, with its keys (including any `[locale]`
+/// suffix) in the order they appear.
+struct Group<'a> {
+    name: &'a str,
+    line: usize,
+    entries: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Group<'a> {
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Checks a desktop file, returning warnings about things that are allowed
+/// but discouraged, or an error listing the problems that make the file
+/// invalid.
+pub fn validate_desktop_entry(contents: &str) -> crate::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let mut groups: Vec<Group> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            match header.strip_suffix(']') {
+                Some(name) if !name.contains(['[', ']']) && !name.contains(char::is_control) => {
+                    if groups.iter().any(|group| group.name == name) {
+                        errors.push(format!("line {line_number}: duplicate group [{name}]"));
+                    }
+                    groups.push(Group {
+                        name,
+                        line: line_number,
+                        entries: Vec::new(),
+                    });
+                }
+                _ => errors.push(format!("line {line_number}: invalid group header {line:?}")),
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(format!(
+                "line {line_number}: {line:?} is not a key=value pair"
+            ));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let Some(group) = groups.last_mut() else {
+            errors.push(format!("line {line_number}: key {key:?} is not in a group"));
+            continue;
+        };
+        if let Err(error) = check_key_name(key) {
+            errors.push(format!("line {line_number}: key {key:?} {error}"));
+        }
+        if let Err(error) = check_escapes(value) {
+            errors.push(format!("line {line_number}: value of {key:?} {error}"));
+        }
+        if group.get(key).is_some() {
+            errors.push(format!(
+                "line {line_number}: duplicate key {key:?} in group [{}]",
+                group.name
+            ));
+        }
+        group.entries.push((key, value));
+    }
+
+    match groups.first() {
+        Some(entry) if entry.name == "Desktop Entry" => {
+            check_main_group(entry, &groups, &mut errors, &mut warnings)
+        }
+        _ => errors.push("the first group is not [Desktop Entry]".to_string()),
+    }
+    for group in groups.iter().skip(1) {
+        if let Some(action) = group.name.strip_prefix("Desktop Action ") {
+            check_action_group(action, group, &mut errors, &mut warnings);
+        } else if !group.name.starts_with("X-") {
+            errors.push(format!(
+                "line {}: unknown group [{}] (custom groups must start with \"X-\")",
+                group.line, group.name
+            ));
+        }
+    }
+
+    if !errors.is_empty() {
+        anyhow::bail!("Invalid desktop file: {}", errors.join("; "));
+    }
+    Ok(warnings)
+}
+
+fn check_main_group(
+    entry: &Group,
+    groups: &[Group],
+    errors: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    for &(key, value) in &entry.entries {
+        let (base_key, locale) = split_locale(key);
+        if base_key.starts_with("X-") {
+            continue;
+        }
+        if DEPRECATED_KEYS.contains(&base_key) {
+            warnings.push(format!("the key {base_key:?} is deprecated"));
+            continue;
+        }
+        if !KNOWN_KEYS.contains(&base_key) {
+            errors.push(format!(
+                "unknown key {base_key:?} (custom keys must start with \"X-\")"
+            ));
+            continue;
+        }
+        if locale.is_some() && !LOCALIZED_KEYS.contains(&base_key) {
+            errors.push(format!("the key {base_key:?} cannot be localized"));
+        }
+        if BOOLEAN_KEYS.contains(&base_key) && value != "true" && value != "false" {
+            errors.push(format!(
+                "the value of {key:?} is {value:?}, not \"true\" or \"false\""
+            ));
+        }
+        if LIST_KEYS.contains(&base_key) {
+            if value.is_empty() {
+                warnings.push(format!("the value of {key:?} is an empty list"));
+            } else if !value.ends_with(';') {
+                warnings.push(format!("the list value of {key:?} should end with ';'"));
+            }
+        }
+    }
+
+    let kind = entry.get("Type");
+    match kind {
+        None => errors.push("required key \"Type\" is missing".to_string()),
+        Some("Application" | "Link" | "Directory") => {}
+        Some(kind) => errors.push(format!("unknown type {kind:?}")),
+    }
+    let name = entry.get("Name");
+    if name.is_none() {
+        errors.push("required key \"Name\" is missing".to_string());
+    }
+    let dbus_activatable = entry.get("DBusActivatable") == Some("true");
+    if kind == Some("Application") && !dbus_activatable && entry.get("Exec").is_none() {
+        errors.push("required key \"Exec\" is missing".to_string());
+    }
+    if kind == Some("Link") && entry.get("URL").is_none() {
+        errors.push("required key \"URL\" is missing".to_string());
+    }
+    if let Some(exec) = entry.get("Exec") {
+        check_exec(exec, "Exec", errors, warnings);
+    }
+    for key in ["Comment", "GenericName"] {
+        if name.is_some() && entry.get(key) == name {
+            warnings.push(format!("the value of {key:?} is the same as \"Name\""));
+        }
+    }
+    for mime_type in list_items(entry.get("MimeType").unwrap_or("")) {
+        let valid = mime_type
+            .split_once('/')
+            .is_some_and(|(kind, subtype)| !kind.is_empty() && !subtype.is_empty());
+        if !valid {
+            errors.push(format!("{mime_type:?} is not a MIME type"));
+        }
+    }
+
+    let actions: BTreeSet<&str> = list_items(entry.get("Actions").unwrap_or("")).collect();
+    let action_groups: BTreeSet<&str> = groups
+        .iter()
+        .filter_map(|group| group.name.strip_prefix("Desktop Action "))
+        .collect();
+    for action in actions.difference(&action_groups) {
+        errors.push(format!(
+            "the action {action:?} has no [Desktop Action {action}] group"
+        ));
+    }
+    for action in action_groups.difference(&actions) {
+        errors.push(format!(
+            "the group [Desktop Action {action}] is not listed in \"Actions\""
+        ));
+    }
+}
+
+fn check_action_group(
+    action: &str,
+    group: &Group,
+    errors: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) {
+    for &(key, _) in &group.entries {
+        let (base_key, locale) = split_locale(key);
+        if base_key.starts_with("X-") {
+            continue;
+        }
+        if !matches!(base_key, "Name" | "Icon" | "Exec") {
+            errors.push(format!(
+                "unknown key {base_key:?} in the group [Desktop Action {action}]"
+            ));
+        } else if locale.is_some() && base_key == "Exec" {
+            errors.push(format!(
+                "the key \"Exec\" of action {action:?} cannot be localized"
+            ));
+        }
+    }
+    if group.get("Name").is_none() {
+        errors.push(format!("the action {action:?} has no \"Name\""));
+    }
+    if let Some(exec) = group.get("Exec") {
+        check_exec(
+            exec,
+            &format!("Exec of action {action:?}"),
+            errors,
+            warnings,
+        );
+    }
+}
+
+/// Checks the field codes (`%f`, etc.) of an `Exec` value.
+fn check_exec(exec: &str, what: &str, errors: &mut Vec<String>, warnings: &mut Vec<String>) {
+    if exec.is_empty() {
+        errors.push(format!("the {what} is empty"));
+        return;
+    }
+    let mut file_codes = 0;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        match chars.next() {
+            Some('f' | 'F' | 'u' | 'U') => file_codes += 1,
+            Some('i' | 'c' | 'k' | '%') => {}
+            Some(code @ ('d' | 'D' | 'n' | 'N' | 'v' | 'm')) => warnings.push(format!(
+                "the field code %{code} in the {what} is deprecated"
+            )),
+            Some(code) => errors.push(format!("invalid field code %{code} in the {what}")),
+            None => errors.push(format!("the {what} ends with an incomplete field code")),
+        }
+    }
+    if file_codes > 1 {
+        errors.push(format!(
+            "the {what} has more than one of the field codes %f, %F, %u and %U"
+        ));
+    }
+}
+
+/// Checks that a key is made of `A-Za-z0-9-`, optionally followed by a
+/// `[locale]` of the form `lang_COUNTRY.ENCODING@MODIFIER`.
+fn check_key_name(key: &str) -> Result<(), &'static str> {
+    let (base_key, locale) = split_locale(key);
+    if base_key.is_empty()
+        || !base_key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err("may only contain A-Z, a-z, 0-9 and '-'");
+    }
+    let Some(locale) = locale else {
+        return Ok(());
+    };
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    let locale = locale.split_once('.').map_or(locale, |(locale, _)| locale);
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+    let is_alpha = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphabetic());
+    if !is_alpha(lang) || !country.is_none_or(is_alpha) || modifier.is_some_and(str::is_empty) {
+        return Err("has an invalid locale");
+    }
+    Ok(())
+}
+
+/// Splits a key into its name and its locale, if it has one.
+fn split_locale(key: &str) -> (&str, Option<&str>) {
+    match key.split_once('[') {
+        Some((name, locale)) => (name, Some(locale.strip_suffix(']').unwrap_or(locale))),
+        None => (key, None),
+    }
+}
+
+/// Checks that a value only uses the escape sequences of the specification,
+/// and has no control characters.
+fn check_escapes(value: &str) -> Result<(), String> {
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('s' | 'n' | 't' | 'r' | '\\' | ';') => {}
+                Some(c) => return Err(format!("has an invalid escape sequence \\{c}")),
+                None => return Err("ends with a lone backslash".to_string()),
+            }
+        } else if c.is_control() {
+            return Err(format!("contains the control character {c:?}"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the items of a list value (which are left escaped).
+fn list_items(value: &str) -> impl Iterator<Item = &str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ';' => {
+                items.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+    items.into_iter().filter(|item| !item.is_empty())
+}
+
+/// Writes the `key=value` lines of localized values, e.g. `Name[de]=...`.
+pub fn localized_entries(key: &str, values: &BTreeMap<String, String>) -> String {
+    values
+        .iter()
+        .map(|(locale, value)| format!("{key}[{locale}]={}\n", escape_string(value)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_string("  a\\b\nc d"), "\\s\\sa\\\\b\\nc d");
+        assert_eq!(escape_list(["a;b", "c"]), "a\\;b;c;");
+        assert_eq!(quote_exec_arg("my-app"), "my-app");
+        assert_eq!(quote_exec_arg("100%"), "100%%");
+        assert_eq!(quote_exec_arg("my app$"), "\"my app\\$\"");
+        assert_eq!(
+            escape_string(&quote_exec_arg("C:\\app")),
+            "\"C:\\\\\\\\app\""
+        );
+    }
+
+    #[test]
+    fn list_items_handle_escapes() {
+        let items: Vec<&str> = list_items("a;b\\;c;;d").collect();
+        assert_eq!(items, ["a", "b\\;c", "d"]);
+    }
+
+    #[test]
+    fn key_names() {
+        assert!(check_key_name("Name").is_ok());
+        assert!(check_key_name("X-GNOME-Foo").is_ok());
+        assert!(check_key_name("Name[de]").is_ok());
+        assert!(check_key_name("Name[sr_RS.UTF-8@latin]").is_ok());
+        assert!(check_key_name("Name[]").is_err());
+        assert!(check_key_name("Na_me").is_err());
+        assert!(check_key_name("Name[de_]").is_err());
+    }
+
+    const VALID: &str = "\
+[Desktop Entry]
+Type=Application
+Name=App
+Name[de]=Anwendung
+Comment=Does things
+Exec=app %U
+Icon=app
+Terminal=false
+Keywords=thing;stuff\\;more;
+Actions=new-window;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=app --new-window
+";
+
+    #[test]
+    fn valid_file() {
+        assert_eq!(validate_desktop_entry(VALID).unwrap(), Vec::<String>::new());
+        let warnings = validate_desktop_entry(&format!("{VALID}\n# Comment\n"))
+            .unwrap()
+            .len();
+        assert_eq!(warnings, 0);
+        let warnings = validate_desktop_entry(&VALID.replace(
+            "Comment=Does things",
+            "Comment=App\nEncoding=UTF-8\nMimeType=",
+        ))
+        .unwrap();
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+    }
+
+    #[test]
+    fn invalid_files() {
+        let error = |contents: String| validate_desktop_entry(&contents).unwrap_err().to_string();
+        assert!(error(VALID.replace("Type=Application\n", "")).contains("\"Type\" is missing"));
+        assert!(error(VALID.replace("Terminal=false", "Terminal=no")).contains("\"Terminal\""));
+        assert!(error(VALID.replace("%U", "%F %u")).contains("more than one"));
+        assert!(error(VALID.replace("%U", "%z")).contains("%z"));
+        assert!(error(VALID.replace("Icon=app", "Icon=a\\pp")).contains("\\p"));
+        assert!(error(VALID.replace("Icon=app", "Colour=red")).contains("\"Colour\""));
+        assert!(error(VALID.replace("Terminal=", "Terminal[de]=")).contains("cannot be localized"));
+        assert!(error(VALID.replace("Actions=new-window;", "Actions=other;")).contains("other"));
+        assert!(error(format!("{VALID}[Extra]\n")).contains("[Extra]"));
+        assert!(error(format!("{VALID}Name=Again\n")).contains("duplicate key"));
+        assert!(error(format!("Name=App\n{VALID}")).contains("not in a group"));
+    }
+}
@@ -0,0 +1,432 @@
+// Helpers for inspecting (and editing) the dynamic linking information of ELF
+// binaries.  See https://refspecs.linuxbase.org/elf/gabi4+/ch5.dynamic.html
+// and, for symbol versioning, https://refspecs.linuxbase.org/LSB_5.0.0/LSB-Core-generic/LSB-Core-generic/symversion.html
+
+use anyhow::Context;
+use goblin::elf::dynamic::{DT_NULL, DT_RPATH, DT_RUNPATH, DT_STRSZ, DT_STRTAB};
+use goblin::elf::program_header::{PF_R, PF_W, PT_DYNAMIC, PT_LOAD, PT_PHDR, ProgramHeader};
+use goblin::elf::{Elf, section_header::SHN_UNDEF, sym::STB_WEAK};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+/// The dynamic linking information of an ELF binary.
+#[derive(Debug, Default)]
+pub struct DynamicInfo {
+    /// The sonames of the libraries listed in the binary's `DT_NEEDED`
+    /// entries, in order.
+    pub needed: Vec<String>,
+    /// The (non-weak) symbols the binary imports from shared libraries.
+    pub imported_symbols: Vec<ImportedSymbol>,
+    /// The directories in the binary's `DT_RPATH`, unexpanded.
+    pub rpath: Vec<String>,
+    /// The directories in the binary's `DT_RUNPATH`, unexpanded.
+    pub runpath: Vec<String>,
+    /// The machine (`e_machine`) the binary was built for.
+    pub machine: u16,
+    pub is_64: bool,
+}
+
+/// A dynamic symbol that an ELF binary expects a shared library to define.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ImportedSymbol {
+    pub name: String,
+    /// The symbol version (e.g. `GLIBC_2.34`) and the soname of the library
+    /// expected to define it, if the symbol is versioned.
+    pub version: Option<(String, String)>,
+}
+
+/// Reads the dynamic linking information of the ELF file at `path`.  Returns
+/// `None` if the file is not an ELF file.
+pub fn read_dynamic_info(path: &Path) -> crate::Result<Option<DynamicInfo>> {
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    if !bytes.starts_with(b"\x7fELF") {
+        return Ok(None);
+    }
+    let elf = Elf::parse(&bytes).with_context(|| format!("Failed to parse ELF file {path:?}"))?;
+
+    // Map version indices (as used by the `.gnu.version` section) to the
+    // version name and the library it is required from.
+    let mut versions = HashMap::new();
+    if let Some(verneed) = &elf.verneed {
+        for need in verneed.iter() {
+            let library = elf.dynstrtab.get_at(need.vn_file).unwrap_or_default();
+            for aux in need.iter() {
+                let name = elf.dynstrtab.get_at(aux.vna_name).unwrap_or_default();
+                versions.insert(aux.vna_other, (name.to_string(), library.to_string()));
+            }
+        }
+    }
+
+    let mut imported_symbols = Vec::new();
+    for (index, sym) in elf.dynsyms.iter().enumerate() {
+        if sym.st_shndx != SHN_UNDEF as usize || sym.st_bind() == STB_WEAK {
+            continue;
+        }
+        let name = match elf.dynstrtab.get_at(sym.st_name) {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        let version = elf
+            .versym
+            .as_ref()
+            .and_then(|versym| versym.get_at(index))
+            .and_then(|versym| versions.get(&versym.version()))
+            .cloned();
+        imported_symbols.push(ImportedSymbol {
+            name: name.to_string(),
+            version,
+        });
+    }
+
+    let split_paths = |paths: &[&str]| -> Vec<String> {
+        paths
+            .iter()
+            .flat_map(|path| path.split(':'))
+            .filter(|dir| !dir.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    Ok(Some(DynamicInfo {
+        needed: elf.libraries.iter().map(|lib| lib.to_string()).collect(),
+        imported_symbols,
+        rpath: split_paths(&elf.rpaths),
+        runpath: split_paths(&elf.runpaths),
+        machine: elf.header.e_machine,
+        is_64: elf.is_64,
+    }))
+}
+
+/// Returns the range of the file occupied by the section `name` of the ELF
+/// file in `bytes`, if it has such a section.
+pub fn section_range(bytes: &[u8], name: &str) -> crate::Result<Option<Range<usize>>> {
+    let elf = Elf::parse(bytes).with_context(|| "Failed to parse ELF file")?;
+    let Some(section) = elf
+        .section_headers
+        .iter()
+        .find(|section| elf.shdr_strtab.get_at(section.sh_name) == Some(name))
+    else {
+        return Ok(None);
+    };
+    let range = section
+        .file_range()
+        .filter(|range| range.end <= bytes.len())
+        .ok_or_else(|| anyhow::anyhow!("Section {name} is not in the file"))?;
+    Ok(Some(range))
+}
+
+/// Sets the `DT_RUNPATH` of the ELF file at `path` to `runpath`, replacing
+/// any existing `DT_RPATH` or `DT_RUNPATH`.
+///
+/// There is rarely room for a new string and dynamic entry in the existing
+/// sections, so (much like patchelf) this appends a new loadable segment to
+/// the file holding a copy of the string table with the new runpath added,
+/// the rewritten dynamic section, and the program header table (which needs
+/// an extra entry for the new segment).  The old copies are left in place,
+/// unused.
+pub fn set_runpath(path: &Path, runpath: &str) -> crate::Result<()> {
+    let original = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let elf =
+        Elf::parse(&original).with_context(|| format!("Failed to parse ELF file {path:?}"))?;
+    if elf.runpaths == [runpath] && elf.rpaths.is_empty() {
+        return Ok(());
+    }
+    let Some(dynamic) = &elf.dynamic else {
+        anyhow::bail!("{path:?} is not dynamically linked");
+    };
+    let dyn_value = |tag| {
+        dynamic
+            .dyns
+            .iter()
+            .find(|entry| entry.d_tag == tag)
+            .map(|entry| entry.d_val)
+    };
+    let (Some(strtab_addr), Some(strtab_size)) = (dyn_value(DT_STRTAB), dyn_value(DT_STRSZ)) else {
+        anyhow::bail!("{path:?} has no dynamic string table");
+    };
+    let loads: Vec<&ProgramHeader> = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .collect();
+    let strtab_offset = loads
+        .iter()
+        .find(|phdr| phdr.p_vaddr <= strtab_addr && strtab_addr < phdr.p_vaddr + phdr.p_filesz)
+        .map(|phdr| (strtab_addr - phdr.p_vaddr + phdr.p_offset) as usize)
+        .ok_or_else(|| anyhow::anyhow!("{path:?} has no loaded dynamic string table"))?;
+    let mut strtab = original
+        .get(strtab_offset..strtab_offset + strtab_size as usize)
+        .ok_or_else(|| anyhow::anyhow!("{path:?} is truncated"))?
+        .to_vec();
+    let runpath_index = strtab.len() as u64;
+    strtab.extend_from_slice(runpath.as_bytes());
+    strtab.push(0);
+
+    // The new segment must come after all the existing ones, both in the file
+    // and in memory.  Keeping its address and offset the same distance apart
+    // as in the first segment means that kernels which assume the program
+    // headers are in the first segment still find them.
+    let layout = Layout {
+        is_64: elf.is_64,
+        little_endian: elf.little_endian,
+    };
+    let first_load = loads
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("{path:?} has no loadable segments"))?;
+    let bias = first_load.p_vaddr.wrapping_sub(first_load.p_offset);
+    let align = loads
+        .iter()
+        .map(|phdr| phdr.p_align)
+        .max()
+        .unwrap_or(0)
+        .max(0x1000);
+    let mem_end = loads
+        .iter()
+        .map(|phdr| phdr.p_vaddr + phdr.p_memsz)
+        .max()
+        .unwrap_or(0);
+    let segment_offset = (original.len() as u64)
+        .max(mem_end.wrapping_sub(bias))
+        .next_multiple_of(align);
+    let segment_addr = segment_offset.wrapping_add(bias);
+
+    let phdrs_size = (elf.program_headers.len() + 1) * layout.phdr_size();
+    let dynamic_offset = phdrs_size.next_multiple_of(layout.word_size());
+    let mut dyns: Vec<(u64, u64)> = Vec::new();
+    for entry in &dynamic.dyns {
+        match entry.d_tag {
+            DT_RPATH | DT_RUNPATH | DT_NULL => {}
+            DT_STRTAB => dyns.push((DT_STRTAB, 0)),
+            DT_STRSZ => dyns.push((DT_STRSZ, strtab.len() as u64)),
+            tag => dyns.push((tag, entry.d_val)),
+        }
+    }
+    dyns.push((DT_RUNPATH, runpath_index));
+    dyns.push((DT_NULL, 0));
+    let dynamic_size = dyns.len() * 2 * layout.word_size();
+    let strtab_offset = dynamic_offset + dynamic_size;
+    let strtab_addr = segment_addr + strtab_offset as u64;
+    for entry in &mut dyns {
+        if entry.0 == DT_STRTAB {
+            entry.1 = strtab_addr;
+        }
+    }
+    let segment_size = (strtab_offset + strtab.len()) as u64;
+
+    let mut phdrs = Vec::new();
+    let last_load = elf
+        .program_headers
+        .iter()
+        .rposition(|phdr| phdr.p_type == PT_LOAD)
+        .unwrap();
+    for (index, phdr) in elf.program_headers.iter().enumerate() {
+        let mut phdr = phdr.clone();
+        if phdr.p_type == PT_PHDR {
+            phdr.p_offset = segment_offset;
+            phdr.p_vaddr = segment_addr;
+            phdr.p_paddr = segment_addr;
+            phdr.p_filesz = phdrs_size as u64;
+            phdr.p_memsz = phdrs_size as u64;
+        } else if phdr.p_type == PT_DYNAMIC {
+            phdr.p_offset = segment_offset + dynamic_offset as u64;
+            phdr.p_vaddr = segment_addr + dynamic_offset as u64;
+            phdr.p_paddr = phdr.p_vaddr;
+            phdr.p_filesz = dynamic_size as u64;
+            phdr.p_memsz = dynamic_size as u64;
+        }
+        phdrs.push(phdr);
+        if index == last_load {
+            phdrs.push(ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: PF_R | PF_W,
+                p_offset: segment_offset,
+                p_vaddr: segment_addr,
+                p_paddr: segment_addr,
+                p_filesz: segment_size,
+                p_memsz: segment_size,
+                p_align: align,
+            });
+        }
+    }
+
+    let mut segment = Vec::new();
+    for phdr in &phdrs {
+        layout.put_phdr(&mut segment, phdr);
+    }
+    segment.resize(dynamic_offset, 0);
+    for &(tag, value) in &dyns {
+        layout.put_word(&mut segment, tag);
+        layout.put_word(&mut segment, value);
+    }
+    segment.extend_from_slice(&strtab);
+
+    let mut bytes = original.clone();
+    // Point the section headers for the dynamic section and string table at
+    // the new copies, so that tools like readelf agree with the loader.
+    let shdr_size = layout.shdr_size();
+    for (index, shdr) in elf.section_headers.iter().enumerate() {
+        let (offset, addr, size) = match elf.shdr_strtab.get_at(shdr.sh_name) {
+            Some(".dynamic") => (
+                segment_offset + dynamic_offset as u64,
+                segment_addr + dynamic_offset as u64,
+                dynamic_size as u64,
+            ),
+            Some(".dynstr") => (
+                segment_offset + strtab_offset as u64,
+                strtab_addr,
+                strtab.len() as u64,
+            ),
+            _ => continue,
+        };
+        let start = elf.header.e_shoff as usize + index * shdr_size;
+        layout.patch_shdr(&mut bytes[start..start + shdr_size], offset, addr, size);
+    }
+    layout.patch_header(&mut bytes, segment_offset, phdrs.len() as u16);
+
+    bytes.resize(segment_offset as usize, 0);
+    bytes.extend_from_slice(&segment);
+    std::fs::write(path, &bytes).with_context(|| format!("Failed to write {path:?}"))?;
+    Ok(())
+}
+
+/// The word size and byte order of an ELF file, used to encode its
+/// structures.
+struct Layout {
+    is_64: bool,
+    little_endian: bool,
+}
+
+impl Layout {
+    fn word_size(&self) -> usize {
+        if self.is_64 { 8 } else { 4 }
+    }
+
+    fn phdr_size(&self) -> usize {
+        if self.is_64 { 56 } else { 32 }
+    }
+
+    fn shdr_size(&self) -> usize {
+        if self.is_64 { 64 } else { 40 }
+    }
+
+    fn encode(&self, value: u64, size: usize) -> Vec<u8> {
+        let bytes = if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        };
+        if self.little_endian {
+            bytes[..size].to_vec()
+        } else {
+            bytes[8 - size..].to_vec()
+        }
+    }
+
+    fn put_word(&self, out: &mut Vec<u8>, value: u64) {
+        out.extend(self.encode(value, self.word_size()));
+    }
+
+    fn put_u32(&self, out: &mut Vec<u8>, value: u32) {
+        out.extend(self.encode(value.into(), 4));
+    }
+
+    fn patch(&self, bytes: &mut [u8], offset: usize, value: u64, size: usize) {
+        bytes[offset..offset + size].copy_from_slice(&self.encode(value, size));
+    }
+
+    fn put_phdr(&self, out: &mut Vec<u8>, phdr: &ProgramHeader) {
+        // The flags come second in 64-bit files, but next to last in 32-bit
+        // ones, to keep the 64-bit fields aligned.
+        self.put_u32(out, phdr.p_type);
+        if self.is_64 {
+            self.put_u32(out, phdr.p_flags);
+        }
+        for value in [
+            phdr.p_offset,
+            phdr.p_vaddr,
+            phdr.p_paddr,
+            phdr.p_filesz,
+            phdr.p_memsz,
+        ] {
+            self.put_word(out, value);
+        }
+        if !self.is_64 {
+            self.put_u32(out, phdr.p_flags);
+        }
+        self.put_word(out, phdr.p_align);
+    }
+
+    /// Sets the address, offset and size of a section header.
+    fn patch_shdr(&self, shdr: &mut [u8], offset: u64, addr: u64, size: u64) {
+        let word = self.word_size();
+        // sh_name, sh_type and sh_flags precede the address.
+        let addr_offset = 8 + word;
+        self.patch(shdr, addr_offset, addr, word);
+        self.patch(shdr, addr_offset + word, offset, word);
+        self.patch(shdr, addr_offset + 2 * word, size, word);
+    }
+
+    /// Sets `e_phoff` and `e_phnum` in the ELF header.
+    fn patch_header(&self, bytes: &mut [u8], phoff: u64, phnum: u16) {
+        if self.is_64 {
+            self.patch(bytes, 32, phoff, 8);
+            self.patch(bytes, 56, phnum.into(), 2);
+        } else {
+            self.patch(bytes, 28, phoff, 4);
+            self.patch(bytes, 44, phnum.into(), 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_elf_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("script.sh");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        assert!(read_dynamic_info(&path).unwrap().is_none());
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn read_own_executable() {
+        let exe = std::env::current_exe().unwrap();
+        let info = read_dynamic_info(&exe).unwrap().unwrap();
+        assert!(info.needed.iter().any(|lib| lib == "libc.so.6"));
+        assert!(info.runpath.is_empty());
+        assert!(info.imported_symbols.iter().any(|sym| {
+            sym.version.as_ref().is_some_and(|(version, library)| {
+                version.starts_with("GLIBC_") && library == "libc.so.6"
+            })
+        }));
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn set_runpath_of_executable() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let exe = temp_dir.path().join("test-exe");
+        std::fs::copy(std::env::current_exe().unwrap(), &exe).unwrap();
+        set_runpath(&exe, "$ORIGIN/../lib").unwrap();
+        let info = read_dynamic_info(&exe).unwrap().unwrap();
+        assert_eq!(info.runpath, ["$ORIGIN/../lib"]);
+        assert!(info.needed.iter().any(|lib| lib == "libc.so.6"));
+
+        // Changing it again replaces the runpath rather than adding another.
+        set_runpath(&exe, "/opt/lib:$ORIGIN").unwrap();
+        let info = read_dynamic_info(&exe).unwrap().unwrap();
+        assert_eq!(info.runpath, ["/opt/lib", "$ORIGIN"]);
+        assert!(info.rpath.is_empty());
+
+        // The rewritten executable must still load and run.
+        let status = std::process::Command::new(&exe)
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
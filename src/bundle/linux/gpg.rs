@@ -0,0 +1,109 @@
+// Runs gpg for signing AppImages and APT repositories.  Keys are always
+// given as files and imported into a temporary home directory, so that the
+// user's own keyrings are neither needed nor modified.
+
+use anyhow::Context;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A temporary gpg home directory, removed (along with the gpg-agent started
+/// for it) when dropped.
+pub struct GpgHome {
+    pub dir: PathBuf,
+}
+
+impl GpgHome {
+    pub fn new() -> crate::Result<GpgHome> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "cargo-bundle-gnupg-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut builder = std::fs::DirBuilder::new();
+        // gpg refuses to use a home directory that others can read.
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&dir)
+            .with_context(|| format!("Failed to create {dir:?}"))?;
+        Ok(GpgHome { dir })
+    }
+
+    /// Imports the (public or secret) OpenPGP key in the file `key`.
+    pub fn import(&self, key: &Path) -> crate::Result<()> {
+        self.gpg([OsStr::new("--import"), key.as_os_str()], None)?;
+        Ok(())
+    }
+
+    /// Runs gpg with the given arguments to sign something with the imported
+    /// secret key.  There is no terminal to prompt on in batch mode, so if
+    /// the key is protected, its passphrase is read from the environment
+    /// variable `passphrase_var` and passed to gpg in loopback mode.
+    pub fn sign<I, S>(&self, args: I, passphrase_var: &str) -> crate::Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let passphrase = std::env::var(passphrase_var).ok();
+        let mut sign_args = vec![
+            OsString::from("--pinentry-mode"),
+            OsString::from("loopback"),
+        ];
+        if passphrase.is_some() {
+            sign_args.extend([OsString::from("--passphrase-fd"), OsString::from("0")]);
+        }
+        sign_args.extend(args.into_iter().map(|arg| arg.as_ref().to_os_string()));
+        self.gpg(sign_args, passphrase.as_deref())
+    }
+
+    /// Runs gpg in batch mode with this home directory and the given
+    /// arguments, writing `input` to its standard input, and returns its
+    /// standard output.
+    pub fn gpg<I, S>(&self, args: I, input: Option<&str>) -> crate::Result<Vec<u8>>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut child = Command::new("gpg")
+            .arg("--homedir")
+            .arg(&self.dir)
+            .args(["--batch", "--yes", "--quiet"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| "Failed to run gpg")?;
+        let mut stdin = child.stdin.take().unwrap();
+        if let Some(input) = input {
+            stdin.write_all(input.as_bytes())?;
+        }
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "gpg failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl Drop for GpgHome {
+    fn drop(&mut self) {
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(&self.dir)
+            .args(["--kill", "gpg-agent"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,180 @@
+// Declares the MIME types of the app's `file_associations` to the desktop, in
+// a shared-mime-info package file
+// (https://specifications.freedesktop.org/shared-mime-info-spec/latest/):
+// `usr/share/mime/packages/<binary name>.xml` maps each MIME type to its file
+// name extensions and description.  Standard types (like `text/plain`) only
+// get the extra extensions, so that their descriptions aren't replaced.
+// Document icons are installed into the hicolor theme's `mimetypes` context
+// under the generic icon name of their MIME type (e.g. `application-x-foo`).
+// The MIME database itself is updated by the shared-mime-info package's
+// triggers when the package is installed.
+
+use crate::bundle::{FileAssociation, Settings, common};
+use image::GenericImageView;
+use quick_xml::se::Serializer;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+const SHARED_MIME_INFO_NAMESPACE: &str = "http://www.freedesktop.org/standards/shared-mime-info";
+
+/// Generates the shared-mime-info package file for the file associations
+/// that have a MIME type, and installs their icons, under the `data_dir`.
+pub fn generate_mime_info_file(settings: &Settings, data_dir: &Path) -> crate::Result<()> {
+    let associations = settings.file_associations();
+    if let Some(xml) = mime_info_xml(associations)? {
+        let path = data_dir
+            .join("usr/share/mime/packages")
+            .join(format!("{}.xml", settings.binary_name()));
+        super::common::create_file_with_data(&path, &xml)?;
+    }
+    let icons_dir = data_dir.join("usr/share/icons/hicolor");
+    for association in associations {
+        let Some(icon_path) = &association.icon else {
+            continue;
+        };
+        match &association.mime_type {
+            Some(mime_type) => install_mime_type_icon(icon_path, mime_type, &icons_dir)?,
+            None => common::print_warning(&format!(
+                "The icon {icon_path:?} of a file association without a MIME type \
+                 is not used on Linux"
+            ))?,
+        }
+    }
+    Ok(())
+}
+
+/// Builds the shared-mime-info XML, or returns `None` if no association has
+/// a MIME type.
+fn mime_info_xml(associations: &[FileAssociation]) -> crate::Result<Option<String>> {
+    let mut mime_types = BTreeMap::<&str, MimeType>::new();
+    for association in associations {
+        let Some(mime_type) = association.mime_type.as_deref() else {
+            continue;
+        };
+        if !mime_type.contains('/') {
+            anyhow::bail!("{mime_type:?} is not a MIME type (e.g. \"application/x-foo\")");
+        }
+        let entry = mime_types.entry(mime_type).or_insert_with(|| MimeType {
+            mime_type: mime_type.to_string(),
+            comment: None,
+            globs: Vec::new(),
+        });
+        // Standard types already have a (translated) comment.
+        if association.is_custom_type() && entry.comment.is_none() {
+            entry.comment = Some(association.description());
+        }
+        entry
+            .globs
+            .extend(association.extensions().map(|extension| Glob {
+                pattern: format!("*.{extension}"),
+            }));
+    }
+    if mime_types.is_empty() {
+        return Ok(None);
+    }
+    let mime_info = MimeInfo {
+        xmlns: SHARED_MIME_INFO_NAMESPACE,
+        mime_types: mime_types.into_values().collect(),
+    };
+    let mut buffer = String::new();
+    let mut serializer = Serializer::new(&mut buffer);
+    serializer.indent(' ', 2);
+    mime_info.serialize(serializer)?;
+    Ok(Some(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{buffer}\n"
+    )))
+}
+
+#[derive(Serialize)]
+#[serde(rename = "mime-info")]
+struct MimeInfo<'a> {
+    #[serde(rename = "@xmlns")]
+    xmlns: &'a str,
+    #[serde(rename = "mime-type")]
+    mime_types: Vec<MimeType>,
+}
+
+#[derive(Serialize)]
+struct MimeType {
+    #[serde(rename = "@type")]
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(rename = "glob")]
+    globs: Vec<Glob>,
+}
+
+#[derive(Serialize)]
+struct Glob {
+    #[serde(rename = "@pattern")]
+    pattern: String,
+}
+
+/// Installs a document icon as the icon of `mime_type`: SVGs as scalable
+/// icons, and other images as PNGs in the directory for their size.
+fn install_mime_type_icon(
+    icon_path: &Path,
+    mime_type: &str,
+    icons_dir: &Path,
+) -> crate::Result<()> {
+    let icon_name = mime_type.replace('/', "-");
+    if icon_path.extension() == Some(OsStr::new("svg")) {
+        let dest_path = icons_dir.join(format!("scalable/mimetypes/{icon_name}.svg"));
+        return common::copy_file(icon_path, &dest_path);
+    }
+    let image = image::open(icon_path)?;
+    let (width, height) = image.dimensions();
+    let dest_path = icons_dir.join(format!("{width}x{height}/mimetypes/{icon_name}.png"));
+    if icon_path.extension() == Some(OsStr::new("png")) {
+        common::copy_file(icon_path, &dest_path)
+    } else {
+        common::create_file(&dest_path)?;
+        image.save_with_format(&dest_path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::FileAssociationRole;
+
+    fn association(extensions: &[&str], mime_type: Option<&str>) -> FileAssociation {
+        FileAssociation {
+            extensions: extensions.iter().map(|s| s.to_string()).collect(),
+            mime_type: mime_type.map(str::to_string),
+            description: None,
+            icon: None,
+            role: FileAssociationRole::Editor,
+        }
+    }
+
+    #[test]
+    fn mime_info_document() {
+        let associations = [
+            association(&[".foo", "fooz"], Some("application/x-foo")),
+            association(&["bar"], None),
+            association(&["foo2"], Some("application/x-foo")),
+            association(&["text"], Some("text/plain")),
+        ];
+        assert_eq!(
+            mime_info_xml(&associations).unwrap().unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  \
+               <mime-type type=\"application/x-foo\">\n    \
+                 <comment>FOO document</comment>\n    \
+                 <glob pattern=\"*.foo\"/>\n    \
+                 <glob pattern=\"*.fooz\"/>\n    \
+                 <glob pattern=\"*.foo2\"/>\n  \
+               </mime-type>\n  \
+               <mime-type type=\"text/plain\">\n    \
+                 <glob pattern=\"*.text\"/>\n  \
+               </mime-type>\n\
+             </mime-info>\n"
+        );
+        assert!(mime_info_xml(&associations[1..2]).unwrap().is_none());
+        assert!(mime_info_xml(&[association(&["foo"], Some("foo"))]).is_err());
+    }
+}
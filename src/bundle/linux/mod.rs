@@ -1,4 +1,20 @@
 pub(crate) mod appimage_bundle;
+pub(crate) mod appimage_signature;
+mod appstream;
+pub(crate) mod apt_repo;
+mod changelog;
 mod common;
 pub(crate) mod deb_bundle;
+pub(crate) mod deb_lint;
+mod deb_reader;
+mod desktop_entry;
+mod elf;
+mod gpg;
+mod license_texts;
+mod mime_info;
 pub(crate) mod rpm_bundle;
+mod shared_libs;
+mod shlibdeps;
+mod squashfs;
+mod systemd;
+mod zsync;
@@ -1,6 +1,1009 @@
-use crate::Settings;
-use std::path::PathBuf;
+// An RPM package (version 4 of the format) is a single file laid out like:
+//
+// foobar-1.2.3-1.x86_64.rpm
+//     lead                # 96 bytes of legacy metadata, mostly ignored nowadays
+//     signature header    # Sizes and digests of the header and payload
+//     header              # Package metadata: name, version, file list, etc.
+//     payload             # A gzip-compressed cpio archive of the files to install:
+//         ./usr/bin/foobar                            # Binary executable file
+//         ./usr/share/applications/foobar.desktop     # Desktop file (for apps)
+//         ./usr/share/icons/hicolor/...               # Icon files (for apps)
+//         ./usr/lib/foobar/...                        # Other resource files
+//
+// Both headers share the same binary structure: a magic number, an index of
+// (tag, type, offset, count) entries, and a data store that the offsets point
+// into.  See https://rpm-software-management.github.io/rpm/manual/format_v4.html
+// for a full explanation.
+//
+// The files placed into the payload use the same layout as the deb bundler,
+// with configuration files from `linux_files` flagged as `%config(noreplace)`.
+// Directories named after the package or its binary (like /usr/lib/foobar or
+// /etc/foobar) are listed too, so that `rpm -e` removes them; the others are
+// shared with the rest of the system.
+// The only scriptlets we generate are those that set up systemd units,
+// sysusers.d and tmpfiles.d files, mirroring the standard systemd rpm macros.
 
-pub fn bundle_project(_settings: &Settings) -> crate::Result<Vec<PathBuf>> {
-    unimplemented!();
+use crate::bundle::{
+    Settings, common,
+    linux::common::{
+        DEFAULT_GZIP_LEVEL, PackageFile, generate_desktop_file, generate_icon_files, gzip_encoder,
+        modification_time, normalized_mode, package_name, transfer_linux_files,
+        transfer_resource_files,
+    },
+    linux::{
+        appstream::generate_metainfo_file, mime_info::generate_mime_info_file,
+        systemd::SystemdFiles,
+    },
+};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const HEADER_MAGIC: [u8; 8] = [0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0];
+
+// Header entry types:
+const TYPE_INT16: u32 = 3;
+const TYPE_INT32: u32 = 4;
+const TYPE_STRING: u32 = 6;
+const TYPE_BIN: u32 = 7;
+const TYPE_STRING_ARRAY: u32 = 8;
+const TYPE_I18NSTRING: u32 = 9;
+
+// Region tags, which mark the extent of a signed header:
+const TAG_HEADERSIGNATURES: u32 = 62;
+const TAG_HEADERIMMUTABLE: u32 = 63;
+const TAG_HEADERI18NTABLE: u32 = 100;
+
+// Signature header tags:
+const SIGTAG_SHA256: u32 = 273;
+const SIGTAG_SIZE: u32 = 1000;
+const SIGTAG_MD5: u32 = 1004;
+const SIGTAG_PAYLOADSIZE: u32 = 1007;
+
+// Main header tags:
+const TAG_NAME: u32 = 1000;
+const TAG_VERSION: u32 = 1001;
+const TAG_RELEASE: u32 = 1002;
+const TAG_SUMMARY: u32 = 1004;
+const TAG_DESCRIPTION: u32 = 1005;
+const TAG_BUILDTIME: u32 = 1006;
+const TAG_SIZE: u32 = 1009;
+const TAG_LICENSE: u32 = 1014;
+const TAG_PACKAGER: u32 = 1015;
+const TAG_GROUP: u32 = 1016;
+const TAG_URL: u32 = 1020;
+const TAG_OS: u32 = 1021;
+const TAG_ARCH: u32 = 1022;
+const TAG_POSTIN: u32 = 1024;
+const TAG_PREUN: u32 = 1025;
+const TAG_POSTUN: u32 = 1026;
+const TAG_FILESIZES: u32 = 1028;
+const TAG_FILEMODES: u32 = 1030;
+const TAG_FILERDEVS: u32 = 1033;
+const TAG_FILEMTIMES: u32 = 1034;
+const TAG_FILEDIGESTS: u32 = 1035;
+const TAG_FILELINKTOS: u32 = 1036;
+const TAG_FILEFLAGS: u32 = 1037;
+const TAG_FILEUSERNAME: u32 = 1039;
+const TAG_FILEGROUPNAME: u32 = 1040;
+const TAG_SOURCERPM: u32 = 1044;
+const TAG_FILEVERIFYFLAGS: u32 = 1045;
+const TAG_PROVIDENAME: u32 = 1047;
+const TAG_REQUIREFLAGS: u32 = 1048;
+const TAG_REQUIRENAME: u32 = 1049;
+const TAG_REQUIREVERSION: u32 = 1050;
+const TAG_POSTINPROG: u32 = 1086;
+const TAG_PREUNPROG: u32 = 1087;
+const TAG_POSTUNPROG: u32 = 1088;
+const TAG_FILEDEVICES: u32 = 1095;
+const TAG_FILEINODES: u32 = 1096;
+const TAG_FILELANGS: u32 = 1097;
+const TAG_PROVIDEFLAGS: u32 = 1112;
+const TAG_PROVIDEVERSION: u32 = 1113;
+const TAG_DIRINDEXES: u32 = 1116;
+const TAG_BASENAMES: u32 = 1117;
+const TAG_DIRNAMES: u32 = 1118;
+const TAG_PAYLOADFORMAT: u32 = 1124;
+const TAG_PAYLOADCOMPRESSOR: u32 = 1125;
+const TAG_PAYLOADFLAGS: u32 = 1126;
+const TAG_FILEDIGESTALGO: u32 = 5011;
+
+// File flags:
+const FILE_CONFIG: u32 = 1 << 0;
+const FILE_NOREPLACE: u32 = 1 << 4;
+
+// Dependency comparison flags:
+const SENSE_LESS: u32 = 0x02;
+const SENSE_GREATER: u32 = 0x04;
+const SENSE_EQUAL: u32 = 0x08;
+const SENSE_INTERP: u32 = 0x0100;
+const SENSE_SCRIPT_POST: u32 = 0x0400;
+const SENSE_SCRIPT_PREUN: u32 = 0x0800;
+const SENSE_SCRIPT_POSTUN: u32 = 0x1000;
+const SENSE_RPMLIB: u32 = 0x0100_0000;
+
+// The digest algorithm used for the FILEDIGESTS tag (8 means SHA-256):
+const DIGEST_ALGO_SHA256: u32 = 8;
+
+// Features of the package format that the installing `rpm` must support:
+const RPMLIB_REQUIREMENTS: &[(&str, &str)] = &[
+    ("rpmlib(CompressedFileNames)", "3.0.4-1"),
+    ("rpmlib(FileDigests)", "4.6.0-1"),
+    ("rpmlib(PayloadFilesHavePrefix)", "4.0-1"),
+];
+
+// The interpreter used to run scriptlets:
+const SCRIPTLET_INTERPRETER: &str = "/bin/sh";
+
+// The gzip compression level of the payload:
+const PAYLOAD_COMPRESSION_LEVEL: u32 = DEFAULT_GZIP_LEVEL;
+
+/// A script that `rpm` runs at some stage of installing or removing the
+/// package.
+struct Scriptlet {
+    tag: u32,
+    /// The tag naming the scriptlet's interpreter.
+    prog_tag: u32,
+    /// The dependency flags for the interpreter's `Requires(...)` entry.
+    sense: u32,
+    body: String,
+}
+
+pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
+    let arch = match settings.binary_arch() {
+        "x86" => "i686",
+        "arm" => "armv7hl",
+        other => other,
+    };
+    let name = package_name(settings);
+    let version = rpm_version(&settings.version_string().to_string());
+    let release = settings.rpm_release();
+    let package_base_name = format!("{name}-{version}-{release}.{arch}");
+    let package_name = format!("{package_base_name}.rpm");
+    common::print_bundling(&package_name)?;
+    let base_dir = settings.project_out_directory().join("bundle/rpm");
+    let package_dir = base_dir.join(&package_base_name);
+    if package_dir.exists() {
+        fs::remove_dir_all(&package_dir)
+            .with_context(|| format!("Failed to remove old {package_base_name}"))?;
+    }
+    let package_path = base_dir.join(&package_name);
+
+    // Generate data files.
+    let data_dir = package_dir.join("data");
+    let binary_dest = data_dir.join("usr/bin").join(settings.binary_name());
+    common::copy_file(settings.binary_path(), &binary_dest)
+        .with_context(|| "Failed to copy binary file")?;
+    transfer_resource_files(settings, &data_dir)
+        .with_context(|| "Failed to copy resource files")?;
+    generate_icon_files(settings, &data_dir).with_context(|| "Failed to create icon files")?;
+    generate_desktop_file(settings, &data_dir).with_context(|| "Failed to create desktop file")?;
+    generate_metainfo_file(settings, &data_dir)
+        .with_context(|| "Failed to create AppStream metainfo file")?;
+    generate_mime_info_file(settings, &data_dir)
+        .with_context(|| "Failed to create shared MIME info file")?;
+    let linux_files =
+        transfer_linux_files(settings, &data_dir).with_context(|| "Failed to copy linux_files")?;
+    let systemd_files = SystemdFiles::install(settings, &data_dir)
+        .with_context(|| "Failed to copy systemd files")?;
+    let scriptlets = [
+        Scriptlet {
+            tag: TAG_POSTIN,
+            prog_tag: TAG_POSTINPROG,
+            sense: SENSE_SCRIPT_POST,
+            body: systemd_files.rpm_post(),
+        },
+        Scriptlet {
+            tag: TAG_PREUN,
+            prog_tag: TAG_PREUNPROG,
+            sense: SENSE_SCRIPT_PREUN,
+            body: systemd_files.rpm_preun(),
+        },
+        Scriptlet {
+            tag: TAG_POSTUN,
+            prog_tag: TAG_POSTUNPROG,
+            sense: SENSE_SCRIPT_POSTUN,
+            body: systemd_files.rpm_postun(),
+        },
+    ];
+
+    // Build the payload and the headers that describe it.
+    let files = collect_files(settings, &data_dir, &linux_files)
+        .with_context(|| "Failed to collect package files")?;
+    let mut file = common::create_file(&package_path)?;
+    write_package(
+        &mut file,
+        settings,
+        &name,
+        &version,
+        arch,
+        &files,
+        &scriptlets,
+    )?;
+    file.flush()?;
+    Ok(vec![package_path])
+}
+
+/// Writes the lead, both headers and the payload of a package containing the
+/// given files.
+fn write_package<W: Write>(
+    writer: &mut W,
+    settings: &Settings,
+    name: &str,
+    version: &str,
+    arch: &str,
+    files: &[FileInfo],
+    scriptlets: &[Scriptlet],
+) -> crate::Result<()> {
+    let (payload, payload_size) =
+        generate_payload(files).with_context(|| "Failed to create cpio payload")?;
+    let header = generate_header(settings, name, version, arch, files, scriptlets)
+        .with_context(|| "Failed to create package header")?;
+    let signature = generate_signature(&header, &payload, payload_size);
+    let release = settings.rpm_release();
+    writer.write_all(&generate_lead(&format!("{name}-{version}-{release}")))?;
+    writer.write_all(&signature)?;
+    writer.write_all(&header)?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// RPM versions may not contain dashes, which separate the version from the
+/// release; pre-release suffixes are expressed with a tilde instead, so that
+/// `1.0.0~beta` sorts before `1.0.0`.
+fn rpm_version(version: &str) -> String {
+    version.replace('-', "~")
+}
+
+// A file, symlink or directory that will be installed by the package.
+struct FileInfo {
+    // The path to the file in the data directory.
+    source_path: PathBuf,
+    // The absolute path where this file will be installed.
+    install_path: String,
+    // The file type and permission bits.
+    mode: u32,
+    // The RPMFILE_* flags of the file.
+    flags: u32,
+    // The modification time of the file, in seconds since the epoch.
+    mtime: u32,
+    // The symlink target, if this is a symlink.
+    link_target: Option<String>,
+    // The size of the file's payload data, in bytes.
+    size: u32,
+    // The hex-encoded SHA-256 digest of the file (empty for symlinks and
+    // directories).
+    digest: String,
+}
+
+impl FileInfo {
+    fn is_dir(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+}
+
+fn collect_files(
+    settings: &Settings,
+    data_dir: &Path,
+    linux_files: &[PackageFile],
+) -> crate::Result<Vec<FileInfo>> {
+    let source_date_epoch = common::source_date_epoch()?;
+    let package_name = package_name(settings);
+    let private_names = [package_name.as_str(), settings.binary_name()];
+    let mut files = Vec::new();
+    for entry in WalkDir::new(data_dir).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let source_path = entry.path().to_path_buf();
+        let rel_path = source_path.strip_prefix(data_dir).unwrap();
+        if entry.file_type().is_dir() && !is_private_dir(rel_path, &private_names) {
+            continue;
+        }
+        let install_path = format!(
+            "/{}",
+            rel_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Non-UTF-8 path: {rel_path:?}"))?
+        );
+        let metadata = fs::symlink_metadata(&source_path)?;
+        let mtime = modification_time(&metadata, source_date_epoch) as u32;
+        let linux_file = linux_files.iter().find(|file| file.path == rel_path);
+        // Configuration files behave like `%config(noreplace)` in a spec file.
+        let flags = match linux_file {
+            Some(file) if file.conffile => FILE_CONFIG | FILE_NOREPLACE,
+            _ => 0,
+        };
+        if entry.file_type().is_dir() {
+            files.push(FileInfo {
+                source_path,
+                install_path,
+                mode: 0o040000 | normalized_mode(&metadata),
+                flags: 0,
+                mtime,
+                link_target: None,
+                size: 0,
+                digest: String::new(),
+            });
+        } else if entry.file_type().is_symlink() {
+            let target = fs::read_link(&source_path)?.to_string_lossy().into_owned();
+            files.push(FileInfo {
+                source_path,
+                install_path,
+                mode: 0o120777,
+                flags,
+                mtime,
+                size: target.len() as u32,
+                link_target: Some(target),
+                digest: String::new(),
+            });
+        } else {
+            let data = fs::read(&source_path)?;
+            files.push(FileInfo {
+                source_path,
+                install_path,
+                mode: 0o100000
+                    | linux_file
+                        .and_then(|file| file.mode)
+                        .unwrap_or_else(|| normalized_mode(&metadata)),
+                flags,
+                mtime,
+                link_target: None,
+                size: data.len() as u32,
+                digest: hex_string(&Sha256::digest(&data)),
+            });
+        }
+    }
+    Ok(files)
+}
+
+/// Returns whether the package owns the directory at `rel_path`, because it
+/// or one of its parents is named after the package or its binary.
+fn is_private_dir(rel_path: &Path, private_names: &[&str]) -> bool {
+    rel_path.components().any(|component| {
+        private_names
+            .iter()
+            .any(|name| component.as_os_str() == OsStr::new(name))
+    })
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Generates the 96-byte lead that starts every RPM file.
+fn generate_lead(nvr: &str) -> Vec<u8> {
+    let mut lead = Vec::with_capacity(96);
+    lead.extend_from_slice(&LEAD_MAGIC);
+    lead.extend_from_slice(&[3, 0]); // Format version 3.0
+    lead.extend_from_slice(&0u16.to_be_bytes()); // Binary package
+    lead.extend_from_slice(&1u16.to_be_bytes()); // Architecture number (unused)
+    let mut name = [0u8; 66];
+    let len = nvr.len().min(65);
+    name[..len].copy_from_slice(&nvr.as_bytes()[..len]);
+    lead.extend_from_slice(&name);
+    lead.extend_from_slice(&1u16.to_be_bytes()); // Operating system number (Linux)
+    lead.extend_from_slice(&5u16.to_be_bytes()); // Signature type (header-style)
+    lead.extend_from_slice(&[0u8; 16]);
+    debug_assert_eq!(lead.len(), 96);
+    lead
+}
+
+/// Creates the gzip-compressed cpio archive containing the package's files.
+/// Returns the compressed payload along with its uncompressed size.
+fn generate_payload(files: &[FileInfo]) -> crate::Result<(Vec<u8>, u32)> {
+    let mut cpio = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        let data = match file.link_target {
+            Some(ref target) => target.as_bytes().to_vec(),
+            None if file.is_dir() => Vec::new(),
+            None => fs::read(&file.source_path)?,
+        };
+        write_cpio_entry(
+            &mut cpio,
+            &format!(".{}", file.install_path),
+            index as u32 + 1,
+            file.mode,
+            file.mtime,
+            &data,
+        );
+    }
+    write_cpio_entry(&mut cpio, "TRAILER!!!", 0, 0, 0, &[]);
+    let payload_size = cpio.len() as u32;
+    let mut encoder = gzip_encoder(Vec::new(), PAYLOAD_COMPRESSION_LEVEL)?;
+    encoder.write_all(&cpio)?;
+    let payload = encoder.finish()?;
+    Ok((payload, payload_size))
+}
+
+/// Appends a single entry in the "new ASCII" (`070701`) cpio format.
+fn write_cpio_entry(
+    cpio: &mut Vec<u8>,
+    name: &str,
+    inode: u32,
+    mode: u32,
+    mtime: u32,
+    data: &[u8],
+) {
+    let fields = [
+        inode,
+        mode,
+        0, // uid
+        0, // gid
+        1, // nlink
+        mtime,
+        data.len() as u32,
+        0, // devmajor
+        0, // devminor
+        0, // rdevmajor
+        0, // rdevminor
+        name.len() as u32 + 1,
+        0, // check
+    ];
+    cpio.extend_from_slice(b"070701");
+    for field in fields {
+        cpio.extend_from_slice(format!("{field:08x}").as_bytes());
+    }
+    cpio.extend_from_slice(name.as_bytes());
+    cpio.push(0);
+    pad_to(cpio, 4);
+    cpio.extend_from_slice(data);
+    pad_to(cpio, 4);
+}
+
+fn pad_to(buffer: &mut Vec<u8>, alignment: usize) {
+    while !buffer.len().is_multiple_of(alignment) {
+        buffer.push(0);
+    }
+}
+
+/// Parses a dependency like `"openssl-libs >= 1.1"` into its name, comparison
+/// flags and version.
+fn parse_dependency(dependency: &str) -> crate::Result<(String, u32, String)> {
+    let parts: Vec<&str> = dependency.split_whitespace().collect();
+    match parts.as_slice() {
+        [name] => Ok((name.to_string(), 0, String::new())),
+        [name, op, version] => {
+            let flags = match *op {
+                "<" => SENSE_LESS,
+                "<=" => SENSE_LESS | SENSE_EQUAL,
+                "=" | "==" => SENSE_EQUAL,
+                ">=" => SENSE_GREATER | SENSE_EQUAL,
+                ">" => SENSE_GREATER,
+                _ => anyhow::bail!("Invalid comparison operator {op:?} in {dependency:?}"),
+            };
+            Ok((name.to_string(), flags, version.to_string()))
+        }
+        _ => anyhow::bail!("Invalid RPM dependency {dependency:?}"),
+    }
+}
+
+fn generate_header(
+    settings: &Settings,
+    name: &str,
+    version: &str,
+    arch: &str,
+    files: &[FileInfo],
+    scriptlets: &[Scriptlet],
+) -> crate::Result<Vec<u8>> {
+    let release = settings.rpm_release();
+    let mut header = Header::default();
+    header.add(
+        TAG_HEADERI18NTABLE,
+        Value::StringArray(vec!["C".to_string()]),
+    );
+    header.add(TAG_NAME, Value::String(name.to_string()));
+    header.add(TAG_VERSION, Value::String(version.to_string()));
+    header.add(TAG_RELEASE, Value::String(release.to_string()));
+    let mut summary = settings.short_description().trim();
+    if summary.is_empty() {
+        summary = settings.bundle_name();
+    }
+    header.add(TAG_SUMMARY, Value::I18nString(summary.to_string()));
+    let description = settings
+        .long_description()
+        .map(str::trim)
+        .filter(|description| !description.is_empty())
+        .unwrap_or(summary);
+    header.add(TAG_DESCRIPTION, Value::I18nString(description.to_string()));
+    let build_time = match common::source_date_epoch()? {
+        Some(timestamp) => timestamp,
+        None => chrono::Utc::now().timestamp() as u64,
+    };
+    header.add(TAG_BUILDTIME, Value::Int32(vec![build_time as u32]));
+    let total_size: u32 = files.iter().map(|file| file.size).sum();
+    header.add(TAG_SIZE, Value::Int32(vec![total_size]));
+    header.add(
+        TAG_LICENSE,
+        Value::String(settings.rpm_license().unwrap_or("Unknown").to_string()),
+    );
+    if let Some(authors) = settings.authors_comma_separated() {
+        header.add(TAG_PACKAGER, Value::String(authors));
+    }
+    header.add(
+        TAG_GROUP,
+        Value::I18nString(settings.rpm_group().to_string()),
+    );
+    if !settings.homepage_url().is_empty() {
+        header.add(TAG_URL, Value::String(settings.homepage_url().to_string()));
+    }
+    header.add(TAG_OS, Value::String("linux".to_string()));
+    header.add(TAG_ARCH, Value::String(arch.to_string()));
+    header.add(
+        TAG_SOURCERPM,
+        Value::String(format!("{name}-{version}-{release}.src.rpm")),
+    );
+
+    // Dependencies:
+    let evr = format!("{version}-{release}");
+    header.add(
+        TAG_PROVIDENAME,
+        Value::StringArray(vec![name.to_string(), format!("{name}({arch})")]),
+    );
+    header.add(TAG_PROVIDEFLAGS, Value::Int32(vec![SENSE_EQUAL; 2]));
+    header.add(
+        TAG_PROVIDEVERSION,
+        Value::StringArray(vec![evr.clone(), evr]),
+    );
+    let mut require_names = Vec::new();
+    let mut require_flags = Vec::new();
+    let mut require_versions = Vec::new();
+    for dependency in settings.rpm_requires() {
+        let (name, flags, version) = parse_dependency(dependency)?;
+        require_names.push(name);
+        require_flags.push(flags);
+        require_versions.push(version);
+    }
+    for scriptlet in scriptlets
+        .iter()
+        .filter(|scriptlet| !scriptlet.body.is_empty())
+    {
+        header.add(scriptlet.tag, Value::String(scriptlet.body.clone()));
+        header.add(
+            scriptlet.prog_tag,
+            Value::String(SCRIPTLET_INTERPRETER.to_string()),
+        );
+        require_names.push(SCRIPTLET_INTERPRETER.to_string());
+        require_flags.push(SENSE_INTERP | scriptlet.sense);
+        require_versions.push(String::new());
+    }
+    for &(name, version) in RPMLIB_REQUIREMENTS {
+        require_names.push(name.to_string());
+        require_flags.push(SENSE_RPMLIB | SENSE_LESS | SENSE_EQUAL);
+        require_versions.push(version.to_string());
+    }
+    header.add(TAG_REQUIRENAME, Value::StringArray(require_names));
+    header.add(TAG_REQUIREFLAGS, Value::Int32(require_flags));
+    header.add(TAG_REQUIREVERSION, Value::StringArray(require_versions));
+
+    // File list:
+    let mut dir_indices = BTreeMap::<String, u32>::new();
+    let mut dirnames = Vec::new();
+    let mut basenames = Vec::new();
+    let mut dirindexes = Vec::new();
+    for file in files {
+        let split = file.install_path.rfind('/').unwrap() + 1;
+        let (dirname, basename) = file.install_path.split_at(split);
+        let index = *dir_indices.entry(dirname.to_string()).or_insert_with(|| {
+            dirnames.push(dirname.to_string());
+            dirnames.len() as u32 - 1
+        });
+        dirindexes.push(index);
+        basenames.push(basename.to_string());
+    }
+    let count = files.len();
+    header.add(
+        TAG_FILESIZES,
+        Value::Int32(files.iter().map(|file| file.size).collect()),
+    );
+    header.add(
+        TAG_FILEMODES,
+        Value::Int16(files.iter().map(|file| file.mode as u16).collect()),
+    );
+    header.add(TAG_FILERDEVS, Value::Int16(vec![0; count]));
+    header.add(
+        TAG_FILEMTIMES,
+        Value::Int32(files.iter().map(|file| file.mtime).collect()),
+    );
+    header.add(
+        TAG_FILEDIGESTS,
+        Value::StringArray(files.iter().map(|file| file.digest.clone()).collect()),
+    );
+    header.add(
+        TAG_FILELINKTOS,
+        Value::StringArray(
+            files
+                .iter()
+                .map(|file| file.link_target.clone().unwrap_or_default())
+                .collect(),
+        ),
+    );
+    header.add(
+        TAG_FILEFLAGS,
+        Value::Int32(files.iter().map(|file| file.flags).collect()),
+    );
+    header.add(
+        TAG_FILEUSERNAME,
+        Value::StringArray(vec!["root".to_string(); count]),
+    );
+    header.add(
+        TAG_FILEGROUPNAME,
+        Value::StringArray(vec!["root".to_string(); count]),
+    );
+    header.add(TAG_FILEVERIFYFLAGS, Value::Int32(vec![u32::MAX; count]));
+    header.add(TAG_FILEDEVICES, Value::Int32(vec![1; count]));
+    header.add(TAG_FILEINODES, Value::Int32((1..=count as u32).collect()));
+    header.add(
+        TAG_FILELANGS,
+        Value::StringArray(vec![String::new(); count]),
+    );
+    header.add(TAG_DIRINDEXES, Value::Int32(dirindexes));
+    header.add(TAG_BASENAMES, Value::StringArray(basenames));
+    header.add(TAG_DIRNAMES, Value::StringArray(dirnames));
+    header.add(TAG_FILEDIGESTALGO, Value::Int32(vec![DIGEST_ALGO_SHA256]));
+
+    // Payload description:
+    header.add(TAG_PAYLOADFORMAT, Value::String("cpio".to_string()));
+    header.add(TAG_PAYLOADCOMPRESSOR, Value::String("gzip".to_string()));
+    header.add(
+        TAG_PAYLOADFLAGS,
+        Value::String(PAYLOAD_COMPRESSION_LEVEL.to_string()),
+    );
+
+    Ok(header.to_bytes(TAG_HEADERIMMUTABLE))
+}
+
+/// Generates the signature header, which holds digests of the main header and
+/// payload, padded to an 8-byte boundary.
+fn generate_signature(header: &[u8], payload: &[u8], payload_size: u32) -> Vec<u8> {
+    let mut md5 = md5::Context::new();
+    md5.consume(header);
+    md5.consume(payload);
+    let mut signature = Header::default();
+    signature.add(
+        SIGTAG_SHA256,
+        Value::String(hex_string(&Sha256::digest(header))),
+    );
+    signature.add(
+        SIGTAG_SIZE,
+        Value::Int32(vec![(header.len() + payload.len()) as u32]),
+    );
+    signature.add(SIGTAG_MD5, Value::Bin(md5.finalize().to_vec()));
+    signature.add(SIGTAG_PAYLOADSIZE, Value::Int32(vec![payload_size]));
+    let mut bytes = signature.to_bytes(TAG_HEADERSIGNATURES);
+    pad_to(&mut bytes, 8);
+    bytes
+}
+
+// The data of a single header entry.
+enum Value {
+    Int16(Vec<u16>),
+    Int32(Vec<u32>),
+    String(String),
+    Bin(Vec<u8>),
+    StringArray(Vec<String>),
+    I18nString(String),
+}
+
+impl Value {
+    fn type_and_count(&self) -> (u32, u32) {
+        match self {
+            Value::Int16(values) => (TYPE_INT16, values.len() as u32),
+            Value::Int32(values) => (TYPE_INT32, values.len() as u32),
+            Value::String(_) => (TYPE_STRING, 1),
+            Value::Bin(bytes) => (TYPE_BIN, bytes.len() as u32),
+            Value::StringArray(values) => (TYPE_STRING_ARRAY, values.len() as u32),
+            Value::I18nString(_) => (TYPE_I18NSTRING, 1),
+        }
+    }
+
+    fn alignment(&self) -> usize {
+        match self {
+            Value::Int16(_) => 2,
+            Value::Int32(_) => 4,
+            _ => 1,
+        }
+    }
+
+    fn write_to(&self, data: &mut Vec<u8>) {
+        match self {
+            Value::Int16(values) => {
+                for value in values {
+                    data.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Value::Int32(values) => {
+                for value in values {
+                    data.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Value::String(value) | Value::I18nString(value) => {
+                data.extend_from_slice(value.as_bytes());
+                data.push(0);
+            }
+            Value::Bin(bytes) => data.extend_from_slice(bytes),
+            Value::StringArray(values) => {
+                for value in values {
+                    data.extend_from_slice(value.as_bytes());
+                    data.push(0);
+                }
+            }
+        }
+    }
+}
+
+// An RPM header structure, as used by both the signature and main headers.
+#[derive(Default)]
+struct Header {
+    entries: BTreeMap<u32, Value>,
+}
+
+impl Header {
+    fn add(&mut self, tag: u32, value: Value) {
+        self.entries.insert(tag, value);
+    }
+
+    /// Serializes the header, wrapping all of its entries in a region with the
+    /// given tag.
+    fn to_bytes(&self, region_tag: u32) -> Vec<u8> {
+        let index_count = self.entries.len() as u32 + 1;
+        let mut index = Vec::new();
+        let mut data = Vec::new();
+        for (&tag, value) in self.entries.iter() {
+            pad_to(&mut data, value.alignment());
+            let (value_type, count) = value.type_and_count();
+            write_index_entry(&mut index, tag, value_type, data.len() as u32, count);
+            value.write_to(&mut data);
+        }
+        // The region trailer is itself an index entry, whose (negative) offset
+        // covers the whole index.
+        let trailer_offset = data.len() as u32;
+        let region_offset = -(index_count as i32 * 16);
+        write_index_entry(&mut data, region_tag, TYPE_BIN, region_offset as u32, 16);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&HEADER_MAGIC);
+        bytes.extend_from_slice(&index_count.to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        write_index_entry(&mut bytes, region_tag, TYPE_BIN, trailer_offset, 16);
+        bytes.extend_from_slice(&index);
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+}
+
+fn write_index_entry(buffer: &mut Vec<u8>, tag: u32, value_type: u32, offset: u32, count: u32) {
+    buffer.extend_from_slice(&tag.to_be_bytes());
+    buffer.extend_from_slice(&value_type.to_be_bytes());
+    buffer.extend_from_slice(&offset.to_be_bytes());
+    buffer.extend_from_slice(&count.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    // A parsed header entry: its type, count, and data from its offset on.
+    type Entry<'a> = (u32, u32, &'a [u8]);
+
+    /// Parses the header at the start of `bytes`, returning its entries by tag
+    /// and its length.
+    fn parse_header(bytes: &[u8]) -> (BTreeMap<u32, Entry<'_>>, usize) {
+        assert_eq!(&bytes[..8], &HEADER_MAGIC);
+        let index_count = read_u32(bytes, 8) as usize;
+        let data_size = read_u32(bytes, 12) as usize;
+        let data_start = 16 + index_count * 16;
+        let data = &bytes[data_start..data_start + data_size];
+        let entries = (0..index_count)
+            .map(|i| {
+                let base = 16 + i * 16;
+                let offset = read_u32(bytes, base + 8) as usize;
+                (
+                    read_u32(bytes, base),
+                    (
+                        read_u32(bytes, base + 4),
+                        read_u32(bytes, base + 12),
+                        &data[offset..],
+                    ),
+                )
+            })
+            .collect();
+        (entries, data_start + data_size)
+    }
+
+    fn strings(data: &[u8], count: u32) -> Vec<String> {
+        data.split(|&byte| byte == 0)
+            .take(count as usize)
+            .map(|string| String::from_utf8(string.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn package_round_trip() {
+        let cli = <crate::Cli as clap::Parser>::parse_from([
+            "cargo-bundle",
+            "--example",
+            "hello",
+            "--format",
+            "rpm",
+        ]);
+        let settings = Settings::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")), &cli).unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        for (path, contents) in [
+            ("usr/bin/hello", "binary"),
+            ("usr/lib/hello/data/a.txt", "resource"),
+            ("usr/share/applications/hello.desktop", "[Desktop Entry]"),
+        ] {
+            let path = data_dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let files = collect_files(&settings, data_dir.path(), &[]).unwrap();
+        let mut package = Vec::new();
+        write_package(
+            &mut package,
+            &settings,
+            "hello",
+            "1.0",
+            "x86_64",
+            &files,
+            &[],
+        )
+        .unwrap();
+
+        // The lead names the package.
+        assert_eq!(&package[..4], &LEAD_MAGIC);
+        assert!(package[10..].starts_with(b"hello-1.0-1\0"));
+
+        // The signature header, padded to 8 bytes, describes the rest.
+        let (signature, signature_len) = parse_header(&package[96..]);
+        let header_start = 96 + signature_len.next_multiple_of(8);
+        let (header, header_len) = parse_header(&package[header_start..]);
+        let header_bytes = &package[header_start..header_start + header_len];
+        let payload = &package[header_start + header_len..];
+        assert_eq!(
+            read_u32(signature[&SIGTAG_SIZE].2, 0) as usize,
+            header_len + payload.len()
+        );
+        assert_eq!(
+            strings(signature[&SIGTAG_SHA256].2, 1),
+            [hex_string(&Sha256::digest(header_bytes))]
+        );
+        let mut cpio = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(payload), &mut cpio).unwrap();
+        assert_eq!(
+            read_u32(signature[&SIGTAG_PAYLOADSIZE].2, 0) as usize,
+            cpio.len()
+        );
+
+        // The main header lists the package-private directories along with
+        // the files, but not the shared directories.
+        assert_eq!(strings(header[&TAG_NAME].2, 1), ["hello"]);
+        assert_eq!(strings(header[&TAG_PAYLOADFLAGS].2, 1), ["9"]);
+        let (_, count, basenames) = header[&TAG_BASENAMES];
+        assert_eq!(
+            strings(basenames, count),
+            ["hello", "hello", "data", "a.txt", "hello.desktop"]
+        );
+        let (_, count, dirnames) = header[&TAG_DIRNAMES];
+        assert_eq!(
+            strings(dirnames, count),
+            [
+                "/usr/bin/",
+                "/usr/lib/",
+                "/usr/lib/hello/",
+                "/usr/lib/hello/data/",
+                "/usr/share/applications/"
+            ]
+        );
+        let modes = header[&TAG_FILEMODES].2;
+        let mode = |i: usize| u16::from_be_bytes([modes[i * 2], modes[i * 2 + 1]]);
+        assert_eq!(mode(0), 0o100644);
+        assert_eq!(mode(1), 0o040755);
+        assert_eq!(mode(2), 0o040755);
+
+        // The payload holds the same entries.
+        for name in [
+            "./usr/bin/hello",
+            "./usr/lib/hello",
+            "./usr/lib/hello/data",
+            "./usr/lib/hello/data/a.txt",
+            "./usr/share/applications/hello.desktop",
+            "TRAILER!!!",
+        ] {
+            let name = format!("{name}\0");
+            assert!(
+                cpio.windows(name.len())
+                    .any(|window| window == name.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut header = Header::default();
+        header.add(TAG_NAME, Value::String("foo".to_string()));
+        header.add(TAG_FILEMODES, Value::Int16(vec![0o100755]));
+        header.add(TAG_FILESIZES, Value::Int32(vec![42]));
+        let bytes = header.to_bytes(TAG_HEADERIMMUTABLE);
+
+        assert_eq!(&bytes[..8], &HEADER_MAGIC);
+        let index_count = read_u32(&bytes, 8) as usize;
+        let data_size = read_u32(&bytes, 12) as usize;
+        assert_eq!(index_count, 4);
+        assert_eq!(bytes.len(), 16 + index_count * 16 + data_size);
+        let data = &bytes[16 + index_count * 16..];
+
+        // The first entry is the region, pointing at the trailer at the end
+        // of the data store.
+        assert_eq!(read_u32(&bytes, 16), TAG_HEADERIMMUTABLE);
+        let trailer = read_u32(&bytes, 24) as usize;
+        assert_eq!(trailer, data_size - 16);
+        assert_eq!(read_u32(data, trailer), TAG_HEADERIMMUTABLE);
+        assert_eq!(
+            read_u32(data, trailer + 8) as i32,
+            -(index_count as i32 * 16)
+        );
+
+        // The remaining entries are sorted by tag, with aligned data.
+        let entry = |i: usize| {
+            let base = 16 + i * 16;
+            (read_u32(&bytes, base), read_u32(&bytes, base + 8) as usize)
+        };
+        assert_eq!(entry(1).0, TAG_NAME);
+        assert_eq!(&data[entry(1).1..entry(1).1 + 4], b"foo\0");
+        assert_eq!(entry(2).0, TAG_FILESIZES);
+        assert_eq!(entry(2).1 % 4, 0);
+        assert_eq!(read_u32(data, entry(2).1), 42);
+        assert_eq!(entry(3).0, TAG_FILEMODES);
+        assert_eq!(
+            &data[entry(3).1..entry(3).1 + 2],
+            &0o100755u16.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn cpio_entries_are_padded() {
+        let mut cpio = Vec::new();
+        write_cpio_entry(&mut cpio, "./usr/bin/foo", 1, 0o100755, 0, b"hello");
+        assert!(cpio.starts_with(b"070701"));
+        assert_eq!(cpio.len() % 4, 0);
+        // 110 header bytes, the NUL-terminated name padded to 4 bytes, then
+        // the data padded to 4 bytes.
+        assert_eq!(cpio.len(), 124 + 8);
+        assert_eq!(&cpio[110..123], b"./usr/bin/foo");
+        assert_eq!(&cpio[124..129], b"hello");
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        assert_eq!(
+            parse_dependency("openssl-libs").unwrap(),
+            ("openssl-libs".to_string(), 0, String::new())
+        );
+        assert_eq!(
+            parse_dependency("SDL2 >= 2.0.5").unwrap(),
+            (
+                "SDL2".to_string(),
+                SENSE_GREATER | SENSE_EQUAL,
+                "2.0.5".to_string()
+            )
+        );
+        assert!(parse_dependency("SDL2 ~> 2.0.5").is_err());
+        assert!(parse_dependency("SDL2 >=").is_err());
+    }
+
+    #[test]
+    fn version_with_prerelease() {
+        assert_eq!(rpm_version("1.2.3"), "1.2.3");
+        assert_eq!(rpm_version("1.2.3-beta.1"), "1.2.3~beta.1");
+    }
 }
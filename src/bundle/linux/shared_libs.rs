@@ -0,0 +1,301 @@
+// Finds the shared libraries that a binary needs to be bundled with, in the
+// same spirit as linuxdeploy: starting from the binary, each soname in the
+// DT_NEEDED entries is looked up the way the dynamic loader would (see
+// ld.so(8)), and the libraries found are in turn searched for their own
+// dependencies.  Libraries that are expected to be present on every system
+// (glibc, the graphics drivers' libraries, etc.) are not bundled, and so
+// their dependencies aren't followed either.
+
+use super::elf::{DynamicInfo, read_dynamic_info};
+use crate::bundle::common::print_warning;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Libraries that must not be bundled, based on the AppImage project's
+/// excludelist (https://github.com/AppImageCommunity/pkg2appimage/blob/master/excludelist).
+/// These are either part of the C library, which must match the system's
+/// dynamic loader, or tied to the system's hardware and drivers.
+const EXCLUDED_LIBRARIES: &[&str] = &[
+    "ld-linux.so.2",
+    "ld-linux-x86-64.so.2",
+    "ld-linux-aarch64.so.1",
+    "ld-linux-armhf.so.3",
+    "libanl.so.1",
+    "libBrokenLocale.so.1",
+    "libc.so.6",
+    "libcidn.so.1",
+    "libdl.so.2",
+    "libm.so.6",
+    "libmvec.so.1",
+    "libnss_compat.so.2",
+    "libnss_dns.so.2",
+    "libnss_files.so.2",
+    "libnss_hesiod.so.2",
+    "libnss_nis.so.2",
+    "libnss_nisplus.so.2",
+    "libpthread.so.0",
+    "libresolv.so.2",
+    "librt.so.1",
+    "libthread_db.so.1",
+    "libutil.so.1",
+    "libstdc++.so.6",
+    "libgcc_s.so.1",
+    "libGL.so.1",
+    "libEGL.so.1",
+    "libGLdispatch.so.0",
+    "libGLX.so.0",
+    "libOpenGL.so.0",
+    "libdrm.so.2",
+    "libglapi.so.0",
+    "libgbm.so.1",
+    "libxcb.so.1",
+    "libxcb-dri2.so.0",
+    "libxcb-dri3.so.0",
+    "libX11.so.6",
+    "libX11-xcb.so.1",
+    "libasound.so.2",
+    "libjack.so.0",
+    "libpipewire-0.3.so.0",
+    "libfontconfig.so.1",
+    "libfreetype.so.6",
+    "libharfbuzz.so.0",
+    "libfribidi.so.0",
+    "libthai.so.0",
+    "libcom_err.so.2",
+    "libexpat.so.1",
+    "libgpg-error.so.0",
+    "libgmp.so.10",
+    "libICE.so.6",
+    "libSM.so.6",
+    "libp11-kit.so.0",
+    "libusb-1.0.so.0",
+    "libuuid.so.1",
+    "libz.so.1",
+];
+
+/// The directories the dynamic loader searches after those in
+/// `/etc/ld.so.conf`.
+const DEFAULT_LIBRARY_DIRS: &[&str] = &["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+/// Returns whether the library `soname` should be left out of bundles.
+pub fn is_excluded(soname: &str) -> bool {
+    EXCLUDED_LIBRARIES.contains(&soname) || soname.starts_with("ld-linux")
+}
+
+/// Returns the paths of the shared libraries that `binary` depends on
+/// (directly or indirectly) and that should be bundled with it, in the order
+/// they were found.  `search_path` lists extra directories to look in before
+/// the system ones, like `LD_LIBRARY_PATH`.  Libraries that can't be found
+/// are skipped with a warning.
+pub fn collect_libraries(binary: &Path, search_path: &[PathBuf]) -> crate::Result<Vec<PathBuf>> {
+    let Some(info) = read_dynamic_info(binary)? else {
+        anyhow::bail!("{binary:?} is not an ELF binary");
+    };
+    let system_dirs = system_library_dirs();
+    let mut seen = HashSet::new();
+    let mut libraries = Vec::new();
+    let mut queue = vec![(binary.to_path_buf(), info)];
+    let mut index = 0;
+    while let Some((path, info)) = queue.get(index) {
+        index += 1;
+        let dirs = search_dirs(path, info, search_path, &system_dirs);
+        let mut found = Vec::new();
+        for soname in &info.needed {
+            if is_excluded(soname) || !seen.insert(soname.clone()) {
+                continue;
+            }
+            match find_library(soname, &dirs, info)? {
+                Some(library) => found.push(library),
+                None => {
+                    let file_name = path.file_name().unwrap_or_default();
+                    print_warning(&format!(
+                        "Failed to find {soname} (needed by {}); it will not be bundled",
+                        file_name.to_string_lossy()
+                    ))?;
+                }
+            }
+        }
+        for (library, info) in found {
+            libraries.push(library.clone());
+            queue.push((library, info));
+        }
+    }
+    Ok(libraries)
+}
+
+/// Returns the path from which the dynamic loader would load each of the
+/// libraries that the binary at `path` (described by `info`) links against
+/// directly, keyed by soname.  Libraries that can't be found are left out.
+pub fn locate_needed(path: &Path, info: &DynamicInfo) -> crate::Result<BTreeMap<String, PathBuf>> {
+    let dirs = search_dirs(path, info, &[], &system_library_dirs());
+    let mut libraries = BTreeMap::new();
+    for soname in &info.needed {
+        if let Some((library, _)) = find_library(soname, &dirs, info)? {
+            libraries.insert(soname.clone(), library);
+        }
+    }
+    Ok(libraries)
+}
+
+/// Returns the directories to search for the libraries needed by the binary
+/// at `path`, in the order the dynamic loader would use.
+fn search_dirs(
+    path: &Path,
+    info: &DynamicInfo,
+    search_path: &[PathBuf],
+    system_dirs: &[PathBuf],
+) -> Vec<PathBuf> {
+    let origin = path.parent().unwrap_or_else(|| Path::new("."));
+    let expand = |dirs: &[String]| -> Vec<PathBuf> {
+        dirs.iter().map(|dir| expand_origin(dir, origin)).collect()
+    };
+    let mut dirs = Vec::new();
+    // DT_RPATH is ignored if there is a DT_RUNPATH.
+    if info.runpath.is_empty() {
+        dirs.extend(expand(&info.rpath));
+    }
+    dirs.extend(search_path.iter().cloned());
+    dirs.extend(expand(&info.runpath));
+    dirs.extend(system_dirs.iter().cloned());
+    dirs
+}
+
+/// Replaces `$ORIGIN` (or `${ORIGIN}`) in a runpath entry with the directory
+/// containing the binary.
+fn expand_origin(dir: &str, origin: &Path) -> PathBuf {
+    let origin = origin.to_string_lossy();
+    PathBuf::from(
+        dir.replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin),
+    )
+}
+
+/// Looks for `soname` in `dirs`, skipping libraries built for a different
+/// architecture than `needed_by`.
+fn find_library(
+    soname: &str,
+    dirs: &[PathBuf],
+    needed_by: &DynamicInfo,
+) -> crate::Result<Option<(PathBuf, DynamicInfo)>> {
+    for dir in dirs {
+        let candidate = dir.join(soname);
+        if !candidate.is_file() {
+            continue;
+        }
+        if let Some(info) = read_dynamic_info(&candidate)?
+            && info.machine == needed_by.machine
+            && info.is_64 == needed_by.is_64
+        {
+            return Ok(Some((candidate, info)));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the directories listed in `/etc/ld.so.conf` (and the files it
+/// includes), followed by the loader's default directories.
+fn system_library_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    read_ld_so_conf(Path::new("/etc/ld.so.conf"), &mut dirs, 0);
+    dirs.extend(DEFAULT_LIBRARY_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+fn read_ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>, depth: usize) {
+    // Guard against include loops.
+    if depth > 8 {
+        return;
+    }
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return;
+    };
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap().trim();
+        if let Some(pattern) = line.strip_prefix("include") {
+            let pattern = pattern.trim();
+            // Relative includes are relative to the including file.
+            let pattern = path
+                .parent()
+                .unwrap_or_else(|| Path::new("/"))
+                .join(pattern);
+            let mut includes: Vec<PathBuf> = glob::glob(&pattern.to_string_lossy())
+                .map(|paths| paths.filter_map(Result::ok).collect())
+                .unwrap_or_default();
+            includes.sort();
+            for include in includes {
+                read_ld_so_conf(&include, dirs, depth + 1);
+            }
+        } else if line.starts_with('/') {
+            let dir = PathBuf::from(line);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excluded_libraries() {
+        assert!(is_excluded("libc.so.6"));
+        assert!(is_excluded("ld-linux-riscv64-lp64d.so.1"));
+        assert!(!is_excluded("libssl.so.3"));
+    }
+
+    #[test]
+    fn origin_expansion() {
+        let origin = Path::new("/opt/app/bin");
+        assert_eq!(
+            expand_origin("$ORIGIN/../lib", origin),
+            Path::new("/opt/app/bin/../lib")
+        );
+        assert_eq!(
+            expand_origin("${ORIGIN}/lib", origin),
+            Path::new("/opt/app/bin/lib")
+        );
+        assert_eq!(expand_origin("/usr/lib", origin), Path::new("/usr/lib"));
+    }
+
+    #[test]
+    fn runpath_overrides_rpath() {
+        let system_dirs = [PathBuf::from("/usr/lib")];
+        let search_path = [PathBuf::from("/extra")];
+        let mut info = DynamicInfo {
+            rpath: vec!["/rpath".to_string()],
+            ..Default::default()
+        };
+        let binary = Path::new("/app/bin/foo");
+        assert_eq!(
+            search_dirs(binary, &info, &search_path, &system_dirs),
+            ["/rpath", "/extra", "/usr/lib"].map(PathBuf::from)
+        );
+        info.runpath = vec!["$ORIGIN/../lib".to_string()];
+        assert_eq!(
+            search_dirs(binary, &info, &search_path, &system_dirs),
+            ["/extra", "/app/bin/../lib", "/usr/lib"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn ld_so_conf_includes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let conf_dir = temp_dir.path().join("ld.so.conf.d");
+        std::fs::create_dir(&conf_dir).unwrap();
+        std::fs::write(
+            temp_dir.path().join("ld.so.conf"),
+            "# Comment\ninclude ld.so.conf.d/*.conf\n/opt/lib\n",
+        )
+        .unwrap();
+        std::fs::write(conf_dir.join("b.conf"), "/usr/lib/b\n").unwrap();
+        std::fs::write(conf_dir.join("a.conf"), "/usr/lib/a # Comment\n").unwrap();
+        let mut dirs = Vec::new();
+        read_ld_so_conf(&temp_dir.path().join("ld.so.conf"), &mut dirs, 0);
+        assert_eq!(
+            dirs,
+            ["/usr/lib/a", "/usr/lib/b", "/opt/lib"].map(PathBuf::from)
+        );
+    }
+}
@@ -0,0 +1,501 @@
+// Works out which Debian packages provide the shared libraries that a binary
+// links against, in the same spirit as dpkg-shlibdeps(1).  For each soname in
+// the binary's DT_NEEDED entries, we find the file that the dynamic loader
+// would load, look up the package that installed that file in the dpkg
+// database (the `info/*.list` files), and then use that package's `symbols`
+// or `shlibs` file to build a (possibly versioned) dependency.  With a
+// `symbols` file, the minimum version is the highest version at which any of
+// the symbols the binary uses was introduced.
+
+use crate::bundle::common::print_warning;
+use crate::bundle::linux::elf::{DynamicInfo, ImportedSymbol};
+use anyhow::Context;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// The default location of the dpkg database.
+const DEFAULT_ADMIN_DIR: &str = "/var/lib/dpkg";
+
+/// Returns the directory containing the dpkg database: `configured` if given
+/// (the `deb_dpkg_admin_dir` setting), or else, as with dpkg itself, the
+/// `DPKG_ADMINDIR` environment variable or `/var/lib/dpkg`.
+pub fn dpkg_admin_dir(configured: Option<&Path>) -> PathBuf {
+    configured
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os("DPKG_ADMINDIR").map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_ADMIN_DIR))
+}
+
+/// Computes the `Depends` entries for the libraries listed in `info`, using
+/// the dpkg database under `admin_dir`.  `library_paths` maps each soname to
+/// the path that the dynamic loader would load it from, which is looked up
+/// in the packages' file lists.  `arch` is the Debian architecture of the
+/// package, used to pick between multiarch packages.
+pub fn resolve_dependencies(
+    info: &DynamicInfo,
+    library_paths: &BTreeMap<String, PathBuf>,
+    admin_dir: &Path,
+    arch: &str,
+) -> crate::Result<Vec<String>> {
+    let info_dir = admin_dir.join("info");
+    let owners = library_owners(&info_dir, library_paths)
+        .with_context(|| format!("Failed to read the dpkg database in {admin_dir:?}"))?;
+    let mut dependencies = Vec::new();
+    for soname in &info.needed {
+        let Some(path) = library_paths.get(soname) else {
+            print_warning(&format!(
+                "Failed to find {soname}; it will not be added to Depends"
+            ))?;
+            continue;
+        };
+        let Some(candidates) = owners.get(path) else {
+            print_warning(&format!(
+                "No installed package provides {}; it will not be added to Depends",
+                path.display()
+            ))?;
+            continue;
+        };
+        let package = preferred_package(candidates, arch);
+        let symbols_path = info_dir.join(format!("{package}.symbols"));
+        let shlibs_path = info_dir.join(format!("{package}.shlibs"));
+        let mut dependency = None;
+        if let Some(symbols) = read_if_exists(&symbols_path)? {
+            dependency = symbols_dependency(&symbols, soname, &info.imported_symbols);
+        }
+        if dependency.is_none()
+            && let Some(shlibs) = read_if_exists(&shlibs_path)?
+        {
+            dependency = shlibs_dependency(&shlibs, soname);
+        }
+        let dependency =
+            dependency.unwrap_or_else(|| package.split(':').next().unwrap().to_string());
+        dependencies.push(dependency);
+    }
+    Ok(merge_dependencies(dependencies))
+}
+
+/// Picks the package to depend on among those (`.list` file stems) that
+/// contain the same library, preferring one qualified with `arch` (e.g.
+/// `libc6:amd64`), then an unqualified one.
+fn preferred_package<'a>(candidates: &'a [String], arch: &str) -> &'a str {
+    let rank = |stem: &String| match stem.split_once(':') {
+        Some((_, package_arch)) if package_arch == arch => 0,
+        None => 1,
+        Some(_) => 2,
+    };
+    candidates.iter().min_by_key(|stem| rank(stem)).unwrap()
+}
+
+/// Maps each of the `library_paths` to the (sorted) names of the `.list`
+/// files in the dpkg database that contain it, e.g.
+/// `/usr/lib/x86_64-linux-gnu/libc.so.6` to `["libc6:amd64"]`.  Paths are
+/// compared after resolving symlinks in their directories (so that `/lib`
+/// and `/usr/lib` match on merged-`/usr` systems), falling back to the fully
+/// resolved path when a library's own symlink isn't in any package.
+fn library_owners(
+    info_dir: &Path,
+    library_paths: &BTreeMap<String, PathBuf>,
+) -> crate::Result<BTreeMap<PathBuf, Vec<String>>> {
+    let mut list_files = Vec::new();
+    for entry in std::fs::read_dir(info_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "list") {
+            list_files.push(path);
+        }
+    }
+    list_files.sort();
+    // Only entries with the same file name as one of the libraries (or the
+    // files their symlinks point to) can match, so there's no need to resolve
+    // the directories of all the others.
+    let file_names: BTreeSet<OsString> = library_paths
+        .values()
+        .flat_map(|path| [Some(path.clone()), std::fs::canonicalize(path).ok()])
+        .flatten()
+        .filter_map(|path| path.file_name().map(OsString::from))
+        .collect();
+    let mut listed: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for path in list_files {
+        let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let contents =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+        for line in contents.lines() {
+            if !Path::new(line)
+                .file_name()
+                .is_some_and(|file_name| file_names.contains(file_name))
+            {
+                continue;
+            }
+            let packages = listed
+                .entry(canonical_dir_path(Path::new(line)))
+                .or_default();
+            if !packages.contains(&stem) {
+                packages.push(stem.clone());
+            }
+        }
+    }
+    let mut owners = BTreeMap::new();
+    for path in library_paths.values() {
+        let packages = listed
+            .get(&canonical_dir_path(path))
+            .or_else(|| listed.get(&std::fs::canonicalize(path).ok()?));
+        if let Some(packages) = packages {
+            owners.insert(path.clone(), packages.clone());
+        }
+    }
+    Ok(owners)
+}
+
+/// Resolves the symlinks in the directory part of `path`, leaving the file
+/// name (which is often a symlink to the versioned library) as it is.
+fn canonical_dir_path(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => std::fs::canonicalize(dir)
+            .unwrap_or_else(|_| dir.to_path_buf())
+            .join(file_name),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn read_if_exists(path: &Path) -> crate::Result<Option<String>> {
+    if path.is_file() {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        Ok(Some(contents))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Builds the dependency for `soname` from a `symbols` file (see
+/// deb-symbols(5)), replacing `#MINVER#` with the highest version at which
+/// one of the `imported` symbols was introduced.
+fn symbols_dependency(contents: &str, soname: &str, imported: &[ImportedSymbol]) -> Option<String> {
+    let mut template = None;
+    let mut min_version: Option<&str> = None;
+    for line in contents.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if template.is_none() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(symbol), Some(version)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let symbol = match symbol.strip_prefix('(') {
+                Some(tagged) => {
+                    let Some((tags, symbol)) = tagged.split_once(')') else {
+                        continue;
+                    };
+                    // Patterns (C++ demangling, regexes, wildcards) are not
+                    // supported.
+                    if tags
+                        .split('|')
+                        .any(|tag| matches!(tag, "c++" | "regex" | "symver"))
+                    {
+                        continue;
+                    }
+                    symbol
+                }
+                None => symbol,
+            };
+            let Some((name, symbol_version)) = symbol.rsplit_once('@') else {
+                continue;
+            };
+            let used = imported.iter().any(|sym| {
+                sym.name == name
+                    && match &sym.version {
+                        Some((version, library)) => version == symbol_version && library == soname,
+                        None => symbol_version == "Base",
+                    }
+            });
+            if used
+                && version != "0"
+                && min_version.is_none_or(|min| compare_versions(version, min) == Ordering::Greater)
+            {
+                min_version = Some(version);
+            }
+        } else if line.starts_with('|') || line.starts_with('*') || line.starts_with('#') {
+            continue;
+        } else if template.is_some() {
+            // The next library's section has started.
+            break;
+        } else if let Some((library, rest)) = line.split_once(char::is_whitespace)
+            && library == soname
+        {
+            template = Some(rest.trim());
+        }
+    }
+    let minver = match min_version {
+        Some(version) => format!("(>= {version})"),
+        None => String::new(),
+    };
+    template.map(|template| template.replace("#MINVER#", &minver).trim().to_string())
+}
+
+/// Looks up the dependency for `soname` in a `shlibs` file (see
+/// deb-shlibs(5)).
+fn shlibs_dependency(contents: &str, soname: &str) -> Option<String> {
+    let (library, version) = split_soname(soname)?;
+    contents.lines().find_map(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (first, rest) = line.split_once(char::is_whitespace)?;
+        // Entries for other package types (e.g. `udeb:`) don't apply to us.
+        if first.ends_with(':') {
+            return None;
+        }
+        let (entry_version, dependency) = rest.trim_start().split_once(char::is_whitespace)?;
+        (first == library && entry_version == version).then(|| dependency.trim().to_string())
+    })
+}
+
+/// Splits a soname into the library name and version used in `shlibs` files,
+/// e.g. `libfoo.so.1` into `("libfoo", "1")` and `libfoo-1.2.so` into
+/// `("libfoo", "1.2")`.
+fn split_soname(soname: &str) -> Option<(&str, &str)> {
+    if let Some((library, version)) = soname.split_once(".so.") {
+        Some((library, version))
+    } else {
+        let (library, version) = soname.strip_suffix(".so")?.rsplit_once('-')?;
+        version
+            .starts_with(|c: char| c.is_ascii_digit())
+            .then_some((library, version))
+    }
+}
+
+/// Removes duplicate dependencies, keeping only the highest minimum version
+/// when the same package is required more than once.
+fn merge_dependencies(dependencies: Vec<String>) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    for dependency in dependencies {
+        let Some((name, version)) = parse_simple_dependency(&dependency) else {
+            if !merged.contains(&dependency) {
+                merged.push(dependency);
+            }
+            continue;
+        };
+        let existing = merged.iter_mut().find(|existing| {
+            parse_simple_dependency(existing)
+                .is_some_and(|(existing_name, _)| existing_name == name)
+        });
+        match existing {
+            Some(existing) => {
+                let (_, existing_version) = parse_simple_dependency(existing).unwrap();
+                let replace = match (existing_version, version) {
+                    (_, None) => false,
+                    (None, Some(_)) => true,
+                    (Some(old), Some(new)) => compare_versions(new, old) == Ordering::Greater,
+                };
+                if replace {
+                    *existing = dependency;
+                }
+            }
+            None => merged.push(dependency),
+        }
+    }
+    merged
+}
+
+/// Parses a dependency of the form `name` or `name (>= version)`.
+fn parse_simple_dependency(dependency: &str) -> Option<(&str, Option<&str>)> {
+    match dependency.split_once(" (>= ") {
+        Some((name, version)) => Some((name, Some(version.strip_suffix(')')?))),
+        None if !dependency.contains(['(', '|', ',', ' ']) => Some((dependency, None)),
+        None => None,
+    }
+}
+
+/// Compares two Debian version strings, as described in deb-version(7).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn split(version: &str) -> (u64, &str, &str) {
+        let (epoch, rest) = match version.split_once(':') {
+            Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+            None => (0, version),
+        };
+        let (upstream, revision) = rest.rsplit_once('-').unwrap_or((rest, ""));
+        (epoch, upstream, revision)
+    }
+    let (epoch_a, upstream_a, revision_a) = split(a);
+    let (epoch_b, upstream_b, revision_b) = split(b);
+    epoch_a
+        .cmp(&epoch_b)
+        .then_with(|| compare_version_part(upstream_a, upstream_b))
+        .then_with(|| compare_version_part(revision_a, revision_b))
+}
+
+fn compare_version_part(a: &str, b: &str) -> Ordering {
+    // Letters sort before non-letters, and `~` sorts before everything, even
+    // the end of the string.
+    fn order(c: Option<char>) -> i32 {
+        match c {
+            Some('~') => -1,
+            None => 0,
+            Some(c) if c.is_ascii_digit() => 0,
+            Some(c) if c.is_ascii_alphabetic() => c as i32,
+            Some(c) => c as i32 + 256,
+        }
+    }
+    let (mut a, mut b) = (a, b);
+    while !a.is_empty() || !b.is_empty() {
+        let a_len = a.find(|c: char| c.is_ascii_digit()).unwrap_or(a.len());
+        let b_len = b.find(|c: char| c.is_ascii_digit()).unwrap_or(b.len());
+        let (mut a_chars, mut b_chars) = (a[..a_len].chars(), b[..b_len].chars());
+        loop {
+            let (x, y) = (a_chars.next(), b_chars.next());
+            if x.is_none() && y.is_none() {
+                break;
+            }
+            match order(x).cmp(&order(y)) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+        let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+        let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+        let a_num = a[..a_len].trim_start_matches('0');
+        let b_num = b[..b_len].trim_start_matches('0');
+        match a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num)) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+        a = &a[a_len..];
+        b = &b[b_len..];
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, version: Option<(&str, &str)>) -> ImportedSymbol {
+        ImportedSymbol {
+            name: name.to_string(),
+            version: version.map(|(version, library)| (version.to_string(), library.to_string())),
+        }
+    }
+
+    #[test]
+    fn version_ordering() {
+        assert_eq!(compare_versions("2.34", "2.4"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0~rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare_versions("1:0.1", "2.0"), Ordering::Greater);
+        assert_eq!(compare_versions("1.0-1", "1.0-1ubuntu1"), Ordering::Less);
+        assert_eq!(compare_versions("1.0a", "1.0+"), Ordering::Less);
+        assert_eq!(compare_versions("2.2.5", "2.2.05"), Ordering::Equal);
+    }
+
+    #[test]
+    fn resolve_from_fixture_database() {
+        let admin_dir = tempfile::tempdir().unwrap();
+        let info_dir = admin_dir.path().join("info");
+        std::fs::create_dir(&info_dir).unwrap();
+        let write = |name: &str, contents: &str| std::fs::write(info_dir.join(name), contents);
+        // The libraries as the dynamic loader would find them.
+        let lib_dir = tempfile::tempdir().unwrap();
+        let lib_dir = lib_dir.path();
+        let library_paths: BTreeMap<String, PathBuf> = [
+            ("libc.so.6", "x86_64-linux-gnu"),
+            ("libm.so.6", "x86_64-linux-gnu"),
+            ("libfoo.so.1", ""),
+            ("libbar-0.4.so", ""),
+            ("libqux.so.3", ""),
+        ]
+        .map(|(soname, dir)| (soname.to_string(), lib_dir.join(dir).join(soname)))
+        .into();
+        let list = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| format!("{}\n", lib_dir.join(path).display()))
+                .collect::<String>()
+        };
+        // A library with a symbols file, installed for two architectures, and
+        // a multilib package that sorts first but contains a different file
+        // with the same name.
+        write(
+            "libc6:amd64.list",
+            &format!(
+                "/.\n{}",
+                list(&["x86_64-linux-gnu/libc.so.6", "x86_64-linux-gnu/libm.so.6"])
+            ),
+        )
+        .unwrap();
+        write("libc6:i386.list", &list(&["i386-linux-gnu/libc.so.6"])).unwrap();
+        write("libc6-i386.list", &list(&["lib32/libc.so.6"])).unwrap();
+        write(
+            "libc6:amd64.symbols",
+            "libc.so.6 libc6 #MINVER#\n\
+             * Build-Depends-Package: libc-dev\n \
+             malloc@GLIBC_2.2.5 2.2.5\n \
+             __libc_start_main@GLIBC_2.34 2.34\n \
+             unused@GLIBC_2.38 2.38\n\
+             libm.so.6 libc6 #MINVER#\n \
+             sqrt@GLIBC_2.2.5 2.2.5\n",
+        )
+        .unwrap();
+        // A library with only a shlibs file.
+        write("libfoo1.list", &list(&["libfoo.so.1"])).unwrap();
+        write(
+            "libfoo1.shlibs",
+            "udeb: libfoo 1 libfoo1-udeb\nlibfoo 1 libfoo1 (>= 1.2)\n",
+        )
+        .unwrap();
+        // A library with neither.
+        write("libbar0.list", &list(&["libbar-0.4.so"])).unwrap();
+        // A library in both an unqualified and an arch-qualified package.
+        write("libqux3.list", &list(&["libqux.so.3"])).unwrap();
+        write("libqux3t64:amd64.list", &list(&["libqux.so.3"])).unwrap();
+
+        let info = DynamicInfo {
+            needed: [
+                "libc.so.6",
+                "libm.so.6",
+                "libfoo.so.1",
+                "libbar-0.4.so",
+                "libqux.so.3",
+                "libmissing.so.2",
+            ]
+            .map(String::from)
+            .to_vec(),
+            imported_symbols: vec![
+                symbol("malloc", Some(("GLIBC_2.2.5", "libc.so.6"))),
+                symbol("__libc_start_main", Some(("GLIBC_2.34", "libc.so.6"))),
+                symbol("sqrt", Some(("GLIBC_2.2.5", "libm.so.6"))),
+                symbol("foo_init", None),
+            ],
+            ..Default::default()
+        };
+        let dependencies =
+            resolve_dependencies(&info, &library_paths, admin_dir.path(), "amd64").unwrap();
+        assert_eq!(
+            dependencies,
+            vec![
+                "libc6 (>= 2.34)",
+                "libfoo1 (>= 1.2)",
+                "libbar0",
+                "libqux3t64"
+            ]
+        );
+    }
+
+    #[test]
+    fn admin_dir_setting_overrides_default() {
+        assert_eq!(
+            dpkg_admin_dir(Some(Path::new("/srv/sysroot/var/lib/dpkg"))),
+            Path::new("/srv/sysroot/var/lib/dpkg")
+        );
+    }
+
+    #[test]
+    fn sonames() {
+        assert_eq!(split_soname("libc.so.6"), Some(("libc", "6")));
+        assert_eq!(split_soname("libfoo-1.2.so"), Some(("libfoo", "1.2")));
+        assert_eq!(split_soname("libfoo.so"), None);
+    }
+}
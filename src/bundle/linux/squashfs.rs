@@ -0,0 +1,861 @@
+// A writer for SquashFS 4.0 images, the read-only filesystem that AppImages
+// embed.  An image is laid out like:
+//
+// superblock          # 96 bytes: sizes, flags and the offsets of the tables below
+// data blocks         # File contents, in blocks of BLOCK_SIZE, each compressed
+// fragment blocks     #   separately; small files are packed into shared fragments
+// inode table         # Metadata blocks with an inode per file, directory and symlink
+// directory table     # Metadata blocks listing the entries of each directory
+// fragment table      # The location and size of each fragment block
+// id table            # The uids and gids used by inodes (only root, here)
+//
+// Metadata blocks hold up to 8 KiB of data, compressed unless that doesn't
+// make them smaller, and are prefixed with a 16-bit header giving their size.
+// Inodes and directory listings are referenced by the offset of their
+// metadata block in the table, and their offset within the uncompressed block.
+//
+// Images are reproducible: entries are written in sorted order, all files are
+// owned by root with normalized permissions, and timestamps are clamped to
+// SOURCE_DATE_EPOCH if it is set.  See
+// https://dr-emann.github.io/squashfs/squashfs.html for the format in detail.
+
+use super::common::{modification_time, normalized_mode};
+use crate::bundle::{Compression, common};
+use anyhow::Context;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: u32 = 0x7371_7368;
+const BLOCK_SIZE: usize = 128 * 1024;
+const BLOCK_LOG: u16 = 17;
+const METADATA_SIZE: usize = 8192;
+const SUPERBLOCK_SIZE: u64 = 96;
+/// Images are padded to a multiple of this size, so they can be loop mounted.
+const DEVICE_BLOCK_SIZE: u64 = 4096;
+
+// Compressor ids:
+const COMPRESSION_GZIP: u16 = 1;
+const COMPRESSION_XZ: u16 = 4;
+const COMPRESSION_ZSTD: u16 = 6;
+
+// Superblock flags:
+const FLAG_UNCOMPRESSED_INODES: u16 = 0x0001;
+const FLAG_UNCOMPRESSED_DATA: u16 = 0x0002;
+const FLAG_UNCOMPRESSED_FRAGMENTS: u16 = 0x0008;
+const FLAG_NO_XATTRS: u16 = 0x0200;
+const FLAG_UNCOMPRESSED_IDS: u16 = 0x0800;
+
+// Inode types:
+const INODE_DIR: u16 = 1;
+const INODE_FILE: u16 = 2;
+const INODE_SYMLINK: u16 = 3;
+const INODE_EXT_DIR: u16 = 8;
+const INODE_EXT_FILE: u16 = 9;
+
+// Markers for uncompressed blocks in block sizes and metadata headers:
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+
+const NO_FRAGMENT: u32 = 0xFFFF_FFFF;
+const NO_XATTR: u32 = 0xFFFF_FFFF;
+const NO_TABLE: u64 = 0xFFFF_FFFF_FFFF_FFFF;
+
+/// The maximum number of entries a directory header can cover.
+const MAX_DIR_HEADER_ENTRIES: usize = 256;
+
+/// Creates a SquashFS image at `dest` containing the contents of `src_dir`.
+pub fn create_squashfs(src_dir: &Path, dest: &Path, compression: Compression) -> crate::Result<()> {
+    let epoch = common::source_date_epoch()?;
+    let mut inode_count = 0;
+    let root = read_tree(src_dir, Vec::new(), epoch, &mut inode_count)?;
+    let output = common::create_file(dest)?;
+    let mut writer = ImageWriter::new(output, Compressor::new(compression), inode_count)?;
+    let root_entry = writer.write_node(&root, inode_count + 1)?;
+    let mtime = match epoch {
+        Some(epoch) => epoch as u32,
+        None => chrono::Utc::now().timestamp() as u32,
+    };
+    writer
+        .finish(root_entry.inode, mtime)
+        .with_context(|| format!("Failed to write {dest:?}"))?;
+    Ok(())
+}
+
+/// A file, directory or symlink to put in the image.
+struct Node {
+    name: Vec<u8>,
+    kind: NodeKind,
+    mode: u16,
+    mtime: u32,
+    inode_number: u32,
+}
+
+enum NodeKind {
+    Directory(Vec<Node>),
+    File(PathBuf, u64),
+    Symlink(Vec<u8>),
+}
+
+/// Reads the tree of files under `path`, sorted by name.  Inodes are numbered
+/// in the order they are written: each directory's entries come before the
+/// directory itself, so the root directory has the highest number.
+fn read_tree(
+    path: &Path,
+    name: Vec<u8>,
+    epoch: Option<u64>,
+    inode_count: &mut u32,
+) -> crate::Result<Node> {
+    let metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to read metadata of {path:?}"))?;
+    let mtime = modification_time(&metadata, epoch) as u32;
+    let (kind, mode) = if metadata.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        let mut children = Vec::new();
+        for entry in entries {
+            let child_name = os_str_bytes(entry.file_name().unwrap_or_default());
+            children.push(read_tree(&entry, child_name, epoch, inode_count)?);
+        }
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        (NodeKind::Directory(children), normalized_mode(&metadata))
+    } else if metadata.is_symlink() {
+        let target = std::fs::read_link(path)?;
+        (NodeKind::Symlink(os_str_bytes(target.as_os_str())), 0o777)
+    } else if metadata.is_file() {
+        (
+            NodeKind::File(path.to_path_buf(), metadata.len()),
+            normalized_mode(&metadata),
+        )
+    } else {
+        anyhow::bail!("{path:?} is not a regular file, directory or symlink");
+    };
+    *inode_count += 1;
+    Ok(Node {
+        name,
+        kind,
+        mode: mode as u16,
+        mtime,
+        inode_number: *inode_count,
+    })
+}
+
+#[cfg(unix)]
+fn os_str_bytes(name: &std::ffi::OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn os_str_bytes(name: &std::ffi::OsStr) -> Vec<u8> {
+    name.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// Compresses blocks with the image's compression algorithm.
+struct Compressor {
+    compression: Compression,
+}
+
+impl Compressor {
+    fn new(compression: Compression) -> Compressor {
+        Compressor { compression }
+    }
+
+    fn id(&self) -> u16 {
+        match self.compression {
+            Compression::Gzip | Compression::None => COMPRESSION_GZIP,
+            Compression::Xz => COMPRESSION_XZ,
+            Compression::Zstd => COMPRESSION_ZSTD,
+        }
+    }
+
+    fn flags(&self) -> u16 {
+        match self.compression {
+            Compression::None => {
+                FLAG_UNCOMPRESSED_INODES
+                    | FLAG_UNCOMPRESSED_DATA
+                    | FLAG_UNCOMPRESSED_FRAGMENTS
+                    | FLAG_UNCOMPRESSED_IDS
+            }
+            _ => 0,
+        }
+    }
+
+    /// Compresses `data`, returning `None` if that doesn't make it smaller
+    /// (in which case it is stored uncompressed).
+    fn compress(&self, data: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+        let compressed = match self.compression {
+            Compression::None => return Ok(None),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Xz => {
+                // The kernel's decompressor only allocates a dictionary as
+                // large as the block size.
+                let mut options = xz2::stream::LzmaOptions::new_preset(6)?;
+                options.dict_size(BLOCK_SIZE as u32);
+                let mut filters = xz2::stream::Filters::new();
+                filters.lzma2(&options);
+                let stream =
+                    xz2::stream::Stream::new_stream_encoder(&filters, xz2::stream::Check::Crc32)?;
+                let mut encoder = xz2::write::XzEncoder::new_stream(Vec::new(), stream);
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Compression::Zstd => zstd::bulk::compress(data, 15)?,
+        };
+        Ok((compressed.len() < data.len()).then_some(compressed))
+    }
+}
+
+/// A reference to an inode: the offset of its metadata block within the
+/// inode table, and its offset within that block once uncompressed.
+#[derive(Clone, Copy)]
+struct MetadataRef {
+    block: u32,
+    offset: u16,
+}
+
+impl MetadataRef {
+    fn inode_ref(self) -> u64 {
+        ((self.block as u64) << 16) | self.offset as u64
+    }
+}
+
+/// Accumulates data into a table of metadata blocks.
+struct MetadataWriter {
+    blocks: Vec<u8>,
+    current: Vec<u8>,
+}
+
+impl MetadataWriter {
+    fn new() -> MetadataWriter {
+        MetadataWriter {
+            blocks: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    /// The position that the next byte written will have.
+    fn position(&self) -> MetadataRef {
+        MetadataRef {
+            block: self.blocks.len() as u32,
+            offset: self.current.len() as u16,
+        }
+    }
+
+    fn write(&mut self, data: &[u8], compressor: &Compressor) -> crate::Result<()> {
+        self.current.extend_from_slice(data);
+        while self.current.len() >= METADATA_SIZE {
+            let rest = self.current.split_off(METADATA_SIZE);
+            self.flush(compressor)?;
+            self.current = rest;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, compressor: &Compressor) -> crate::Result<()> {
+        match compressor.compress(&self.current)? {
+            Some(compressed) => {
+                self.blocks
+                    .extend_from_slice(&(compressed.len() as u16).to_le_bytes());
+                self.blocks.extend_from_slice(&compressed);
+            }
+            None => {
+                let header = self.current.len() as u16 | METADATA_UNCOMPRESSED;
+                self.blocks.extend_from_slice(&header.to_le_bytes());
+                self.blocks.extend_from_slice(&self.current);
+            }
+        }
+        self.current.clear();
+        Ok(())
+    }
+
+    fn finish(mut self, compressor: &Compressor) -> crate::Result<Vec<u8>> {
+        if !self.current.is_empty() {
+            self.flush(compressor)?;
+        }
+        Ok(self.blocks)
+    }
+}
+
+/// An entry in a directory listing.
+struct DirEntry<'a> {
+    name: &'a [u8],
+    inode: MetadataRef,
+    inode_number: u32,
+    basic_type: u16,
+}
+
+struct ImageWriter<W: Write + Seek> {
+    output: W,
+    position: u64,
+    compressor: Compressor,
+    inode_count: u32,
+    inodes: MetadataWriter,
+    directories: MetadataWriter,
+    /// The location and (on-disk) size of each fragment block written.
+    fragments: Vec<(u64, u32)>,
+    fragment_buffer: Vec<u8>,
+}
+
+impl<W: Write + Seek> ImageWriter<W> {
+    fn new(mut output: W, compressor: Compressor, inode_count: u32) -> crate::Result<Self> {
+        // The superblock is filled in once everything else is written.
+        output.write_all(&[0; SUPERBLOCK_SIZE as usize])?;
+        Ok(ImageWriter {
+            output,
+            position: SUPERBLOCK_SIZE,
+            compressor,
+            inode_count,
+            inodes: MetadataWriter::new(),
+            directories: MetadataWriter::new(),
+            fragments: Vec::new(),
+            fragment_buffer: Vec::new(),
+        })
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> crate::Result<()> {
+        self.output.write_all(data)?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes a data block, returning its size as stored in block lists.
+    fn write_block(&mut self, data: &[u8]) -> crate::Result<u32> {
+        match self.compressor.compress(data)? {
+            Some(compressed) => {
+                self.write_raw(&compressed)?;
+                Ok(compressed.len() as u32)
+            }
+            None => {
+                self.write_raw(data)?;
+                Ok(data.len() as u32 | DATA_UNCOMPRESSED)
+            }
+        }
+    }
+
+    fn flush_fragment(&mut self) -> crate::Result<()> {
+        if self.fragment_buffer.is_empty() {
+            return Ok(());
+        }
+        let start = self.position;
+        let data = std::mem::take(&mut self.fragment_buffer);
+        let size = self.write_block(&data)?;
+        self.fragments.push((start, size));
+        Ok(())
+    }
+
+    /// Writes the node (and, for directories, everything under it), returning
+    /// the entry to list it under in its parent directory.
+    fn write_node<'a>(&mut self, node: &'a Node, parent_inode: u32) -> crate::Result<DirEntry<'a>> {
+        let (basic_type, inode) = match &node.kind {
+            NodeKind::Directory(children) => (
+                INODE_DIR,
+                self.write_directory(node, children, parent_inode)?,
+            ),
+            NodeKind::File(path, size) => (INODE_FILE, self.write_file(node, path, *size)?),
+            NodeKind::Symlink(target) => {
+                let mut inode = inode_header(INODE_SYMLINK, node);
+                inode.extend_from_slice(&1u32.to_le_bytes());
+                inode.extend_from_slice(&(target.len() as u32).to_le_bytes());
+                inode.extend_from_slice(target);
+                (INODE_SYMLINK, inode)
+            }
+        };
+        let position = self.inodes.position();
+        self.inodes.write(&inode, &self.compressor)?;
+        Ok(DirEntry {
+            name: &node.name,
+            inode: position,
+            inode_number: node.inode_number,
+            basic_type,
+        })
+    }
+
+    /// Writes the file's contents, returning its inode.
+    fn write_file(&mut self, node: &Node, path: &Path, size: u64) -> crate::Result<Vec<u8>> {
+        let mut file = File::open(path).with_context(|| format!("Failed to open {path:?}"))?;
+        let mut blocks_start = 0;
+        let mut block_sizes = Vec::new();
+        let mut fragment = (NO_FRAGMENT, 0);
+        if size > 0 && size < BLOCK_SIZE as u64 {
+            // Small files are packed together into fragment blocks.
+            let mut data = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut data)?;
+            if self.fragment_buffer.len() + data.len() > BLOCK_SIZE {
+                self.flush_fragment()?;
+            }
+            fragment = (
+                self.fragments.len() as u32,
+                self.fragment_buffer.len() as u32,
+            );
+            self.fragment_buffer.extend_from_slice(&data);
+        } else if size > 0 {
+            blocks_start = self.position;
+            let mut buffer = vec![0; BLOCK_SIZE];
+            let mut remaining = size;
+            while remaining > 0 {
+                let len = remaining.min(BLOCK_SIZE as u64) as usize;
+                file.read_exact(&mut buffer[..len])
+                    .with_context(|| format!("Failed to read {path:?}"))?;
+                block_sizes.push(self.write_block(&buffer[..len])?);
+                remaining -= len as u64;
+            }
+        }
+
+        let mut inode;
+        if blocks_start <= u32::MAX as u64 && size <= u32::MAX as u64 {
+            inode = inode_header(INODE_FILE, node);
+            inode.extend_from_slice(&(blocks_start as u32).to_le_bytes());
+            inode.extend_from_slice(&fragment.0.to_le_bytes());
+            inode.extend_from_slice(&fragment.1.to_le_bytes());
+            inode.extend_from_slice(&(size as u32).to_le_bytes());
+        } else {
+            inode = inode_header(INODE_EXT_FILE, node);
+            inode.extend_from_slice(&blocks_start.to_le_bytes());
+            inode.extend_from_slice(&size.to_le_bytes());
+            inode.extend_from_slice(&0u64.to_le_bytes()); // Sparse bytes
+            inode.extend_from_slice(&1u32.to_le_bytes()); // Link count
+            inode.extend_from_slice(&fragment.0.to_le_bytes());
+            inode.extend_from_slice(&fragment.1.to_le_bytes());
+            inode.extend_from_slice(&NO_XATTR.to_le_bytes());
+        }
+        for block_size in block_sizes {
+            inode.extend_from_slice(&block_size.to_le_bytes());
+        }
+        Ok(inode)
+    }
+
+    /// Writes the directory's children and its listing, returning its inode.
+    fn write_directory(
+        &mut self,
+        node: &Node,
+        children: &[Node],
+        parent_inode: u32,
+    ) -> crate::Result<Vec<u8>> {
+        let mut entries = Vec::with_capacity(children.len());
+        for child in children {
+            entries.push(self.write_node(child, node.inode_number)?);
+        }
+
+        let listing = directory_listing(&entries);
+        let start = self.directories.position();
+        self.directories.write(&listing, &self.compressor)?;
+        // The listing size includes the implied `.` and `..` entries.
+        let file_size = listing.len() as u32 + 3;
+        let subdirectories = entries
+            .iter()
+            .filter(|entry| entry.basic_type == INODE_DIR)
+            .count() as u32;
+
+        let mut inode;
+        if file_size <= u16::MAX as u32 {
+            inode = inode_header(INODE_DIR, node);
+            inode.extend_from_slice(&start.block.to_le_bytes());
+            inode.extend_from_slice(&(2 + subdirectories).to_le_bytes());
+            inode.extend_from_slice(&(file_size as u16).to_le_bytes());
+            inode.extend_from_slice(&start.offset.to_le_bytes());
+            inode.extend_from_slice(&parent_inode.to_le_bytes());
+        } else {
+            inode = inode_header(INODE_EXT_DIR, node);
+            inode.extend_from_slice(&(2 + subdirectories).to_le_bytes());
+            inode.extend_from_slice(&file_size.to_le_bytes());
+            inode.extend_from_slice(&start.block.to_le_bytes());
+            inode.extend_from_slice(&parent_inode.to_le_bytes());
+            inode.extend_from_slice(&0u16.to_le_bytes()); // Index count
+            inode.extend_from_slice(&start.offset.to_le_bytes());
+            inode.extend_from_slice(&NO_XATTR.to_le_bytes());
+        }
+        Ok(inode)
+    }
+
+    /// Writes a table of fixed-size entries as metadata blocks followed by an
+    /// index of the blocks' locations, returning the location of the index.
+    fn write_lookup_table(&mut self, entries: &[u8]) -> crate::Result<u64> {
+        let mut index = Vec::new();
+        for chunk in entries.chunks(METADATA_SIZE) {
+            index.extend_from_slice(&self.position.to_le_bytes());
+            let mut table = MetadataWriter::new();
+            table.write(chunk, &self.compressor)?;
+            let block = table.finish(&self.compressor)?;
+            self.write_raw(&block)?;
+        }
+        let start = self.position;
+        self.write_raw(&index)?;
+        Ok(start)
+    }
+
+    fn finish(mut self, root_inode: MetadataRef, mtime: u32) -> crate::Result<()> {
+        self.flush_fragment()?;
+
+        let inode_table_start = self.position;
+        let inodes = std::mem::replace(&mut self.inodes, MetadataWriter::new());
+        let inode_table = inodes.finish(&self.compressor)?;
+        self.write_raw(&inode_table)?;
+
+        let directory_table_start = self.position;
+        let directories = std::mem::replace(&mut self.directories, MetadataWriter::new());
+        let directory_table = directories.finish(&self.compressor)?;
+        self.write_raw(&directory_table)?;
+
+        let mut fragment_entries = Vec::new();
+        for &(start, size) in &self.fragments {
+            fragment_entries.extend_from_slice(&start.to_le_bytes());
+            fragment_entries.extend_from_slice(&size.to_le_bytes());
+            fragment_entries.extend_from_slice(&0u32.to_le_bytes());
+        }
+        let fragment_table_start = self.write_lookup_table(&fragment_entries)?;
+        // Everything is owned by root, so the only id is 0.
+        let id_table_start = self.write_lookup_table(&0u32.to_le_bytes())?;
+
+        let bytes_used = self.position;
+        let padding = bytes_used.next_multiple_of(DEVICE_BLOCK_SIZE) - bytes_used;
+        self.write_raw(&vec![0; padding as usize])?;
+
+        let mut superblock = Vec::with_capacity(SUPERBLOCK_SIZE as usize);
+        superblock.extend_from_slice(&MAGIC.to_le_bytes());
+        superblock.extend_from_slice(&self.inode_count.to_le_bytes());
+        superblock.extend_from_slice(&mtime.to_le_bytes());
+        superblock.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        superblock.extend_from_slice(&(self.fragments.len() as u32).to_le_bytes());
+        superblock.extend_from_slice(&self.compressor.id().to_le_bytes());
+        superblock.extend_from_slice(&BLOCK_LOG.to_le_bytes());
+        superblock.extend_from_slice(&(self.compressor.flags() | FLAG_NO_XATTRS).to_le_bytes());
+        superblock.extend_from_slice(&1u16.to_le_bytes()); // Id count
+        superblock.extend_from_slice(&4u16.to_le_bytes()); // Major version
+        superblock.extend_from_slice(&0u16.to_le_bytes()); // Minor version
+        superblock.extend_from_slice(&root_inode.inode_ref().to_le_bytes());
+        superblock.extend_from_slice(&bytes_used.to_le_bytes());
+        superblock.extend_from_slice(&id_table_start.to_le_bytes());
+        superblock.extend_from_slice(&NO_TABLE.to_le_bytes()); // Xattr table
+        superblock.extend_from_slice(&inode_table_start.to_le_bytes());
+        superblock.extend_from_slice(&directory_table_start.to_le_bytes());
+        superblock.extend_from_slice(&fragment_table_start.to_le_bytes());
+        superblock.extend_from_slice(&NO_TABLE.to_le_bytes()); // Export table
+        self.output.seek(SeekFrom::Start(0))?;
+        self.output.write_all(&superblock)?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+/// The fields shared by all inodes.
+fn inode_header(inode_type: u16, node: &Node) -> Vec<u8> {
+    let mut header = Vec::with_capacity(64);
+    header.extend_from_slice(&inode_type.to_le_bytes());
+    header.extend_from_slice(&(node.mode & 0o7777).to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes()); // uid index
+    header.extend_from_slice(&0u16.to_le_bytes()); // gid index
+    header.extend_from_slice(&node.mtime.to_le_bytes());
+    header.extend_from_slice(&node.inode_number.to_le_bytes());
+    header
+}
+
+/// Serializes the entries of a directory (which must be sorted by name).  A
+/// header is needed for each run of entries whose inodes are in the same
+/// metadata block, with inode numbers close enough to the header's.
+fn directory_listing(entries: &[DirEntry]) -> Vec<u8> {
+    let mut listing = Vec::new();
+    let mut index = 0;
+    while index < entries.len() {
+        let first = &entries[index];
+        let run = entries[index..]
+            .iter()
+            .take(MAX_DIR_HEADER_ENTRIES)
+            .take_while(|entry| {
+                let difference = entry.inode_number as i64 - first.inode_number as i64;
+                entry.inode.block == first.inode.block
+                    && (i16::MIN as i64..=i16::MAX as i64).contains(&difference)
+            })
+            .count();
+        listing.extend_from_slice(&(run as u32 - 1).to_le_bytes());
+        listing.extend_from_slice(&first.inode.block.to_le_bytes());
+        listing.extend_from_slice(&first.inode_number.to_le_bytes());
+        for entry in &entries[index..index + run] {
+            let difference = (entry.inode_number as i64 - first.inode_number as i64) as i16;
+            listing.extend_from_slice(&entry.inode.offset.to_le_bytes());
+            listing.extend_from_slice(&difference.to_le_bytes());
+            listing.extend_from_slice(&entry.basic_type.to_le_bytes());
+            listing.extend_from_slice(&(entry.name.len() as u16 - 1).to_le_bytes());
+            listing.extend_from_slice(entry.name);
+        }
+        index += run;
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Entry {
+        Directory(u16),
+        File(u16, Vec<u8>),
+        Symlink(Vec<u8>),
+    }
+
+    /// A minimal SquashFS reader, to check images by reading them back.
+    struct Image {
+        data: Vec<u8>,
+        compression: u16,
+        inodes: (Vec<u8>, HashMap<u32, usize>),
+        directories: (Vec<u8>, HashMap<u32, usize>),
+        fragments: Vec<(u64, u32)>,
+    }
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn decompress(compression: u16, data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        match compression {
+            COMPRESSION_GZIP => flate2::read::ZlibDecoder::new(data)
+                .read_to_end(&mut output)
+                .unwrap(),
+            COMPRESSION_XZ => xz2::read::XzDecoder::new(data)
+                .read_to_end(&mut output)
+                .unwrap(),
+            COMPRESSION_ZSTD => zstd::Decoder::new(data)
+                .unwrap()
+                .read_to_end(&mut output)
+                .unwrap(),
+            _ => panic!("unknown compression {compression}"),
+        };
+        output
+    }
+
+    impl Image {
+        fn open(data: Vec<u8>) -> Image {
+            assert_eq!(u32_at(&data, 0), MAGIC);
+            assert_eq!(data.len() % DEVICE_BLOCK_SIZE as usize, 0);
+            let compression = u16_at(&data, 20);
+            let fragment_count = u32_at(&data, 16) as usize;
+            let mut image = Image {
+                compression,
+                inodes: (Vec::new(), HashMap::new()),
+                directories: (Vec::new(), HashMap::new()),
+                fragments: Vec::new(),
+                data,
+            };
+            let inode_table_start = u64_at(&image.data, 64) as usize;
+            let directory_table_start = u64_at(&image.data, 72) as usize;
+            let fragment_table_start = u64_at(&image.data, 80) as usize;
+            image.inodes = image.read_metadata(inode_table_start, directory_table_start);
+            image.directories = image.read_metadata(directory_table_start, fragment_table_start);
+            for index in 0..fragment_count.div_ceil(METADATA_SIZE / 16) {
+                let block_start = u64_at(&image.data, fragment_table_start + index * 8) as usize;
+                let (entries, _) = image.read_metadata(block_start, block_start + 1);
+                for entry in entries.chunks(16) {
+                    image.fragments.push((u64_at(entry, 0), u32_at(entry, 8)));
+                }
+            }
+            image.fragments.truncate(fragment_count);
+            image
+        }
+
+        /// Decompresses the metadata blocks starting at `start` (up to the
+        /// first block starting at or after `end`), mapping the offset of each
+        /// block to the position of its data.
+        fn read_metadata(&self, start: usize, end: usize) -> (Vec<u8>, HashMap<u32, usize>) {
+            let mut output = Vec::new();
+            let mut blocks = HashMap::new();
+            let mut position = start;
+            while position < end {
+                blocks.insert((position - start) as u32, output.len());
+                let header = u16_at(&self.data, position);
+                let size = (header & !METADATA_UNCOMPRESSED) as usize;
+                let block = &self.data[position + 2..position + 2 + size];
+                if header & METADATA_UNCOMPRESSED != 0 {
+                    output.extend_from_slice(block);
+                } else {
+                    output.extend(decompress(self.compression, block));
+                }
+                position += 2 + size;
+            }
+            (output, blocks)
+        }
+
+        fn inode(&self, inode_ref: u64) -> &[u8] {
+            let start = self.inodes.1[&((inode_ref >> 16) as u32)];
+            &self.inodes.0[start + (inode_ref & 0xFFFF) as usize..]
+        }
+
+        fn data_block(&self, start: usize, size: u32) -> Vec<u8> {
+            let len = (size & !DATA_UNCOMPRESSED) as usize;
+            let block = &self.data[start..start + len];
+            if size & DATA_UNCOMPRESSED != 0 {
+                block.to_vec()
+            } else {
+                decompress(self.compression, block)
+            }
+        }
+
+        fn read_tree(&self, inode_ref: u64, path: String, tree: &mut BTreeMap<String, Entry>) {
+            let inode = self.inode(inode_ref);
+            let mode = u16_at(inode, 2);
+            assert_eq!(u16_at(inode, 4), 0, "uid index");
+            match u16_at(inode, 0) {
+                INODE_DIR | INODE_EXT_DIR => {
+                    let (block, offset, size) = if u16_at(inode, 0) == INODE_DIR {
+                        (
+                            u32_at(inode, 16),
+                            u16_at(inode, 26),
+                            u16_at(inode, 24) as usize,
+                        )
+                    } else {
+                        (
+                            u32_at(inode, 24),
+                            u16_at(inode, 34),
+                            u32_at(inode, 20) as usize,
+                        )
+                    };
+                    tree.insert(path.clone(), Entry::Directory(mode));
+                    let start = self.directories.1[&block] + offset as usize;
+                    let listing = &self.directories.0[start..start + size - 3];
+                    let mut position = 0;
+                    let mut previous_name = Vec::new();
+                    while position < listing.len() {
+                        let count = u32_at(listing, position) + 1;
+                        let inode_block = u32_at(listing, position + 4) as u64;
+                        position += 12;
+                        for _ in 0..count {
+                            let inode_offset = u16_at(listing, position) as u64;
+                            let name_len = u16_at(listing, position + 6) as usize + 1;
+                            let name = listing[position + 8..position + 8 + name_len].to_vec();
+                            assert!(name > previous_name, "entries must be sorted");
+                            position += 8 + name_len;
+                            let child_path =
+                                format!("{path}/{}", String::from_utf8(name.clone()).unwrap());
+                            self.read_tree((inode_block << 16) | inode_offset, child_path, tree);
+                            previous_name = name;
+                        }
+                    }
+                }
+                INODE_FILE => {
+                    let blocks_start = u32_at(inode, 16) as usize;
+                    let fragment = u32_at(inode, 20);
+                    let fragment_offset = u32_at(inode, 24) as usize;
+                    let size = u32_at(inode, 28) as usize;
+                    let block_count = if fragment == NO_FRAGMENT {
+                        size.div_ceil(BLOCK_SIZE)
+                    } else {
+                        size / BLOCK_SIZE
+                    };
+                    let mut contents = Vec::new();
+                    let mut position = blocks_start;
+                    for index in 0..block_count {
+                        let block_size = u32_at(inode, 32 + index * 4);
+                        contents.extend(self.data_block(position, block_size));
+                        position += (block_size & !DATA_UNCOMPRESSED) as usize;
+                    }
+                    if fragment != NO_FRAGMENT {
+                        let (start, block_size) = self.fragments[fragment as usize];
+                        let block = self.data_block(start as usize, block_size);
+                        let tail = size - contents.len();
+                        contents.extend(&block[fragment_offset..fragment_offset + tail]);
+                    }
+                    assert_eq!(contents.len(), size);
+                    tree.insert(path, Entry::File(mode, contents));
+                }
+                INODE_SYMLINK => {
+                    let len = u32_at(inode, 20) as usize;
+                    tree.insert(path, Entry::Symlink(inode[24..24 + len].to_vec()));
+                }
+                other => panic!("unexpected inode type {other}"),
+            }
+        }
+
+        fn tree(&self) -> BTreeMap<String, Entry> {
+            let mut tree = BTreeMap::new();
+            self.read_tree(u64_at(&self.data, 32), String::new(), &mut tree);
+            tree
+        }
+    }
+
+    /// Deterministic pseudo-random (so incompressible) data.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let src_dir = temp_dir.path().join("AppDir");
+        let mut expected = BTreeMap::new();
+        expected.insert(String::new(), Entry::Directory(0o755));
+        let mut add_file = |path: &str, contents: Vec<u8>| {
+            let full_path = src_dir.join(path);
+            std::fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            std::fs::write(&full_path, &contents).unwrap();
+            expected.insert(format!("/{path}"), Entry::File(0o644, contents));
+        };
+        add_file("empty", Vec::new());
+        add_file("usr/share/small.txt", b"hello world\n".to_vec());
+        add_file("usr/share/noise", noise(BLOCK_SIZE * 2 + 1000));
+        add_file("usr/share/zeros", vec![0; BLOCK_SIZE * 3]);
+        // Enough entries to need several directory headers and metadata blocks.
+        for index in 0..600 {
+            add_file(
+                &format!("usr/lib/many/file-{index:03}"),
+                format!("{index}").into_bytes(),
+            );
+        }
+        for dir in ["/usr", "/usr/share", "/usr/lib", "/usr/lib/many"] {
+            expected.insert(dir.to_string(), Entry::Directory(0o755));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let binary = src_dir.join("usr/bin/app");
+            std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+            std::fs::write(&binary, b"#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o700)).unwrap();
+            expected.insert("/usr/bin".to_string(), Entry::Directory(0o755));
+            expected.insert(
+                "/usr/bin/app".to_string(),
+                Entry::File(0o755, b"#!/bin/sh\n".to_vec()),
+            );
+            std::os::unix::fs::symlink("usr/bin/app", src_dir.join("AppRun")).unwrap();
+            expected.insert(
+                "/AppRun".to_string(),
+                Entry::Symlink(b"usr/bin/app".to_vec()),
+            );
+        }
+
+        for compression in [
+            Compression::Gzip,
+            Compression::Xz,
+            Compression::Zstd,
+            Compression::None,
+        ] {
+            let image_path = temp_dir.path().join("image.squashfs");
+            create_squashfs(&src_dir, &image_path, compression).unwrap();
+            let image = Image::open(std::fs::read(&image_path).unwrap());
+            assert_eq!(u32_at(&image.data, 4) as usize, expected.len());
+            assert_eq!(image.tree(), expected, "{compression:?}");
+        }
+    }
+}
@@ -0,0 +1,373 @@
+// Support for packaging daemons: systemd units are installed into
+// /usr/lib/systemd/system, and sysusers.d(5) and tmpfiles.d(5) snippets into
+// /usr/lib/sysusers.d and /usr/lib/tmpfiles.d.  For deb packages, we generate
+// the same maintainer script logic that dh_installsystemd, dh_installsysusers
+// and dh_installtmpfiles would; for rpm packages, the scriptlets that the
+// %systemd_post, %systemd_preun and %systemd_postun_with_restart macros expand
+// to.
+
+use crate::bundle::{Settings, common};
+use anyhow::Context;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The file extensions of the unit types that can be installed.
+const UNIT_EXTENSIONS: &[&str] = &[
+    "service",
+    "socket",
+    "timer",
+    "path",
+    "target",
+    "mount",
+    "automount",
+    "slice",
+];
+
+/// The maintainer script arguments under which dpkg is configuring the
+/// package (or rolling back a failed upgrade or removal).
+const DEB_CONFIGURE_CONDITION: &str = r#"[ "$1" = "configure" ] || [ "$1" = "abort-upgrade" ] || [ "$1" = "abort-deconfigure" ] || [ "$1" = "abort-remove" ]"#;
+
+/// The systemd-related files installed by a package.
+#[derive(Debug, Default)]
+pub struct SystemdFiles {
+    /// The file names of all installed units.
+    units: Vec<String>,
+    /// The units that should be enabled and started: those that have an
+    /// `[Install]` section and aren't templates.
+    enabled_units: Vec<String>,
+    sysusers: Vec<String>,
+    tmpfiles: Vec<String>,
+    enable: bool,
+    start: bool,
+}
+
+impl SystemdFiles {
+    /// Copies the bundle's systemd units, sysusers.d and tmpfiles.d files into
+    /// the `data_dir`.
+    pub fn install(settings: &Settings, data_dir: &Path) -> crate::Result<SystemdFiles> {
+        let mut files = SystemdFiles {
+            enable: settings.linux_systemd_enable(),
+            start: settings.linux_systemd_start(),
+            ..SystemdFiles::default()
+        };
+        let unit_dir = data_dir.join("usr/lib/systemd/system");
+        for src in settings.linux_systemd_units() {
+            let src = src?;
+            let name = file_name(&src)?;
+            let extension = Path::new(&name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("");
+            if !UNIT_EXTENSIONS.contains(&extension) {
+                anyhow::bail!(
+                    "{src:?} is not a systemd unit (expected one of the extensions {UNIT_EXTENSIONS:?})"
+                );
+            }
+            let contents = std::fs::read_to_string(&src)
+                .with_context(|| format!("Failed to read systemd unit {src:?}"))?;
+            let is_template = name.contains("@.");
+            let has_install_section = contents.lines().any(|line| line.trim() == "[Install]");
+            if has_install_section && !is_template {
+                files.enabled_units.push(name.clone());
+            }
+            common::copy_file(&src, &unit_dir.join(&name))?;
+            files.units.push(name);
+        }
+        for (paths, dir, names) in [
+            (
+                settings.linux_sysusers(),
+                "usr/lib/sysusers.d",
+                &mut files.sysusers,
+            ),
+            (
+                settings.linux_tmpfiles(),
+                "usr/lib/tmpfiles.d",
+                &mut files.tmpfiles,
+            ),
+        ] {
+            for src in paths {
+                let src = src?;
+                let name = file_name(&src)?;
+                if !name.ends_with(".conf") {
+                    anyhow::bail!("{src:?} must have a .conf extension to be installed in /{dir}");
+                }
+                common::copy_file(&src, &data_dir.join(dir).join(&name))?;
+                names.push(name);
+            }
+        }
+        Ok(files)
+    }
+
+    /// The commands to run in a deb package's `postinst` script.
+    pub fn deb_postinst(&self) -> String {
+        let mut script = String::new();
+        if !self.sysusers.is_empty() {
+            writeln!(
+                script,
+                "if {DEB_CONFIGURE_CONDITION} ; then\n\
+                 \tif command -v systemd-sysusers >/dev/null 2>&1; then\n\
+                 \t\tsystemd-sysusers ${{DPKG_ROOT:+--root=\"$DPKG_ROOT\"}} {}\n\
+                 \tfi\n\
+                 fi",
+                quoted(&self.sysusers)
+            )
+            .unwrap();
+        }
+        if !self.tmpfiles.is_empty() {
+            writeln!(
+                script,
+                "if {DEB_CONFIGURE_CONDITION} ; then\n\
+                 \tif command -v systemd-tmpfiles >/dev/null 2>&1; then\n\
+                 \t\tsystemd-tmpfiles ${{DPKG_ROOT:+--root=\"$DPKG_ROOT\"}} --create {} || true\n\
+                 \tfi\n\
+                 fi",
+                quoted(&self.tmpfiles)
+            )
+            .unwrap();
+        }
+        if self.enable {
+            for unit in &self.enabled_units {
+                writeln!(
+                    script,
+                    "if {DEB_CONFIGURE_CONDITION} ; then\n\
+                     \t# This will only remove masks created by deb-systemd-helper on package removal.\n\
+                     \tdeb-systemd-helper unmask '{unit}' >/dev/null || true\n\
+                     \n\
+                     \t# was-enabled defaults to true, so new installations run enable.\n\
+                     \tif deb-systemd-helper --quiet was-enabled '{unit}'; then\n\
+                     \t\t# Enables the unit on first installation, creates new\n\
+                     \t\t# symlinks on upgrades if the unit file has changed.\n\
+                     \t\tdeb-systemd-helper enable '{unit}' >/dev/null || true\n\
+                     \telse\n\
+                     \t\t# Update the statefile to add new symlinks (if any), which need to be\n\
+                     \t\t# cleaned up on purge. Also remove old symlinks.\n\
+                     \t\tdeb-systemd-helper update-state '{unit}' >/dev/null || true\n\
+                     \tfi\n\
+                     fi"
+                )
+                .unwrap();
+            }
+        }
+        if !self.units.is_empty() {
+            write!(
+                script,
+                "if {DEB_CONFIGURE_CONDITION} ; then\n\
+                 \tif [ -z \"${{DPKG_ROOT:-}}\" ] && [ -d /run/systemd/system ]; then\n\
+                 \t\tsystemctl --system daemon-reload >/dev/null || true\n"
+            )
+            .unwrap();
+            if self.start && !self.enabled_units.is_empty() {
+                write!(
+                    script,
+                    "\t\tif [ -n \"$2\" ]; then\n\
+                     \t\t\t_dh_action=restart\n\
+                     \t\telse\n\
+                     \t\t\t_dh_action=start\n\
+                     \t\tfi\n\
+                     \t\tdeb-systemd-invoke $_dh_action {} >/dev/null || true\n",
+                    quoted(&self.enabled_units)
+                )
+                .unwrap();
+            }
+            writeln!(script, "\tfi\nfi").unwrap();
+        }
+        script
+    }
+
+    /// The commands to run in a deb package's `prerm` script.
+    pub fn deb_prerm(&self) -> String {
+        if !self.start || self.enabled_units.is_empty() {
+            return String::new();
+        }
+        format!(
+            "if [ -z \"${{DPKG_ROOT:-}}\" ] && [ \"$1\" = remove ] && [ -d /run/systemd/system ] ; then\n\
+             \tdeb-systemd-invoke stop {} >/dev/null || true\n\
+             fi\n",
+            quoted(&self.enabled_units)
+        )
+    }
+
+    /// The commands to run in a deb package's `postrm` script.
+    pub fn deb_postrm(&self) -> String {
+        if self.units.is_empty() {
+            return String::new();
+        }
+        let mut script = String::from(
+            "if [ -d /run/systemd/system ] && [ \"$1\" = remove ]; then\n\
+             \tsystemctl --system daemon-reload >/dev/null || true\n\
+             fi\n",
+        );
+        if self.enable && !self.enabled_units.is_empty() {
+            let units = quoted(&self.enabled_units);
+            write!(
+                script,
+                "if [ \"$1\" = \"remove\" ]; then\n\
+                 \tif [ -x \"/usr/bin/deb-systemd-helper\" ]; then\n\
+                 \t\tdeb-systemd-helper mask {units} >/dev/null || true\n\
+                 \tfi\n\
+                 fi\n\
+                 if [ \"$1\" = \"purge\" ]; then\n\
+                 \tif [ -x \"/usr/bin/deb-systemd-helper\" ]; then\n\
+                 \t\tdeb-systemd-helper purge {units} >/dev/null || true\n\
+                 \t\tdeb-systemd-helper unmask {units} >/dev/null || true\n\
+                 \tfi\n\
+                 fi\n"
+            )
+            .unwrap();
+        }
+        script
+    }
+
+    /// The body of an rpm package's `%post` scriptlet.
+    pub fn rpm_post(&self) -> String {
+        let mut script = String::new();
+        if !self.sysusers.is_empty() {
+            writeln!(
+                script,
+                "systemd-sysusers {} >/dev/null 2>&1 || :",
+                quoted(&self.sysusers)
+            )
+            .unwrap();
+        }
+        if !self.tmpfiles.is_empty() {
+            writeln!(
+                script,
+                "systemd-tmpfiles --create {} >/dev/null 2>&1 || :",
+                quoted(&self.tmpfiles)
+            )
+            .unwrap();
+        }
+        if self.enable && !self.enabled_units.is_empty() {
+            writeln!(
+                script,
+                "if [ $1 -eq 1 ] ; then\n\
+                 \t# Initial installation\n\
+                 \tsystemctl --no-reload preset {} >/dev/null 2>&1 || :\n\
+                 fi",
+                quoted(&self.enabled_units)
+            )
+            .unwrap();
+        }
+        script
+    }
+
+    /// The body of an rpm package's `%preun` scriptlet.
+    pub fn rpm_preun(&self) -> String {
+        if self.enabled_units.is_empty() {
+            return String::new();
+        }
+        format!(
+            "if [ $1 -eq 0 ] ; then\n\
+             \t# Package removal, not upgrade\n\
+             \tsystemctl --no-reload disable --now {} >/dev/null 2>&1 || :\n\
+             fi\n",
+            quoted(&self.enabled_units)
+        )
+    }
+
+    /// The body of an rpm package's `%postun` scriptlet.
+    pub fn rpm_postun(&self) -> String {
+        if self.units.is_empty() {
+            return String::new();
+        }
+        let mut script = String::from("systemctl daemon-reload >/dev/null 2>&1 || :\n");
+        if self.start && !self.enabled_units.is_empty() {
+            write!(
+                script,
+                "if [ $1 -ge 1 ] ; then\n\
+                 \t# Package upgrade, not uninstall\n\
+                 \tsystemctl try-restart {} >/dev/null 2>&1 || :\n\
+                 fi\n",
+                quoted(&self.enabled_units)
+            )
+            .unwrap();
+        }
+        script
+    }
+}
+
+fn file_name(path: &Path) -> crate::Result<String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid file name: {path:?}"))?;
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || ":-_.@\\".contains(c));
+    if !valid {
+        anyhow::bail!("{name:?} is not a valid systemd file name");
+    }
+    Ok(name.to_string())
+}
+
+/// Joins the given names into a list of single-quoted shell words.
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{name}'"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> SystemdFiles {
+        SystemdFiles {
+            units: vec!["foo.service".into(), "foo@.service".into()],
+            enabled_units: vec!["foo.service".into()],
+            sysusers: vec!["foo.conf".into()],
+            tmpfiles: vec![],
+            enable: true,
+            start: true,
+        }
+    }
+
+    #[test]
+    fn deb_scripts() {
+        let files = files();
+        let postinst = files.deb_postinst();
+        assert!(
+            postinst.contains("systemd-sysusers ${DPKG_ROOT:+--root=\"$DPKG_ROOT\"} 'foo.conf'")
+        );
+        assert!(!postinst.contains("systemd-tmpfiles"));
+        assert!(postinst.contains("deb-systemd-helper enable 'foo.service'"));
+        assert!(!postinst.contains("foo@.service"));
+        assert!(postinst.contains("deb-systemd-invoke $_dh_action 'foo.service'"));
+        assert!(
+            files
+                .deb_prerm()
+                .contains("deb-systemd-invoke stop 'foo.service'")
+        );
+        assert!(
+            files
+                .deb_postrm()
+                .contains("deb-systemd-helper purge 'foo.service'")
+        );
+
+        let no_start = SystemdFiles {
+            start: false,
+            ..self::files()
+        };
+        assert!(!no_start.deb_postinst().contains("deb-systemd-invoke"));
+        assert!(no_start.deb_prerm().is_empty());
+
+        assert!(SystemdFiles::default().deb_postinst().is_empty());
+        assert!(SystemdFiles::default().deb_postrm().is_empty());
+    }
+
+    #[test]
+    fn rpm_scriptlets() {
+        let files = files();
+        assert!(
+            files
+                .rpm_post()
+                .contains("systemctl --no-reload preset 'foo.service'")
+        );
+        assert!(files.rpm_preun().contains("disable --now 'foo.service'"));
+        assert!(files.rpm_postun().contains("try-restart 'foo.service'"));
+        assert!(SystemdFiles::default().rpm_post().is_empty());
+        assert!(SystemdFiles::default().rpm_postun().is_empty());
+    }
+}
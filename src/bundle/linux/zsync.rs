@@ -0,0 +1,200 @@
+// Generates the .zsync control files that zsync (and AppImageUpdate) use to
+// download only the changed parts of a file.  A control file is a set of
+// headers followed by a checksum for each block of the file: a weak rolling
+// checksum to find candidate blocks in the old file, and a truncated MD4
+// checksum to confirm them.  The checksum lengths are chosen the same way as
+// zsyncmake 0.6.2 chooses them, so the files are interchangeable.  See
+// http://zsync.moria.org.uk/paper/ for the details.
+
+use crate::bundle::common;
+use anyhow::Context;
+use md4::{Digest, Md4};
+use sha1::Sha1;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+const ZSYNC_VERSION: &str = "0.6.2";
+
+/// Writes a zsync control file for the file at `path` to `<path>.zsync`,
+/// pointing at `url` (which may be relative to the control file's URL), and
+/// returns the path of the control file.
+pub fn write_zsync_file(path: &Path, url: &str) -> crate::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{path:?} has no file name"))?
+        .to_string_lossy();
+    let mtime = match common::source_date_epoch()? {
+        Some(timestamp) => timestamp as i64,
+        None => {
+            let modified = std::fs::metadata(path)?.modified()?;
+            chrono::DateTime::<chrono::Utc>::from(modified).timestamp()
+        }
+    };
+    let mtime = chrono::DateTime::from_timestamp(mtime, 0)
+        .ok_or_else(|| anyhow::anyhow!("Modification time of {path:?} is out of range"))?;
+    let file = File::open(path).with_context(|| format!("Failed to open {path:?}"))?;
+    let length = file.metadata()?.len();
+    let control = zsync_control(
+        BufReader::new(file),
+        length,
+        &file_name,
+        &mtime.format("%a, %d %b %Y %H:%M:%S %z").to_string(),
+        url,
+    )?;
+    let zsync_path = path.with_file_name(format!("{file_name}.zsync"));
+    std::fs::write(&zsync_path, control)
+        .with_context(|| format!("Failed to write {zsync_path:?}"))?;
+    Ok(zsync_path)
+}
+
+/// Builds a zsync control file for the `length` bytes read from `reader`.
+fn zsync_control<R: Read>(
+    mut reader: R,
+    length: u64,
+    file_name: &str,
+    mtime: &str,
+    url: &str,
+) -> crate::Result<Vec<u8>> {
+    // zsyncmake uses larger blocks for files of 100MB or more.
+    let block_size: usize = if length >= 100_000_000 { 4096 } else { 2048 };
+    let (seq_matches, rsum_len, checksum_len) = hash_lengths(length, block_size as u64);
+    let mut sha1 = Sha1::new();
+    let mut block_sums = Vec::new();
+    let mut block = vec![0; block_size];
+    let mut read_length = 0;
+    loop {
+        let read = read_block(&mut reader, &mut block)?;
+        if read == 0 {
+            break;
+        }
+        sha1.update(&block[..read]);
+        read_length += read as u64;
+        // The last block is padded with zeros.
+        block[read..].fill(0);
+        let (rsum, checksum) = block_checksums(&block);
+        // The trailing bytes of the rolling checksum are the most useful.
+        block_sums.extend_from_slice(&rsum[4 - rsum_len..]);
+        block_sums.extend_from_slice(&checksum[..checksum_len]);
+    }
+    if read_length != length {
+        anyhow::bail!("File changed size while its zsync file was being generated");
+    }
+
+    let mut control = Vec::new();
+    write!(
+        control,
+        "zsync: {ZSYNC_VERSION}\n\
+         Filename: {file_name}\n\
+         MTime: {mtime}\n\
+         Blocksize: {block_size}\n\
+         Length: {length}\n\
+         Hash-Lengths: {seq_matches},{rsum_len},{checksum_len}\n\
+         URL: {url}\n\
+         SHA-1: {:x}\n\
+         \n",
+        sha1.finalize()
+    )?;
+    control.extend_from_slice(&block_sums);
+    Ok(control)
+}
+
+/// Fills `block` from `reader`, returning the number of bytes read, which is
+/// less than the block's length only at the end of the file.
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+/// Returns the rolling checksum (in network byte order) and the MD4 checksum
+/// of a block.
+fn block_checksums(block: &[u8]) -> ([u8; 4], [u8; 16]) {
+    let (mut a, mut b) = (0u16, 0u16);
+    for (index, &byte) in block.iter().enumerate() {
+        let remaining = (block.len() - index) as u16;
+        a = a.wrapping_add(byte.into());
+        b = b.wrapping_add(remaining.wrapping_mul(byte.into()));
+    }
+    let mut rsum = [0; 4];
+    rsum[..2].copy_from_slice(&a.to_be_bytes());
+    rsum[2..].copy_from_slice(&b.to_be_bytes());
+    (rsum, Md4::digest(block).into())
+}
+
+/// Returns the number of consecutive blocks that must match, and the number
+/// of bytes of the rolling and MD4 checksums to store per block, for a file
+/// of `length` bytes: just enough to make false matches unlikely.
+fn hash_lengths(length: u64, block_size: u64) -> (usize, usize, usize) {
+    let seq_matches = if length > block_size { 2 } else { 1 };
+    let log2 = |value: f64| value.ln() / 2f64.ln();
+    let blocks = (1 + length / block_size) as f64;
+    let length = length.max(1) as f64;
+    let rsum_len = ((log2(length) + log2(block_size as f64) - 8.6) / seq_matches as f64 / 8.0)
+        .ceil()
+        .clamp(2.0, 4.0) as usize;
+    let checksum_len = ((20.0 + log2(length) + log2(blocks)) / seq_matches as f64 / 8.0).ceil();
+    let min_checksum_len = ((7.9 + (20.0 + log2(blocks))) / 8.0).floor();
+    let checksum_len = checksum_len.max(min_checksum_len).min(16.0) as usize;
+    (seq_matches, rsum_len, checksum_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_checksum() {
+        let (rsum, _) = block_checksums(&[1, 2, 3]);
+        // a = 1 + 2 + 3, b = 3 * 1 + 2 * 2 + 1 * 3
+        assert_eq!(rsum, [0, 6, 0, 10]);
+        let (_, checksum) = block_checksums(b"");
+        assert_eq!(
+            checksum,
+            [
+                0x31, 0xd6, 0xcf, 0xe0, 0xd1, 0x6a, 0xe9, 0x31, 0xb7, 0x3c, 0x59, 0xd7, 0xe0, 0xc0,
+                0x89, 0xc0
+            ]
+        );
+    }
+
+    #[test]
+    fn control_file() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7) as u8).collect();
+        let control = zsync_control(
+            data.as_slice(),
+            data.len() as u64,
+            "App.AppImage",
+            "Thu, 01 Jan 1970 00:00:00 +0000",
+            "App.AppImage",
+        )
+        .unwrap();
+        let header_end = control.windows(2).position(|w| w == b"\n\n").unwrap() + 2;
+        let header = std::str::from_utf8(&control[..header_end]).unwrap();
+        assert!(header.starts_with("zsync: 0.6.2\nFilename: App.AppImage\n"));
+        assert!(header.contains("\nBlocksize: 2048\nLength: 5000\nHash-Lengths: 2,2,3\n"));
+        assert!(header.contains(&format!("\nSHA-1: {:x}\n", Sha1::digest(&data))));
+        // Three blocks, the last one padded.
+        assert_eq!(control.len() - header_end, 3 * (2 + 3));
+        let mut last_block = data[4096..].to_vec();
+        last_block.resize(2048, 0);
+        let (rsum, checksum) = block_checksums(&last_block);
+        let mut expected = rsum[2..].to_vec();
+        expected.extend_from_slice(&checksum[..3]);
+        assert_eq!(&control[control.len() - 5..], expected.as_slice());
+    }
+
+    #[test]
+    fn hash_lengths_match_zsyncmake() {
+        assert_eq!(hash_lengths(1000, 2048), (1, 2, 4));
+        assert_eq!(hash_lengths(50_000_000, 2048), (2, 2, 5));
+        assert_eq!(hash_lengths(500_000_000, 4096), (2, 3, 5));
+    }
+}
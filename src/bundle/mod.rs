@@ -7,9 +7,15 @@ mod osx_bundle;
 mod settings;
 mod wxsmsi_bundle;
 
-pub use self::common::{print_error, print_finished};
+pub use self::common::{print_error, print_finished, print_lint_finding, print_verified};
 use self::linux::appimage_bundle;
-pub use self::settings::{BuildArtifact, PackageType, Settings};
+pub use self::linux::appimage_signature::verify_appimage;
+pub use self::linux::apt_repo::update_apt_repository;
+pub use self::linux::deb_lint::{Severity, lint_deb};
+pub use self::settings::{
+    AppRun, BuildArtifact, Compression, DebScript, FileAssociation, FileAssociationRole, LinuxFile,
+    PackageType, Settings,
+};
 use crate::bundle::linux::{deb_bundle, rpm_bundle};
 use std::path::PathBuf;
 
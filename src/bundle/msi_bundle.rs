@@ -1,8 +1,7 @@
 use super::common;
-use super::settings::Settings;
+use super::settings::{FileAssociationRole, Settings};
 use anyhow::Context;
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::ffi::OsStr;
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
@@ -100,6 +99,12 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
     create_media_table(&mut package, &cabinets)
         .with_context(|| "Failed to generate Media table")?;
     create_file_table(&mut package, &cabinets).with_context(|| "Failed to generate File table")?;
+    create_registry_table(&mut package, settings)
+        .with_context(|| "Failed to generate Registry table")?;
+    let icon_name = create_icon_table(&mut package, settings)
+        .with_context(|| "Failed to generate Icon table")?;
+    create_shortcut_table(&mut package, settings, icon_name.as_deref())
+        .with_context(|| "Failed to generate Shortcut table")?;
     create_install_execute_sequence_table(&mut package, &cabinets)
         .with_context(|| "Failed to generate InstallExecuteSequence table")?;
     create_install_ui_sequence_table(&mut package, &cabinets)
@@ -116,24 +121,6 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
         .with_context(|| "Failed to generate TextStyle table")?;
     // TODO: Create other needed tables.
 
-    // Create app icon:
-    package.create_table(
-        "Icon",
-        vec![
-            msi::Column::build("Name").primary_key().id_string(72),
-            msi::Column::build("Data").binary(),
-        ],
-    )?;
-    let icon_name = format!("{}.ico", settings.binary_name());
-    {
-        let stream_name = format!("Icon.{icon_name}");
-        let mut stream = package.write_stream(&stream_name)?;
-        create_app_icon(&mut stream, settings)?;
-    }
-    package.insert_rows(
-        msi::Insert::into("Icon").row(vec![msi::Value::Str(icon_name), msi::Value::from("Name")]),
-    )?;
-
     package.flush()?;
     Ok(vec![msi_path])
 }
@@ -427,6 +414,11 @@ fn create_directory_table(
                 msi::Value::from("TARGETDIR"),
                 msi::Value::from("."),
             ])
+            .row(vec![
+                msi::Value::from("ProgramMenuFolder"),
+                msi::Value::from("TARGETDIR"),
+                msi::Value::from("."),
+            ])
             .rows(rows),
     )?;
     Ok(())
@@ -643,6 +635,96 @@ fn create_file_table(package: &mut Package, cabinets: &[CabinetInfo]) -> crate::
     Ok(())
 }
 
+// Creates and populates the `Registry` database table for the package, with
+// the ProgID and file extension keys of the file associations, and the keys of
+// the URL schemes that the app handles.  The keys are
+// under `Software\Classes` of HKLM or HKCU, for per-machine and per-user
+// installs respectively.
+fn create_registry_table(package: &mut Package, settings: &Settings) -> crate::Result<()> {
+    package.create_table(
+        "Registry",
+        vec![
+            msi::Column::build("Registry").primary_key().id_string(72),
+            msi::Column::build("Root").range(-1, 3).int16(),
+            msi::Column::build("Key")
+                .localizable()
+                .category(msi::Category::RegPath)
+                .string(255),
+            msi::Column::build("Name")
+                .nullable()
+                .localizable()
+                .formatted_string(255),
+            msi::Column::build("Value")
+                .nullable()
+                .localizable()
+                .formatted_string(0),
+            msi::Column::build("Component_")
+                .foreign_key("Component", 1)
+                .id_string(72),
+        ],
+    )?;
+    // The main executable's file key is its file name; see `create_file_table`.
+    let exe = settings.binary_name();
+    let mut rows = Vec::new();
+    let mut add_row = |key: String, name: Option<&str>, value: String| {
+        rows.push(vec![
+            msi::Value::Str(format!("REG{:04}", rows.len())),
+            msi::Value::Int(-1),
+            msi::Value::Str(format!("Software\\Classes\\{key}")),
+            name.map_or(msi::Value::Null, msi::Value::from),
+            msi::Value::Str(value),
+            msi::Value::from("INSTALLDIR"),
+        ]);
+    };
+    for (index, association) in settings.file_associations().iter().enumerate() {
+        let prog_id = common::windows_prog_id(settings.bundle_name(), index, association);
+        add_row(prog_id.clone(), None, association.description());
+        add_row(
+            format!("{prog_id}\\DefaultIcon"),
+            None,
+            format!("[#{exe}],0"),
+        );
+        if association.role != FileAssociationRole::None {
+            add_row(
+                format!("{prog_id}\\shell\\open\\command"),
+                None,
+                format!("\"[#{exe}]\" \"%1\""),
+            );
+        }
+        for extension in association.extensions() {
+            add_row(format!(".{extension}"), None, prog_id.clone());
+            if let Some(mime_type) = &association.mime_type {
+                add_row(
+                    format!(".{extension}"),
+                    Some("Content Type"),
+                    mime_type.clone(),
+                );
+            }
+        }
+    }
+    for scheme in settings.url_schemes() {
+        add_row(
+            scheme.clone(),
+            None,
+            format!("URL:{} Protocol", settings.bundle_name()),
+        );
+        // An empty `URL Protocol` value marks the key as a URL scheme.
+        add_row(scheme.clone(), Some("URL Protocol"), String::new());
+        add_row(
+            format!("{scheme}\\DefaultIcon"),
+            None,
+            format!("[#{exe}],0"),
+        );
+        add_row(
+            format!("{scheme}\\shell\\open\\command"),
+            None,
+            format!("\"[#{exe}]\" \"%1\""),
+        );
+    }
+    package.insert_rows(msi::Insert::into("Registry").rows(rows))?;
+    Ok(())
+}
+
 fn create_install_execute_sequence_table(
     package: &mut Package,
     _cabinets: &[CabinetInfo],
@@ -662,7 +744,7 @@ fn create_install_execute_sequence_table(
         ],
     )?;
     let mut rows = Vec::new();
-    let actions: [(&str, &str, i32); 24] = [
+    let actions: [(&str, &str, i32); 28] = [
         //("LaunchConditions", "", 100), // Requires a LaunchCondition table
         //("FindRelatedProducts", "", 200), // Requires an Upgrade table
         //("AppSearch", "", 400), // Requires a Signature table
@@ -687,13 +769,13 @@ fn create_install_execute_sequence_table(
         //("UnregisterTypeLibraries", "", 2300), // Requires a TypeLib table
         //("RemoveODBC", "", 2400), // Requires an ODBC* table
         //("UnregisterFonts", "", 2500), // Requires a Font table
-        //("RemoveRegistryValues", "", 2600), // Requires a Registry table
+        ("RemoveRegistryValues", "", 2600),
         //("UnregisterClassInfo", "", 2700), // Requires a Class table
         //("UnregisterExtensionInfo", "", 2800), // Requires an Extension table
         //("UnregisterProgIdInfo", "", 2900), // Requires ProgId, Extension or Class table
         //("UnregisterMIMEInfo", "", 3000), // Requires a MIME table
         //("RemoveIniValues", "", 3100), // Requires an IniFile table
-        //("RemoveShortcuts", "", 3200), // Requires a Shortcut table
+        ("RemoveShortcuts", "", 3200),
         //("RemoveEnvironmentStrings", "", 3300), // Requires an Environment table
         //("RemoveDuplicateFiles", "", 3400), // Requires a DuplicateFile table
         ("RemoveFiles", "", 3500),
@@ -704,12 +786,12 @@ fn create_install_execute_sequence_table(
         //("PatchFiles", "", 4090), // Requires a Patch table
         //("DuplicateFiles", "", 4210), // Requires a DuplicateFile table
         //("BindImage", "", 4300), // Requires a BindImage table
-        //("CreateShortcuts", "", 4500), // Requires a Shortcut table
+        ("CreateShortcuts", "", 4500),
         //("RegisterClassInfo", "", 4600), // Requires a Class table
         //("RegisterExtensionInfo", "", 4700), // Requires an Extension table
         //("RegisterProgIdInfo", "", 4800), // Requires a ProgId table
         //("RegisterMIMEInfo", "", 4900), // Requires a MIME table
-        //("WriteRegistryValues", "", 5000), // Requires a Registry table
+        ("WriteRegistryValues", "", 5000),
         //("WriteIniValues", "", 5100), // Requires an IniFile table
         //("WriteEnvironmentStrings", "", 5200), // Requires an Environment table
         //("RegisterFonts", "", 5300), // Requires a Font table
@@ -1149,16 +1231,96 @@ fn create_text_style_table(package: &mut Package, _cabinets: &[CabinetInfo]) ->
     Ok(())
 }
 
-fn create_app_icon<W: Write>(writer: &mut W, settings: &Settings) -> crate::Result<()> {
-    // Prefer ICO files.
-    for icon_path in settings.icon_files() {
-        let icon_path = icon_path?;
-        if icon_path.extension() == Some(OsStr::new("ico")) {
-            io::copy(&mut fs::File::open(icon_path)?, writer)?;
-            return Ok(());
-        }
+// Creates and populates the `Icon` database table for the package, with the
+// app's icon, and sets it as the icon shown in Add/Remove Programs.  Returns
+// the key of the icon, or `None` if the bundle has no icons.
+fn create_icon_table(package: &mut Package, settings: &Settings) -> crate::Result<Option<String>> {
+    package.create_table(
+        "Icon",
+        vec![
+            msi::Column::build("Name").primary_key().id_string(72),
+            msi::Column::build("Data").binary(),
+        ],
+    )?;
+    let mut icon = Vec::new();
+    if !common::write_app_ico(settings, &mut icon)? {
+        return Ok(None);
     }
-    // TODO: convert svg to appropriate format?
-    // TODO: Convert from other formats.
+    let icon_name = format!("{}.ico", settings.binary_name());
+    package
+        .write_stream(&format!("Icon.{icon_name}"))?
+        .write_all(&icon)?;
+    package.insert_rows(
+        msi::Insert::into("Icon").row(vec![msi::Value::Str(icon_name.clone()), msi::Value::Binary]),
+    )?;
+    package.insert_rows(msi::Insert::into("Property").row(vec![
+        msi::Value::from("ARPPRODUCTICON"),
+        msi::Value::Str(icon_name.clone()),
+    ]))?;
+    Ok(Some(icon_name))
+}
+
+// Creates and populates the `Shortcut` database table for the package, with a
+// Start menu shortcut to the app's executable.
+fn create_shortcut_table(
+    package: &mut Package,
+    settings: &Settings,
+    icon_name: Option<&str>,
+) -> crate::Result<()> {
+    package.create_table(
+        "Shortcut",
+        vec![
+            msi::Column::build("Shortcut").primary_key().id_string(72),
+            msi::Column::build("Directory_")
+                .foreign_key("Directory", 1)
+                .id_string(72),
+            msi::Column::build("Name")
+                .localizable()
+                .category(msi::Category::Filename)
+                .string(128),
+            msi::Column::build("Component_")
+                .foreign_key("Component", 1)
+                .id_string(72),
+            msi::Column::build("Target")
+                .category(msi::Category::Shortcut)
+                .string(72),
+            msi::Column::build("Arguments")
+                .nullable()
+                .formatted_string(255),
+            msi::Column::build("Description")
+                .nullable()
+                .localizable()
+                .text_string(255),
+            msi::Column::build("Hotkey").nullable().int16(),
+            msi::Column::build("Icon_")
+                .nullable()
+                .foreign_key("Icon", 1)
+                .id_string(72),
+            msi::Column::build("IconIndex").nullable().int16(),
+            msi::Column::build("ShowCmd").nullable().int16(),
+            msi::Column::build("WkDir").nullable().id_string(72),
+        ],
+    )?;
+    let description = settings.short_description();
+    package.insert_rows(msi::Insert::into("Shortcut").row(vec![
+        msi::Value::from("ApplicationShortcut"),
+        msi::Value::from("ProgramMenuFolder"),
+        msi::Value::from(settings.bundle_name()),
+        msi::Value::from("INSTALLDIR"),
+        // The main executable's file key is its file name; see
+        // `create_file_table`.
+        msi::Value::Str(format!("[#{}]", settings.binary_name())),
+        msi::Value::Null,
+        if description.is_empty() {
+            msi::Value::Null
+        } else {
+            msi::Value::from(description)
+        },
+        msi::Value::Null,
+        icon_name.map_or(msi::Value::Null, msi::Value::from),
+        icon_name.map_or(msi::Value::Null, |_| msi::Value::Int(0)),
+        msi::Value::Null,
+        msi::Value::from("INSTALLDIR"),
+    ]))?;
     Ok(())
 }
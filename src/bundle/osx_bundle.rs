@@ -18,8 +18,9 @@
 // Currently, cargo-bundle does not support Frameworks, nor does it support placing arbitrary
 // files into the `Contents` directory of the bundle.
 
-use super::common::{self, read_file};
+use super::common::{self, IconSource, read_file};
 use crate::Settings;
+use crate::bundle::{FileAssociation, FileAssociationRole};
 use anyhow::Context;
 use image::imageops::FilterType::Lanczos3;
 use image::{self, GenericImageView};
@@ -51,8 +52,16 @@ pub fn bundle_project(settings: &Settings) -> crate::Result<Vec<PathBuf>> {
         create_icns_file(&resources_dir, settings).with_context(|| "Failed to create app icon")?
     };
 
-    create_info_plist(&bundle_directory, bundle_icon_file, settings)
-        .with_context(|| "Failed to create Info.plist")?;
+    let document_icon_files = create_document_icon_files(&resources_dir, settings)
+        .with_context(|| "Failed to create document icons")?;
+
+    create_info_plist(
+        &bundle_directory,
+        bundle_icon_file,
+        &document_icon_files,
+        settings,
+    )
+    .with_context(|| "Failed to create Info.plist")?;
 
     let copied = copy_frameworks_to_bundle(&bundle_directory, settings)
         .with_context(|| "Failed to bundle frameworks")?;
@@ -207,6 +216,7 @@ fn add_rpath(bundle_directory: &Path, settings: &Settings) -> crate::Result<()>
 fn create_info_plist(
     bundle_dir: &Path,
     bundle_icon_file: Option<PathBuf>,
+    document_icon_files: &[Option<String>],
     settings: &Settings,
 ) -> crate::Result<()> {
     let build_number = chrono::Utc::now().format("%Y%m%d.%H%M%S");
@@ -229,6 +239,9 @@ fn create_info_plist(
         "  <key>CFBundleDisplayName</key>\n  <string>{}</string>\n",
         settings.bundle_name().format_plist_entry()
     )?;
+    if !settings.file_associations().is_empty() {
+        write_document_types(file, document_icon_files, settings)?;
+    }
     write!(
         file,
         "  <key>CFBundleExecutable</key>\n  <string>{}</string>\n",
@@ -265,7 +278,7 @@ fn create_info_plist(
         "  <key>CFBundleShortVersionString</key>\n  <string>{}</string>\n",
         settings.version_string()
     )?;
-    if !settings.osx_url_schemes().is_empty() {
+    if !settings.url_schemes().is_empty() {
         write!(
             file,
             "  <key>CFBundleURLTypes</key>\n  \
@@ -279,7 +292,7 @@ fn create_info_plist(
                        <array>\n",
             settings.bundle_name().format_plist_entry()
         )?;
-        for scheme in settings.osx_url_schemes() {
+        for scheme in settings.url_schemes() {
             writeln!(
                 file,
                 "        <string>{}</string>",
@@ -325,6 +338,13 @@ fn create_info_plist(
             copyright.format_plist_entry()
         )?;
     }
+    if settings
+        .file_associations()
+        .iter()
+        .any(FileAssociation::is_custom_type)
+    {
+        write_exported_type_declarations(file, document_icon_files, settings)?;
+    }
     for plist in settings.osx_info_plist_exts() {
         let plist = plist?;
         let contents = read_file(&plist)?;
@@ -335,6 +355,193 @@ fn create_info_plist(
     Ok(())
 }
 
+/// Returns the uniform type identifier that the bundle exports for a file
+/// association, based on the bundle identifier and its first extension.
+fn exported_type_identifier(settings: &Settings, association: &FileAssociation) -> String {
+    format!(
+        "{}.{}",
+        settings.bundle_identifier(),
+        association.extensions().next().unwrap_or("document")
+    )
+}
+
+/// Writes a `<key>` followed by an `<array>` of strings.
+fn write_string_array<'a, W, I>(file: &mut W, indent: &str, key: &str, values: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a str>,
+{
+    writeln!(file, "{indent}<key>{key}</key>")?;
+    writeln!(file, "{indent}<array>")?;
+    for value in values {
+        writeln!(
+            file,
+            "{indent}  <string>{}</string>",
+            value.format_plist_entry()
+        )?;
+    }
+    writeln!(file, "{indent}</array>")
+}
+
+/// Writes the `CFBundleDocumentTypes` of the file associations, which tell
+/// Launch Services that the app can open them.
+fn write_document_types<W: Write>(
+    file: &mut W,
+    document_icon_files: &[Option<String>],
+    settings: &Settings,
+) -> crate::Result<()> {
+    writeln!(file, "  <key>CFBundleDocumentTypes</key>\n  <array>")?;
+    for (association, icon_file) in settings.file_associations().iter().zip(document_icon_files) {
+        writeln!(file, "    <dict>")?;
+        write_string_array(
+            file,
+            "      ",
+            "CFBundleTypeExtensions",
+            association.extensions(),
+        )?;
+        if let Some(icon_file) = icon_file {
+            writeln!(
+                file,
+                "      <key>CFBundleTypeIconFile</key>\n      <string>{}</string>",
+                icon_file.format_plist_entry()
+            )?;
+        }
+        if let Some(mime_type) = &association.mime_type {
+            write_string_array(
+                file,
+                "      ",
+                "CFBundleTypeMIMETypes",
+                [mime_type.as_str()],
+            )?;
+        }
+        writeln!(
+            file,
+            "      <key>CFBundleTypeName</key>\n      <string>{}</string>",
+            association.description().format_plist_entry()
+        )?;
+        let role = match association.role {
+            FileAssociationRole::Editor => "Editor",
+            FileAssociationRole::Viewer => "Viewer",
+            FileAssociationRole::Shell => "Shell",
+            FileAssociationRole::None => "None",
+        };
+        writeln!(
+            file,
+            "      <key>CFBundleTypeRole</key>\n      <string>{role}</string>"
+        )?;
+        // Standard types are matched by their extensions and MIME types.
+        if association.is_custom_type() {
+            writeln!(
+                file,
+                "      <key>LSHandlerRank</key>\n      <string>Owner</string>"
+            )?;
+            let identifier = exported_type_identifier(settings, association);
+            write_string_array(file, "      ", "LSItemContentTypes", [identifier.as_str()])?;
+        } else {
+            writeln!(
+                file,
+                "      <key>LSHandlerRank</key>\n      <string>Alternate</string>"
+            )?;
+        }
+        writeln!(file, "    </dict>")?;
+    }
+    writeln!(file, "  </array>")?;
+    Ok(())
+}
+
+/// Writes the `UTExportedTypeDeclarations` that declare a uniform type
+/// identifier for each file association of a custom type.
+fn write_exported_type_declarations<W: Write>(
+    file: &mut W,
+    document_icon_files: &[Option<String>],
+    settings: &Settings,
+) -> crate::Result<()> {
+    writeln!(file, "  <key>UTExportedTypeDeclarations</key>\n  <array>")?;
+    for (association, icon_file) in settings.file_associations().iter().zip(document_icon_files) {
+        if !association.is_custom_type() {
+            continue;
+        }
+        writeln!(file, "    <dict>")?;
+        write_string_array(file, "      ", "UTTypeConformsTo", ["public.data"])?;
+        writeln!(
+            file,
+            "      <key>UTTypeDescription</key>\n      <string>{}</string>",
+            association.description().format_plist_entry()
+        )?;
+        if let Some(icon_file) = icon_file {
+            writeln!(
+                file,
+                "      <key>UTTypeIconFile</key>\n      <string>{}</string>",
+                icon_file.format_plist_entry()
+            )?;
+        }
+        writeln!(
+            file,
+            "      <key>UTTypeIdentifier</key>\n      <string>{}</string>",
+            exported_type_identifier(settings, association).format_plist_entry()
+        )?;
+        writeln!(
+            file,
+            "      <key>UTTypeTagSpecification</key>\n      <dict>"
+        )?;
+        write_string_array(
+            file,
+            "        ",
+            "public.filename-extension",
+            association.extensions(),
+        )?;
+        if let Some(mime_type) = &association.mime_type {
+            write_string_array(file, "        ", "public.mime-type", [mime_type.as_str()])?;
+        }
+        writeln!(file, "      </dict>\n    </dict>")?;
+    }
+    writeln!(file, "  </array>")?;
+    Ok(())
+}
+
+/// Copies (or converts) the icons of the file associations into ICNS files
+/// in the resources directory, returning the file name of each
+/// association's icon.
+fn create_document_icon_files(
+    resources_dir: &Path,
+    settings: &Settings,
+) -> crate::Result<Vec<Option<String>>> {
+    let mut icon_files = Vec::new();
+    for association in settings.file_associations() {
+        let Some(icon_path) = &association.icon else {
+            icon_files.push(None);
+            continue;
+        };
+        let stem = icon_path.file_stem().unwrap_or_default().to_string_lossy();
+        let dest_path = resources_dir.join(format!("{stem}.icns"));
+        if icon_path.extension() == Some(OsStr::new("icns")) {
+            common::copy_file(icon_path, &dest_path)?;
+        } else if icon_path.extension() == Some(OsStr::new("svg")) {
+            let mut family = icns::IconFamily::new();
+            add_source_to_family(&IconSource::open(icon_path)?, &mut family)?;
+            fs::create_dir_all(resources_dir)?;
+            family.write(BufWriter::new(File::create(&dest_path)?))?;
+        } else {
+            let icon = image::open(icon_path)
+                .with_context(|| format!("Failed to read icon {icon_path:?}"))?;
+            let (w, h) = icon.dimensions();
+            let size = 2f32.powf((min(w, h) as f32).log2().floor()) as u32;
+            let icon = if (w, h) == (size, size) {
+                icon
+            } else {
+                icon.resize_exact(size, size, Lanczos3)
+            };
+            let mut family = icns::IconFamily::new();
+            add_icon_to_family(icon, 1, &mut family)
+                .with_context(|| format!("Failed to convert icon {icon_path:?}"))?;
+            fs::create_dir_all(resources_dir)?;
+            family.write(BufWriter::new(File::create(&dest_path)?))?;
+        }
+        icon_files.push(Some(format!("{stem}.icns")));
+    }
+    Ok(icon_files)
+}
+
 fn copy_framework_from(dest_dir: &Path, framework: &str, src_dir: &Path) -> crate::Result<bool> {
     let src_name = format!("{framework}.framework");
     let src_path = src_dir.join(&src_name);
@@ -416,6 +623,21 @@ fn copy_plugins_to_bundle(bundle_directory: &Path, settings: &Settings) -> crate
     Ok(())
 }
 
+/// The pixel sizes and densities of the ICNS icon types that are generated
+/// from SVG icons and the icon source: 16x16 to 512x512 points, at 1x and 2x.
+const ICNS_SIZES: [(u32, u32); 10] = [
+    (16, 1),
+    (32, 1),
+    (32, 2),
+    (64, 2),
+    (128, 1),
+    (256, 1),
+    (256, 2),
+    (512, 1),
+    (512, 2),
+    (1024, 2),
+];
+
 /// Given a list of icon files, try to produce an ICNS file in the resources
 /// directory and return the path to it.  Returns `Ok(None)` if no usable icons
 /// were provided.
@@ -423,7 +645,7 @@ fn create_icns_file(
     resources_dir: &PathBuf,
     settings: &Settings,
 ) -> crate::Result<Option<PathBuf>> {
-    if settings.icon_files().count() == 0 {
+    if settings.icon_files().count() == 0 && settings.icon_source().is_none() {
         return Ok(None);
     }
 
@@ -441,34 +663,13 @@ fn create_icns_file(
     // Otherwise, read available images and pack them into a new ICNS file.
     let mut family = icns::IconFamily::new();
 
-    fn add_icon_to_family(
-        icon: image::DynamicImage,
-        density: u32,
-        family: &mut icns::IconFamily,
-    ) -> io::Result<()> {
-        // Try to add this image to the icon family.  Ignore images whose sizes
-        // don't map to any ICNS icon type; print warnings and skip images that
-        // fail to encode.
-        match icns::IconType::from_pixel_size_and_density(icon.width(), icon.height(), density) {
-            Some(icon_type) => {
-                if !family.has_icon_with_type(icon_type) {
-                    let icon = make_icns_image(icon)?;
-                    family.add_icon_with_type(&icon, icon_type)?;
-                }
-                Ok(())
-            }
-            None => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "No matching IconType",
-            )),
-        }
-    }
-
     let mut images_to_resize: Vec<(image::DynamicImage, u32, u32)> = vec![];
+    let mut svg_paths = vec![];
     for icon_path in settings.icon_files() {
         let icon_path = icon_path?;
         if icon_path.extension() == Some(OsStr::new("svg")) {
-            continue; // TODO: convert svg to appropriate format?
+            svg_paths.push(icon_path);
+            continue;
         }
         let icon = image::open(&icon_path)?;
         let density = if common::is_retina(&icon_path) { 2 } else { 1 };
@@ -487,6 +688,15 @@ fn create_icns_file(
         add_icon_to_family(icon, density, &mut family)?;
     }
 
+    // Generate the sizes that no other icon file provides from SVG icons, and
+    // then from the icon source.
+    for svg_path in svg_paths {
+        add_source_to_family(&IconSource::open(&svg_path)?, &mut family)?;
+    }
+    if let Some(source) = common::open_icon_source(settings)? {
+        add_source_to_family(&source, &mut family)?;
+    }
+
     if !family.is_empty() {
         fs::create_dir_all(resources_dir)?;
         let mut dest_path = resources_dir.clone();
@@ -500,6 +710,42 @@ fn create_icns_file(
     anyhow::bail!("No usable icon files found.");
 }
 
+fn add_icon_to_family(
+    icon: image::DynamicImage,
+    density: u32,
+    family: &mut icns::IconFamily,
+) -> io::Result<()> {
+    // Try to add this image to the icon family.  Ignore images whose sizes
+    // don't map to any ICNS icon type; print warnings and skip images that
+    // fail to encode.
+    match icns::IconType::from_pixel_size_and_density(icon.width(), icon.height(), density) {
+        Some(icon_type) => {
+            if !family.has_icon_with_type(icon_type) {
+                let icon = make_icns_image(icon)?;
+                family.add_icon_with_type(&icon, icon_type)?;
+            }
+            Ok(())
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No matching IconType",
+        )),
+    }
+}
+
+/// Generates an icon from `source` for each of the ICNS icon types that the
+/// family doesn't have yet.
+fn add_source_to_family(source: &IconSource, family: &mut icns::IconFamily) -> crate::Result<()> {
+    for (size, density) in ICNS_SIZES {
+        let icon_type = icns::IconType::from_pixel_size_and_density(size, size, density)
+            .expect("ICNS_SIZES are valid icon sizes");
+        if !family.has_icon_with_type(icon_type) {
+            add_icon_to_family(source.render(size)?, density, family)?;
+        }
+    }
+    Ok(())
+}
+
 /// Converts an image::DynamicImage into an icns::Image.
 fn make_icns_image(img: image::DynamicImage) -> io::Result<icns::Image> {
     let pixel_format = match img.color() {
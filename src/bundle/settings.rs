@@ -3,7 +3,7 @@ use super::common::print_warning;
 use cargo_metadata::{Metadata, MetadataCommand, Package, TargetKind};
 use serde_json::Value;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
@@ -86,6 +86,186 @@ impl PackageType {
     }
 }
 
+/// The compression applied to the archives within a package.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    Gzip,
+    Xz,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    /// The file extension of a tar archive compressed with this compression.
+    pub const fn tar_extension(&self) -> &'static str {
+        match *self {
+            Compression::Gzip => "tar.gz",
+            Compression::Xz => "tar.xz",
+            Compression::Zstd => "tar.zst",
+            Compression::None => "tar",
+        }
+    }
+}
+
+/// An entry of the `linux_files` table, mapping files in the project to
+/// where they are installed by Linux packages.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LinuxFile {
+    /// A path or glob pattern matching the files to install.
+    pub src: String,
+    /// The absolute path to install the file(s) to; if this ends with `/`, it
+    /// is a directory which the matched files are copied into.
+    pub dest: String,
+    /// The permission bits of the installed file(s).
+    pub mode: Option<u32>,
+    /// Whether the file is a configuration file that package upgrades should
+    /// not overwrite once the user has modified it.
+    #[serde(default)]
+    pub conffile: bool,
+}
+
+/// An entry of the `file_associations` list: a type of document that the app
+/// can open.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct FileAssociation {
+    /// The file name extensions of the document type, e.g. `["jpg", "jpeg"]`.
+    pub extensions: Vec<String>,
+    pub mime_type: Option<String>,
+    /// A human-readable name for the document type, e.g. "JPEG image".
+    pub description: Option<String>,
+    /// The path to an icon for documents of this type.
+    pub icon: Option<PathBuf>,
+    #[serde(default)]
+    pub role: FileAssociationRole,
+}
+
+impl FileAssociation {
+    /// Returns the extensions, without any leading `.`.
+    pub fn extensions(&self) -> impl Iterator<Item = &str> {
+        self.extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.'))
+    }
+
+    /// Returns whether the document type belongs to the app: it has no MIME
+    /// type, or a private (`x-`) or vendor (`vnd.`) one.  Other types, like
+    /// `text/plain`, are already known to the system, so the app only
+    /// declares that it can open them.
+    pub fn is_custom_type(&self) -> bool {
+        match self
+            .mime_type
+            .as_deref()
+            .and_then(|mime| mime.split_once('/'))
+        {
+            Some((_, subtype)) => subtype.starts_with("x-") || subtype.starts_with("vnd."),
+            None => true,
+        }
+    }
+
+    /// Returns the description, or a generic one based on the first
+    /// extension (e.g. "FOO document").
+    pub fn description(&self) -> String {
+        match &self.description {
+            Some(description) => description.clone(),
+            None => format!(
+                "{} document",
+                self.extensions().next().unwrap_or("").to_uppercase()
+            ),
+        }
+    }
+}
+
+/// What an app does with the documents of a [`FileAssociation`], as in the
+/// `CFBundleTypeRole` of macOS.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize)]
+pub enum FileAssociationRole {
+    /// The app can open and save documents of this type.
+    #[default]
+    Editor,
+    /// The app can open, but not save, documents of this type.
+    Viewer,
+    /// The app provides runtime services for the type (macOS only; treated
+    /// like `Viewer` elsewhere).
+    Shell,
+    /// The app declares the type, but can't open it.
+    None,
+}
+
+/// An entry of the `linux_desktop_actions` list: an additional way to launch
+/// the app (e.g. "New Window"), offered by launchers and docks.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct DesktopAction {
+    /// The identifier of the action, e.g. `new-window`.
+    pub id: String,
+    pub name: String,
+    /// The command line to run, e.g. `my_app --new-window`.
+    pub exec: String,
+    /// The name of the icon to show for the action.
+    pub icon: Option<String>,
+}
+
+/// An entry of the `linux_screenshots` list, shown by software centers.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Screenshot {
+    /// The URL of the screenshot image.
+    pub url: String,
+    /// A short description of what the screenshot shows.
+    pub caption: Option<String>,
+}
+
+/// An entry of the `linux_releases` list, describing a released version of
+/// the app.
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct Release {
+    pub version: String,
+    /// The release date, as `YYYY-MM-DD`.
+    pub date: String,
+    /// The release notes, as paragraphs separated by blank lines.
+    pub description: Option<String>,
+}
+
+/// A maintainer script or `triggers` file of a deb package, given either as
+/// the path of a file (relative to `Cargo.toml`), as in `"debian/postinst"`
+/// or `{ file = "debian/postinst" }`, or inline, as in
+/// `{ inline = "ldconfig" }`.
+#[derive(Clone, Debug, Eq, PartialEq, serde::Deserialize)]
+#[serde(from = "RawDebScript")]
+pub enum DebScript {
+    File(PathBuf),
+    Inline(String),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawDebScript {
+    Path(PathBuf),
+    File { file: PathBuf },
+    Inline { inline: String },
+}
+
+impl From<RawDebScript> for DebScript {
+    fn from(raw: RawDebScript) -> Self {
+        match raw {
+            RawDebScript::Path(file) | RawDebScript::File { file } => DebScript::File(file),
+            RawDebScript::Inline { inline } => DebScript::Inline(inline),
+        }
+    }
+}
+
+/// How an AppImage's `AppRun` entry point launches the app.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AppRun {
+    /// `AppRun` is a symlink to the binary.
+    Symlink,
+    /// `AppRun` is a shell script that sets up the environment for the
+    /// AppDir and then runs the binary.
+    Script,
+    /// `AppRun` is a copy of the given launcher (e.g. a prebuilt ELF one).
+    File(PathBuf),
+}
+
 #[derive(Clone, Debug)]
 pub enum BuildArtifact {
     Main,
@@ -99,21 +279,72 @@ struct BundleSettings {
     name: Option<String>,
     identifier: Option<String>,
     icon: Option<Vec<String>>,
+    icon_source: Option<PathBuf>,
     version: Option<String>,
     resources: Option<Vec<String>>,
     copyright: Option<String>,
     category: Option<AppCategory>,
     short_description: Option<String>,
     long_description: Option<String>,
+    file_associations: Option<Vec<FileAssociation>>,
     // OS-specific settings:
     linux_mime_types: Option<Vec<String>>,
     linux_exec_args: Option<String>,
     linux_use_terminal: Option<bool>,
+    linux_generic_name: Option<String>,
+    linux_keywords: Option<Vec<String>>,
+    linux_startup_wm_class: Option<String>,
+    linux_startup_notify: Option<bool>,
+    linux_no_display: Option<bool>,
+    linux_localized_name: Option<BTreeMap<String, String>>,
+    linux_localized_comment: Option<BTreeMap<String, String>>,
+    linux_desktop_actions: Option<Vec<DesktopAction>>,
+    linux_files: Option<Vec<LinuxFile>>,
+    appimage_runtime: Option<String>,
+    appimage_runtime_sha256: Option<String>,
+    appimage_compression: Option<Compression>,
+    appimage_library_path: Option<Vec<String>>,
+    appimage_apprun: Option<String>,
+    appimage_update_info: Option<String>,
+    linux_systemd_units: Option<Vec<String>>,
+    linux_systemd_enable: Option<bool>,
+    linux_systemd_start: Option<bool>,
+    linux_sysusers: Option<Vec<String>>,
+    linux_tmpfiles: Option<Vec<String>>,
+    linux_screenshots: Option<Vec<Screenshot>>,
+    linux_content_rating: Option<BTreeMap<String, String>>,
+    linux_releases: Option<Vec<Release>>,
     deb_depends: Option<Vec<String>>,
+    deb_auto_depends: Option<bool>,
+    deb_dpkg_admin_dir: Option<PathBuf>,
+    deb_compression: Option<Compression>,
+    deb_compression_level: Option<u32>,
+    deb_pre_depends: Option<Vec<String>>,
+    deb_recommends: Option<Vec<String>>,
+    deb_suggests: Option<Vec<String>>,
+    deb_enhances: Option<Vec<String>>,
+    deb_breaks: Option<Vec<String>>,
+    deb_conflicts: Option<Vec<String>>,
+    deb_replaces: Option<Vec<String>>,
+    deb_provides: Option<Vec<String>>,
+    deb_section: Option<String>,
+    deb_priority: Option<String>,
+    deb_preinst: Option<DebScript>,
+    deb_postinst: Option<DebScript>,
+    deb_prerm: Option<DebScript>,
+    deb_postrm: Option<DebScript>,
+    deb_triggers: Option<DebScript>,
+    deb_refresh_caches: Option<bool>,
+    deb_changelog: Option<String>,
+    rpm_requires: Option<Vec<String>>,
+    rpm_license: Option<String>,
+    rpm_group: Option<String>,
+    rpm_release: Option<String>,
     osx_frameworks: Option<Vec<String>>,
     osx_plugins: Option<Vec<String>>,
     osx_minimum_system_version: Option<String>,
-    osx_url_schemes: Option<Vec<String>>,
+    #[serde(alias = "osx_url_schemes")]
+    url_schemes: Option<Vec<String>>,
     osx_info_plist_exts: Option<Vec<String>>,
     // Bundles for other binaries/examples:
     bin: Option<HashMap<String, BundleSettings>>,
@@ -131,6 +362,9 @@ pub struct Settings {
     profile: String,
     all_features: bool,
     no_default_features: bool,
+    appimage_runtime: Option<PathBuf>,
+    appimage_sign_key: Option<PathBuf>,
+    offline: bool,
     binary_path: PathBuf,
     binary_name: String,
     bundle_settings: BundleSettings,
@@ -172,6 +406,12 @@ impl Settings {
             None
         };
         let features = cli.features.as_ref().map(|features| features.into());
+        let appimage_runtime = cli.appimage_runtime.clone();
+        let appimage_sign_key = cli.appimage_sign_key.clone();
+        // Like cargo, honour `CARGO_NET_OFFLINE` as well as `--offline`.
+        let offline = cli.offline
+            || std::env::var("CARGO_NET_OFFLINE")
+                .is_ok_and(|value| value == "true" || value == "1");
         let cargo_settings = load_metadata(&current_dir)?;
         let package = Settings::find_bundle_package(cli.package.as_deref(), &cargo_settings)?;
         let bundle_settings = Settings::bundle_settings_of_package(package)?;
@@ -205,6 +445,14 @@ impl Settings {
         };
         binary_name += binary_extension;
         let binary_path = target_dir.join(&binary_name);
+        for scheme in bundle_settings.url_schemes.iter().flatten() {
+            if !is_valid_url_scheme(scheme) {
+                anyhow::bail!(
+                    "Invalid URL scheme {scheme:?}: schemes start with a letter, followed by \
+                     letters, digits, `+`, `-` or `.`"
+                );
+            }
+        }
         Ok(Settings {
             package: package.clone(),
             package_type,
@@ -214,6 +462,9 @@ impl Settings {
             profile,
             all_features,
             no_default_features,
+            appimage_runtime,
+            appimage_sign_key,
+            offline,
             project_out_directory: target_dir,
             binary_path,
             binary_name,
@@ -359,7 +610,11 @@ impl Settings {
             match target_os {
                 "macos" => Ok(vec![PackageType::OsxBundle]),
                 "ios" => Ok(vec![PackageType::IosBundle]),
-                "linux" => Ok(vec![PackageType::Deb, PackageType::AppImage]), // TODO: Do Rpm too, once it's implemented.
+                "linux" => Ok(vec![
+                    PackageType::Deb,
+                    PackageType::Rpm,
+                    PackageType::AppImage,
+                ]),
                 "windows" => Ok(vec![PackageType::WindowsMsi]),
                 os => anyhow::bail!("Native {} bundles not yet supported.", os),
             }
@@ -401,6 +656,11 @@ impl Settings {
         self.no_default_features
     }
 
+    /// Returns whether bundling must not access the network.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
     pub fn bundle_name(&self) -> &str {
         self.bundle_settings
             .name
@@ -430,6 +690,12 @@ impl Settings {
         }
     }
 
+    /// Returns the path of the high-resolution image that each platform's
+    /// icon sizes are generated from, if any.
+    pub fn icon_source(&self) -> Option<&Path> {
+        self.bundle_settings.icon_source.as_deref()
+    }
+
     /// Returns an iterator over the resource files to be included in this
     /// bundle.
     pub fn resource_files(&self) -> ResourcePaths<'_> {
@@ -482,29 +748,43 @@ impl Settings {
         self.bundle_settings.long_description.as_deref()
     }
 
-    pub fn license_content(&self) -> Option<String> {
-        self.package
-            .license_file
-            .as_ref()
-            .and_then(|license_file| {
-                let dir = self
-                    .manifest_path()
-                    .parent()
-                    .unwrap_or_else(|| Path::new("."));
-
-                let license_path = dir.join(license_file);
-                match std::fs::read_to_string(&license_path) {
-                    Ok(content) => Some(content),
-                    Err(err) => {
-                        print_warning(&format!(
-                            "Failed to read license file '{license_path:?}': {err} -- ignoring",
-                        ))
-                        .ok();
-                        None
-                    }
+    pub fn file_associations(&self) -> &[FileAssociation] {
+        self.bundle_settings
+            .file_associations
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    /// Returns the SPDX license expression of the package, if any.
+    pub fn license(&self) -> Option<&str> {
+        self.package.license.as_deref()
+    }
+
+    /// Returns the contents of the package's `license-file`, if it has one.
+    pub fn license_file_content(&self) -> Option<String> {
+        self.package.license_file.as_ref().and_then(|license_file| {
+            let dir = self
+                .manifest_path()
+                .parent()
+                .unwrap_or_else(|| Path::new("."));
+
+            let license_path = dir.join(license_file);
+            match std::fs::read_to_string(&license_path) {
+                Ok(content) => Some(content),
+                Err(err) => {
+                    print_warning(&format!(
+                        "Failed to read license file '{license_path:?}': {err} -- ignoring",
+                    ))
+                    .ok();
+                    None
                 }
-            })
-            .or_else(|| self.package.license.as_ref().map(|s| s.to_string()))
+            }
+        })
+    }
+
+    pub fn license_content(&self) -> Option<String> {
+        self.license_file_content()
+            .or_else(|| self.license().map(|s| s.to_string()))
     }
 
     pub fn debian_dependencies(&self) -> &[String] {
@@ -514,6 +794,144 @@ impl Settings {
         }
     }
 
+    /// Returns whether the shared libraries the binary links against should
+    /// be added to `Depends` automatically (defaults to `false`).
+    pub fn deb_auto_depends(&self) -> bool {
+        self.bundle_settings.deb_auto_depends.unwrap_or(false)
+    }
+
+    /// Returns the dpkg database (relative to the manifest directory) in which
+    /// to look up shared library dependencies, if it isn't the system's.
+    pub fn deb_dpkg_admin_dir(&self) -> Option<PathBuf> {
+        self.bundle_settings.deb_dpkg_admin_dir.as_ref().map(|dir| {
+            self.manifest_path()
+                .parent()
+                .unwrap_or_else(|| Path::new("."))
+                .join(dir)
+        })
+    }
+
+    pub fn deb_compression(&self) -> Compression {
+        self.bundle_settings.deb_compression.unwrap_or_default()
+    }
+
+    pub fn deb_compression_level(&self) -> Option<u32> {
+        self.bundle_settings.deb_compression_level
+    }
+
+    pub fn deb_pre_depends(&self) -> &[String] {
+        self.bundle_settings
+            .deb_pre_depends
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    pub fn deb_recommends(&self) -> &[String] {
+        self.bundle_settings
+            .deb_recommends
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    pub fn deb_suggests(&self) -> &[String] {
+        self.bundle_settings.deb_suggests.as_deref().unwrap_or(&[])
+    }
+
+    pub fn deb_enhances(&self) -> &[String] {
+        self.bundle_settings.deb_enhances.as_deref().unwrap_or(&[])
+    }
+
+    pub fn deb_breaks(&self) -> &[String] {
+        self.bundle_settings.deb_breaks.as_deref().unwrap_or(&[])
+    }
+
+    pub fn deb_conflicts(&self) -> &[String] {
+        self.bundle_settings.deb_conflicts.as_deref().unwrap_or(&[])
+    }
+
+    pub fn deb_replaces(&self) -> &[String] {
+        self.bundle_settings.deb_replaces.as_deref().unwrap_or(&[])
+    }
+
+    pub fn deb_provides(&self) -> &[String] {
+        self.bundle_settings.deb_provides.as_deref().unwrap_or(&[])
+    }
+
+    /// Returns the archive section of the deb package, falling back to one
+    /// derived from the app category.
+    pub fn deb_section(&self) -> Option<&str> {
+        self.bundle_settings.deb_section.as_deref().or_else(|| {
+            self.app_category()
+                .map(|category| category.debian_section())
+        })
+    }
+
+    pub fn deb_priority(&self) -> &str {
+        self.bundle_settings
+            .deb_priority
+            .as_deref()
+            .unwrap_or("optional")
+    }
+
+    pub fn deb_preinst(&self) -> Option<&DebScript> {
+        self.bundle_settings.deb_preinst.as_ref()
+    }
+
+    pub fn deb_postinst(&self) -> Option<&DebScript> {
+        self.bundle_settings.deb_postinst.as_ref()
+    }
+
+    pub fn deb_prerm(&self) -> Option<&DebScript> {
+        self.bundle_settings.deb_prerm.as_ref()
+    }
+
+    pub fn deb_postrm(&self) -> Option<&DebScript> {
+        self.bundle_settings.deb_postrm.as_ref()
+    }
+
+    pub fn deb_triggers(&self) -> Option<&DebScript> {
+        self.bundle_settings.deb_triggers.as_ref()
+    }
+
+    /// Returns whether the generated `postinst` and `postrm` scripts should
+    /// refresh the icon cache and desktop database (defaults to `true`).
+    pub fn deb_refresh_caches(&self) -> bool {
+        self.bundle_settings.deb_refresh_caches.unwrap_or(true)
+    }
+
+    /// Returns the path (relative to the manifest directory) of a changelog in
+    /// the Keep a Changelog format to convert into the deb package's changelog.
+    pub fn deb_changelog(&self) -> Option<&str> {
+        self.bundle_settings.deb_changelog.as_deref()
+    }
+
+    pub fn rpm_requires(&self) -> &[String] {
+        match self.bundle_settings.rpm_requires {
+            Some(ref requires) => requires.as_slice(),
+            None => &[],
+        }
+    }
+
+    /// Returns the license of the RPM package, falling back to the license
+    /// declared in `Cargo.toml`.
+    pub fn rpm_license(&self) -> Option<&str> {
+        self.bundle_settings
+            .rpm_license
+            .as_deref()
+            .or(self.package.license.as_deref())
+    }
+
+    pub fn rpm_group(&self) -> &str {
+        self.bundle_settings
+            .rpm_group
+            .as_deref()
+            .unwrap_or("Unspecified")
+    }
+
+    pub fn rpm_release(&self) -> &str {
+        self.bundle_settings.rpm_release.as_deref().unwrap_or("1")
+    }
+
     pub fn linux_mime_types(&self) -> &[String] {
         match self.bundle_settings.linux_mime_types {
             Some(ref mime_types) => mime_types.as_slice(),
@@ -529,6 +947,184 @@ impl Settings {
         self.bundle_settings.linux_exec_args.as_deref()
     }
 
+    pub fn linux_generic_name(&self) -> Option<&str> {
+        self.bundle_settings.linux_generic_name.as_deref()
+    }
+
+    pub fn linux_keywords(&self) -> &[String] {
+        self.bundle_settings
+            .linux_keywords
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    pub fn linux_startup_wm_class(&self) -> Option<&str> {
+        self.bundle_settings.linux_startup_wm_class.as_deref()
+    }
+
+    pub fn linux_startup_notify(&self) -> Option<bool> {
+        self.bundle_settings.linux_startup_notify
+    }
+
+    pub fn linux_no_display(&self) -> Option<bool> {
+        self.bundle_settings.linux_no_display
+    }
+
+    /// Returns the translations of the bundle name, keyed by locale (e.g.
+    /// `de` or `pt_BR`).
+    pub fn linux_localized_name(&self) -> Option<&BTreeMap<String, String>> {
+        self.bundle_settings.linux_localized_name.as_ref()
+    }
+
+    /// Returns the translations of the short description, keyed by locale.
+    pub fn linux_localized_comment(&self) -> Option<&BTreeMap<String, String>> {
+        self.bundle_settings.linux_localized_comment.as_ref()
+    }
+
+    pub fn linux_desktop_actions(&self) -> &[DesktopAction] {
+        self.bundle_settings
+            .linux_desktop_actions
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    pub fn linux_files(&self) -> &[LinuxFile] {
+        self.bundle_settings.linux_files.as_deref().unwrap_or(&[])
+    }
+
+    pub fn linux_screenshots(&self) -> &[Screenshot] {
+        self.bundle_settings
+            .linux_screenshots
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    /// Returns the OARS content rating attributes (e.g. `violence-cartoon`)
+    /// and their values (e.g. `mild`); those not listed are `none`.
+    pub fn linux_content_rating(&self) -> Option<&BTreeMap<String, String>> {
+        self.bundle_settings.linux_content_rating.as_ref()
+    }
+
+    pub fn linux_releases(&self) -> &[Release] {
+        self.bundle_settings
+            .linux_releases
+            .as_deref()
+            .unwrap_or(&[])
+    }
+
+    /// Returns the path of the AppImage runtime to use instead of downloading
+    /// one: either the one given on the command line, or the `appimage_runtime`
+    /// setting (relative to the manifest directory).
+    pub fn appimage_runtime(&self) -> Option<PathBuf> {
+        if let Some(runtime) = &self.appimage_runtime {
+            return Some(runtime.clone());
+        }
+        self.bundle_settings
+            .appimage_runtime
+            .as_ref()
+            .map(|runtime| {
+                self.manifest_path()
+                    .parent()
+                    .unwrap_or_else(|| Path::new("."))
+                    .join(runtime)
+            })
+    }
+
+    /// Returns the file containing the OpenPGP secret key to sign AppImages
+    /// with, if they are to be signed.
+    pub fn appimage_sign_key(&self) -> Option<&Path> {
+        self.appimage_sign_key.as_deref()
+    }
+
+    /// Returns the SHA-256 checksum (in hex) that the AppImage runtime must
+    /// have, if it is pinned.
+    pub fn appimage_runtime_sha256(&self) -> Option<&str> {
+        self.bundle_settings.appimage_runtime_sha256.as_deref()
+    }
+
+    /// Returns the compression of the AppImage's filesystem image (defaults
+    /// to zstd).
+    pub fn appimage_compression(&self) -> Compression {
+        self.bundle_settings
+            .appimage_compression
+            .unwrap_or(Compression::Zstd)
+    }
+
+    /// Returns the extra directories (relative to the manifest directory) to
+    /// search for shared libraries to bundle into an AppImage, before the
+    /// system ones.
+    pub fn appimage_library_path(&self) -> Vec<PathBuf> {
+        let manifest_dir = self
+            .manifest_path()
+            .parent()
+            .unwrap_or_else(|| Path::new("."));
+        self.bundle_settings
+            .appimage_library_path
+            .as_deref()
+            .unwrap_or(&[])
+            .iter()
+            .map(|dir| manifest_dir.join(dir))
+            .collect()
+    }
+
+    /// Returns how the AppImage's `AppRun` launches the app: `"symlink"` (the
+    /// default), `"script"`, or otherwise the path (relative to the manifest
+    /// directory) of a launcher to use.
+    pub fn appimage_apprun(&self) -> AppRun {
+        match self.bundle_settings.appimage_apprun.as_deref() {
+            None | Some("symlink") => AppRun::Symlink,
+            Some("script") => AppRun::Script,
+            Some(path) => AppRun::File(
+                self.manifest_path()
+                    .parent()
+                    .unwrap_or_else(|| Path::new("."))
+                    .join(path),
+            ),
+        }
+    }
+
+    /// Returns the update information to embed in the AppImage, e.g.
+    /// `gh-releases-zsync|user|repo|latest|App-*x86_64.AppImage.zsync`.
+    pub fn appimage_update_info(&self) -> Option<&str> {
+        self.bundle_settings.appimage_update_info.as_deref()
+    }
+
+    /// Returns an iterator over the systemd unit files for this bundle
+    pub fn linux_systemd_units(&self) -> ResourcePaths<'_> {
+        match self.bundle_settings.linux_systemd_units {
+            Some(ref paths) => ResourcePaths::new(paths.as_slice(), false),
+            None => ResourcePaths::new(&[], false),
+        }
+    }
+
+    /// Returns whether the package's systemd units should be enabled on
+    /// installation (defaults to `true`).
+    pub fn linux_systemd_enable(&self) -> bool {
+        self.bundle_settings.linux_systemd_enable.unwrap_or(true)
+    }
+
+    /// Returns whether the package's systemd units should be started on
+    /// installation and restarted on upgrades (defaults to `true`).
+    pub fn linux_systemd_start(&self) -> bool {
+        self.bundle_settings.linux_systemd_start.unwrap_or(true)
+    }
+
+    /// Returns an iterator over the sysusers.d files for this bundle
+    pub fn linux_sysusers(&self) -> ResourcePaths<'_> {
+        match self.bundle_settings.linux_sysusers {
+            Some(ref paths) => ResourcePaths::new(paths.as_slice(), false),
+            None => ResourcePaths::new(&[], false),
+        }
+    }
+
+    /// Returns an iterator over the tmpfiles.d files for this bundle
+    pub fn linux_tmpfiles(&self) -> ResourcePaths<'_> {
+        match self.bundle_settings.linux_tmpfiles {
+            Some(ref paths) => ResourcePaths::new(paths.as_slice(), false),
+            None => ResourcePaths::new(&[], false),
+        }
+    }
+
     pub fn osx_frameworks(&self) -> &[String] {
         match self.bundle_settings.osx_frameworks {
             Some(ref frameworks) => frameworks.as_slice(),
@@ -547,9 +1143,11 @@ impl Settings {
         self.bundle_settings.osx_minimum_system_version.as_deref()
     }
 
-    pub fn osx_url_schemes(&self) -> &[String] {
-        match self.bundle_settings.osx_url_schemes {
-            Some(ref urlosx_url_schemes) => urlosx_url_schemes.as_slice(),
+    /// Returns the URL schemes (e.g. `myapp` for `myapp://` links) that the
+    /// app handles.
+    pub fn url_schemes(&self) -> &[String] {
+        match self.bundle_settings.url_schemes {
+            Some(ref url_schemes) => url_schemes.as_slice(),
             None => &[],
         }
     }
@@ -578,6 +1176,15 @@ fn bundle_settings_from_table(
     }
 }
 
+/// Checks that `scheme` is a URL scheme as defined by RFC 3986: a letter
+/// followed by letters, digits, `+`, `-` and `.`.
+fn is_valid_url_scheme(scheme: &str) -> bool {
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
 pub struct ResourcePaths<'a> {
     pattern_iter: std::slice::Iter<'a, String>,
     glob_iter: Option<glob::Paths>,
@@ -649,7 +1256,8 @@ impl Iterator for ResourcePaths<'_> {
 
 #[cfg(test)]
 mod tests {
-    use super::{AppCategory, BundleSettings};
+    use super::{AppCategory, BundleSettings, DebScript, is_valid_url_scheme};
+    use std::path::PathBuf;
 
     #[test]
     fn parse_cargo_toml() {
@@ -682,6 +1290,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_linux_files() {
+        let toml_str = "\
+            [[linux_files]]\n\
+            src = \"config/default.toml\"\n\
+            dest = \"/etc/example/config.toml\"\n\
+            mode = 0o640\n\
+            conffile = true\n\
+            \n\
+            [[linux_files]]\n\
+            src = \"data/*.dat\"\n\
+            dest = \"/usr/share/example/\"\n";
+        let bundle: BundleSettings = toml::from_str(toml_str).unwrap();
+        let files = bundle.linux_files.unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].dest, "/etc/example/config.toml");
+        assert_eq!(files[0].mode, Some(0o640));
+        assert!(files[0].conffile);
+        assert_eq!(files[1].src, "data/*.dat");
+        assert_eq!(files[1].mode, None);
+        assert!(!files[1].conffile);
+    }
+
+    #[test]
+    fn parse_deb_scripts() {
+        let toml_str = "\
+            deb_preinst = \"debian/my preinst\"\n\
+            deb_postinst = { inline = \"ldconfig\" }\n\
+            deb_postrm = { file = \"debian/postrm\" }\n";
+        let bundle: BundleSettings = toml::from_str(toml_str).unwrap();
+        assert_eq!(
+            bundle.deb_preinst,
+            Some(DebScript::File(PathBuf::from("debian/my preinst")))
+        );
+        assert_eq!(
+            bundle.deb_postinst,
+            Some(DebScript::Inline("ldconfig".to_string()))
+        );
+        assert_eq!(
+            bundle.deb_postrm,
+            Some(DebScript::File(PathBuf::from("debian/postrm")))
+        );
+        assert!(
+            toml::from_str::<BundleSettings>("deb_prerm = { inline = \"a\", file = \"b\" }\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn parse_url_schemes() {
+        let bundle: BundleSettings = toml::from_str("url_schemes = [\"myapp\"]\n").unwrap();
+        assert_eq!(bundle.url_schemes, Some(vec!["myapp".to_string()]));
+        let bundle: BundleSettings = toml::from_str("osx_url_schemes = [\"myapp\"]\n").unwrap();
+        assert_eq!(bundle.url_schemes, Some(vec!["myapp".to_string()]));
+
+        assert!(is_valid_url_scheme("myapp"));
+        assert!(is_valid_url_scheme("com.example.app+x-1"));
+        assert!(!is_valid_url_scheme(""));
+        assert!(!is_valid_url_scheme("1app"));
+        assert!(!is_valid_url_scheme("myapp://"));
+    }
+
     #[test]
     fn parse_bin_and_example_bundles() {
         let toml_str = "\
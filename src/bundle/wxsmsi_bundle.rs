@@ -1,4 +1,6 @@
-use super::settings::Settings;
+use super::common::{self, windows_prog_id};
+use super::settings::{FileAssociationRole, Settings};
+use anyhow::Context;
 use quick_xml::se::Serializer;
 use serde::Serialize;
 use std::path::{Path, PathBuf};
@@ -113,6 +115,8 @@ fn generate_wxs_file(wxs_path: &Path, settings: &Settings) -> crate::Result<()>
                 source: binary_path.to_string(),
                 key_path: Some("yes".to_string()),
             }),
+            prog_ids: generate_prog_ids(settings, &exe_id),
+            registry_keys: generate_url_scheme_keys(settings, &exe_id),
             ..Component::default()
         };
         installfolder_components.push(comp);
@@ -202,7 +206,7 @@ fn generate_wxs_file(wxs_path: &Path, settings: &Settings) -> crate::Result<()>
 
     let main_icon_id = "main_ico_id";
 
-    let icon_path = get_icon_path(settings);
+    let icon_path = get_icon_path(settings).context("Failed to create the app icon")?;
 
     // ProgramMenuFolder GUID
     let program_menu_folder_guid = uuid::Uuid::new_v5(
@@ -255,6 +259,10 @@ fn generate_wxs_file(wxs_path: &Path, settings: &Settings) -> crate::Result<()>
                 id: "WixUI_InstallDir".to_string(),
             },
             properties: vec![
+                Property {
+                    id: "ARPPRODUCTICON".to_string(),
+                    value: main_icon_id.to_string(),
+                },
                 Property {
                     id: "WIXUI_INSTALLDIR".to_string(),
                     value: "INSTALLFOLDER".to_string(),
@@ -346,6 +354,8 @@ fn generate_wxs_file(wxs_path: &Path, settings: &Settings) -> crate::Result<()>
                                     on: "uninstall".to_string(),
                                 }),
                                 file: None,
+                                prog_ids: vec![],
+                                registry_keys: vec![],
                             }],
                             directories: vec![],
                         }),
@@ -605,6 +615,66 @@ struct Component {
     remove_file: Option<RemoveFile>,
     #[serde(rename = "File", skip_serializing_if = "Option::is_none")]
     file: Option<File>,
+    #[serde(rename = "ProgId", skip_serializing_if = "Vec::is_empty")]
+    prog_ids: Vec<ProgId>,
+    #[serde(rename = "RegistryKey", skip_serializing_if = "Vec::is_empty")]
+    registry_keys: Vec<RegistryKey>,
+}
+
+#[derive(Clone, Serialize)]
+struct ProgId {
+    #[serde(rename = "@Id")]
+    id: String,
+    #[serde(rename = "@Description")]
+    description: String,
+    #[serde(rename = "@Icon")]
+    icon: String,
+    #[serde(rename = "@IconIndex")]
+    icon_index: u32,
+    #[serde(rename = "Extension")]
+    extensions: Vec<Extension>,
+}
+
+#[derive(Clone, Serialize)]
+struct Extension {
+    #[serde(rename = "@Id")]
+    id: String,
+    #[serde(rename = "@ContentType", skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(rename = "Verb", skip_serializing_if = "Option::is_none")]
+    verb: Option<Verb>,
+}
+
+#[derive(Clone, Serialize)]
+struct Verb {
+    #[serde(rename = "@Id")]
+    id: String,
+    #[serde(rename = "@TargetFile")]
+    target_file: String,
+    #[serde(rename = "@Argument")]
+    argument: String,
+}
+
+#[derive(Clone, Serialize)]
+struct RegistryKey {
+    #[serde(rename = "@Root", skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+    #[serde(rename = "@Key")]
+    key: String,
+    #[serde(rename = "RegistryValue")]
+    values: Vec<RegistryKeyValue>,
+    #[serde(rename = "RegistryKey", skip_serializing_if = "Vec::is_empty")]
+    subkeys: Vec<RegistryKey>,
+}
+
+#[derive(Clone, Serialize)]
+struct RegistryKeyValue {
+    #[serde(rename = "@Name", skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "@Type")]
+    value_type: String,
+    #[serde(rename = "@Value")]
+    value: String,
 }
 
 #[derive(Clone, Serialize)]
@@ -762,6 +832,67 @@ fn sanitize_version_for_wix(version: &str) -> String {
     format!("{}.{}.{}.{}", major, minor, build, revision)
 }
 
+/// Generates a ProgId, with its file extensions, for each file association,
+/// opening the files with the executable whose File ID is `exe_id`.
+fn generate_prog_ids(settings: &Settings, exe_id: &str) -> Vec<ProgId> {
+    settings
+        .file_associations()
+        .iter()
+        .enumerate()
+        .map(|(index, association)| ProgId {
+            id: windows_prog_id(settings.bundle_name(), index, association),
+            description: association.description(),
+            icon: exe_id.to_string(),
+            icon_index: 0,
+            extensions: association
+                .extensions()
+                .map(|extension| Extension {
+                    id: extension.to_string(),
+                    content_type: association.mime_type.clone(),
+                    verb: (association.role != FileAssociationRole::None).then(|| Verb {
+                        id: "open".to_string(),
+                        target_file: exe_id.to_string(),
+                        argument: "\"%1\"".to_string(),
+                    }),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Generates the registry keys that register the executable whose File ID is
+/// `exe_id` as the handler of each URL scheme.
+fn generate_url_scheme_keys(settings: &Settings, exe_id: &str) -> Vec<RegistryKey> {
+    let string_value = |name: Option<&str>, value: String| RegistryKeyValue {
+        name: name.map(str::to_string),
+        value_type: "string".to_string(),
+        value,
+    };
+    let subkey = |key: &str, value: String| RegistryKey {
+        root: None,
+        key: key.to_string(),
+        values: vec![string_value(None, value)],
+        subkeys: vec![],
+    };
+    settings
+        .url_schemes()
+        .iter()
+        .map(|scheme| RegistryKey {
+            root: Some("HKMU".to_string()),
+            key: format!("Software\\Classes\\{scheme}"),
+            values: vec![
+                string_value(None, format!("URL:{} Protocol", settings.bundle_name())),
+                // An empty `URL Protocol` value marks the key as a URL scheme.
+                string_value(Some("URL Protocol"), String::new()),
+            ],
+            subkeys: vec![
+                subkey("DefaultIcon", format!("[#{exe_id}],0")),
+                subkey("shell\\open\\command", format!("\"[#{exe_id}]\" \"%1\"")),
+            ],
+        })
+        .collect()
+}
+
 fn sanitize_identifier(input: &str, replacement: char, to_lowercase: bool) -> String {
     let result: String = input
         .chars()
@@ -812,68 +943,19 @@ fn find_default_license() -> Option<String> {
     .find_map(|&filename| std::fs::read_to_string(filename).ok())
 }
 
-fn get_icon_path(settings: &Settings) -> PathBuf {
-    let package_dir = settings
-        .manifest_path()
-        .parent()
-        .unwrap_or_else(|| Path::new("."));
-
-    // Try to get the first icon file from BundleSettings.icon
-    if let Some(icon_result) = settings.icon_files().next()
-        && let Ok(icon_path) = icon_result
-    {
-        let full_path = package_dir.join(icon_path);
-
-        // Check if the icon file exists
-        if full_path.exists() {
-            let extension = full_path
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-
-            // WiX supports ICO, EXE, and DLL formats for icons
-            if matches!(extension.as_str(), "ico" | "exe" | "dll") {
-                return full_path;
-            }
-
-            let out_dir = settings.project_out_directory();
-
-            let file_stem = full_path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("icon");
-
-            let ico_path = out_dir.join(format!("{file_stem}-generated.ico"));
-
-            if extension == "svg" {
-                // TODO: convert svg to appropriate format?
-            } else if convert_to_ico(&full_path, &ico_path).is_ok() {
-                return ico_path;
-            }
-        }
+/// Returns the path of the app's icon, built from its icon files, or of the
+/// executable (whose own icon WiX can use) if there are none.
+fn get_icon_path(settings: &Settings) -> crate::Result<PathBuf> {
+    let ico_path = settings
+        .project_out_directory()
+        .join(Path::new(settings.binary_name()).with_extension("ico"));
+    let mut icon = Vec::new();
+    if common::write_app_ico(settings, &mut icon)? {
+        std::fs::write(&ico_path, icon)?;
+        Ok(ico_path)
+    } else {
+        Ok(settings.binary_path().to_path_buf())
     }
-
-    // Fallback: use the executable file itself as the icon source
-    // EXE files can be used directly as icon sources in WiX
-    settings.binary_path().to_path_buf()
-}
-
-// Try to convert other formats to ICO
-fn convert_to_ico(input_path: &Path, ico_path: &Path) -> crate::Result<()> {
-    // Ensure output directory exists
-    std::fs::create_dir_all(ico_path.parent().ok_or(anyhow::anyhow!("Parent dir"))?)?;
-
-    // Load the image
-    let img = image::open(input_path)?;
-
-    // Convert and save as ICO
-    // ICO format typically uses 256x256, 128x128, 64x64, 32x32, 16x16 sizes
-    // We'll resize to 256x256 as the primary size
-    let resized = img.resize(256, 256, image::imageops::FilterType::Lanczos3);
-    resized.save_with_format(ico_path, image::ImageFormat::Ico)?;
-
-    Ok(())
 }
 
 fn build_directory_structure(directories: &mut Vec<Directory>, path: &Path, component: Component) {
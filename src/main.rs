@@ -1,10 +1,11 @@
 mod bundle;
 
-use crate::bundle::{BuildArtifact, PackageType, Settings, bundle_project};
+use crate::bundle::{BuildArtifact, PackageType, Settings, Severity, bundle_project};
 use anyhow::Result;
 use clap::builder::{PossibleValuesParser, TypedValueParser};
 use std::env;
 use std::ffi::OsString;
+use std::path::PathBuf;
 use std::process;
 
 #[macro_export]
@@ -72,6 +73,43 @@ pub struct Cli {
     /// The name of the package to bundle. If not specified, the root package will be used.
     #[arg(short, long, value_name = "SPEC")]
     pub package: Option<String>,
+
+    /// Use this AppImage runtime instead of downloading one
+    #[arg(long, value_name = "PATH")]
+    pub appimage_runtime: Option<PathBuf>,
+
+    /// Sign the AppImage with the OpenPGP secret key in this file
+    #[arg(long, value_name = "FILE")]
+    pub appimage_sign_key: Option<PathBuf>,
+
+    /// Check the embedded signature of this AppImage instead of bundling
+    #[arg(long, value_name = "FILE")]
+    pub verify_appimage: Option<PathBuf>,
+
+    /// Fail instead of accessing the network (also enabled by CARGO_NET_OFFLINE=true)
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Check the bundled deb packages for Debian policy violations, failing if there are errors
+    #[arg(long)]
+    pub lint: bool,
+
+    /// Add the bundled deb packages to the APT repository in this directory
+    #[arg(long, value_name = "DIR")]
+    pub apt_repo: Option<PathBuf>,
+
+    /// The suite of the APT repository to add packages to
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "stable",
+        requires = "apt_repo"
+    )]
+    pub apt_suite: String,
+
+    /// Sign the APT repository's Release file with the OpenPGP secret key in this file
+    #[arg(long, value_name = "FILE", requires = "apt_repo")]
+    pub apt_sign_key: Option<PathBuf>,
 }
 
 /// Runs `cargo build` to make sure the binary file is up-to-date.
@@ -114,6 +152,9 @@ fn build_project_if_unbuilt(settings: &Settings) -> crate::Result<()> {
     if settings.no_default_features() {
         cargo.arg("--no-default-features");
     }
+    if settings.offline() {
+        cargo.arg("--offline");
+    }
     let status = cargo.status()?;
     if !status.success() {
         anyhow::bail!(
@@ -124,6 +165,32 @@ fn build_project_if_unbuilt(settings: &Settings) -> crate::Result<()> {
     Ok(())
 }
 
+/// Checks the given deb packages, printing any problems found.  Fails if any
+/// of them are errors.
+fn lint_packages(debs: &[PathBuf]) -> crate::Result<()> {
+    if debs.is_empty() {
+        anyhow::bail!("--lint requires a deb package to be bundled");
+    }
+    let mut error_count = 0;
+    for deb in debs {
+        let file_name = deb.file_name().unwrap_or_default().to_string_lossy();
+        for finding in bundle::lint_deb(deb)? {
+            if finding.severity == Severity::Error {
+                error_count += 1;
+            }
+            bundle::print_lint_finding(
+                &finding.severity.to_string(),
+                finding.tag,
+                &format!("{file_name}: {}", finding.message),
+            )?;
+        }
+    }
+    if error_count > 0 {
+        anyhow::bail!("Found {error_count} policy error(s) in the bundled packages");
+    }
+    Ok(())
+}
+
 fn run() -> crate::Result<()> {
     let mut args: Vec<String> = std::env::args().collect();
     if args.len() > 1 && args[1] == "bundle" {
@@ -131,6 +198,12 @@ fn run() -> crate::Result<()> {
     }
     let cli = <Cli as clap::Parser>::parse_from(args); // <Cli as clap::Parser>::parse();
 
+    if let Some(appimage) = &cli.verify_appimage {
+        let signer = bundle::verify_appimage(appimage)?;
+        bundle::print_verified(&appimage.display().to_string(), &signer)?;
+        return Ok(());
+    }
+
     {
         let output_paths = env::current_dir()
             .map_err(From::from)
@@ -140,6 +213,22 @@ fn run() -> crate::Result<()> {
                 Ok(s)
             })
             .and_then(bundle_project)?;
+        let debs: Vec<_> = output_paths
+            .iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "deb"))
+            .cloned()
+            .collect();
+        if cli.lint {
+            lint_packages(&debs)?;
+        }
+        if let Some(repo_dir) = &cli.apt_repo {
+            bundle::update_apt_repository(
+                repo_dir,
+                &cli.apt_suite,
+                cli.apt_sign_key.as_deref(),
+                &debs,
+            )?;
+        }
         bundle::print_finished(&output_paths)?;
     }
     Ok(())